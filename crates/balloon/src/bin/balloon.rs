@@ -1,25 +1,211 @@
 use std::io::{Read, Write};
+use std::os::fd::AsFd;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
 
-const CHUNK_SIZE: usize = 4096 * 4;
-const BUFFER_SIZE: usize = 1024^2 * 5;
+use balloon::{
+    parse_size, resize_pipe,
+    proxy::{self, ProxyConfig},
+    stats::Stats,
+    throttle::Throttle,
+};
 
-fn main() -> Result<(), std::io::Error> {
-    let mut chunk = [0u8; CHUNK_SIZE];
-    let mut buffer = dbg!(Vec::with_capacity(BUFFER_SIZE));
-    let mut reader = std::io::stdin();
-    let mut writer = std::io::stdout();
-
-    while let Ok(n) = reader.read(&mut chunk) {
-        if n == 0 {
-            break;
-        }  
-        buffer.extend(&chunk[..n]);
+#[derive(Debug, StructOpt)]
+#[structopt(name = "balloon", about = "Pipe throughput diagnostics")]
+enum Cli {
+    /// Write to stdout at a fixed rate
+    Produce {
+        #[structopt(flatten)]
+        opts: Common,
+
+        /// Total bytes to write, runs until stdout closes if unset
+        #[structopt(long, parse(try_from_str = parse_size))]
+        bytes: Option<usize>,
+    },
+
+    /// Read from stdin at a fixed rate
+    Consume {
+        #[structopt(flatten)]
+        opts: Common,
+
+        /// Wait until the pipe is this full (0.0 - 1.0) before reading,
+        /// and report how long it took. Mirrors mockingbird's old
+        /// MKBIRD_PIPE_THRESHOLD behaviour.
+        #[structopt(long)]
+        threshold: Option<f32>,
+    },
+
+    /// Buffer stdin into stdout between watermarks
+    Proxy {
+        /// Buffer size (accepts k/M/G suffixes)
+        #[structopt(long, default_value = "16M", parse(try_from_str = parse_size))]
+        size: usize,
+
+        /// Start writing once the buffer is this full (0.0 - 1.0)
+        #[structopt(long, default_value = "0.5")]
+        high: f32,
+
+        /// Resume reading once a full buffer drains below this (0.0 - 1.0),
+        /// no higher than --high
+        #[structopt(long, default_value = "0.25")]
+        low: f32,
+
+        /// Bytes per read/write
+        #[structopt(long, default_value = "64k", parse(try_from_str = parse_size))]
+        chunk: usize,
+
+        /// Seconds between progress lines on stderr, 0 to disable
+        #[structopt(long, default_value = "1")]
+        progress: f64,
+
+        /// Resize stdin and stdout pipes ("max" for pipe-max-size)
+        #[structopt(long = "pipe-size", parse(try_from_str = parse_size))]
+        pipe_size: Option<usize>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+struct Common {
+    /// Bytes per second, unlimited if unset (accepts k/M/G suffixes)
+    #[structopt(long, parse(try_from_str = parse_size))]
+    rate: Option<usize>,
+
+    /// Bytes per read/write
+    #[structopt(long, default_value = "16k", parse(try_from_str = parse_size))]
+    chunk: usize,
+
+    /// Resize the pipe before starting ("max" for pipe-max-size)
+    #[structopt(long = "pipe-size", parse(try_from_str = parse_size))]
+    pipe_size: Option<usize>,
+
+    /// Calls slower than this many milliseconds count as stalls
+    #[structopt(long = "stall-ms", default_value = "1")]
+    stall_ms: u64,
+}
+
+fn produce(opts: Common, bytes: Option<usize>) -> std::io::Result<Stats> {
+    let stdout = std::io::stdout();
+    resize_pipe("stdout", &stdout, opts.pipe_size)
+        .map_err(std::io::Error::other)?;
+
+    let mut writer = stdout.lock();
+    let chunk = vec![0u8; opts.chunk.max(1)];
+    let mut stats = Stats::new(Duration::from_millis(opts.stall_ms));
+    let mut throttle = Throttle::new(opts.rate);
+    let mut remaining = bytes.unwrap_or(usize::MAX);
+
+    while remaining > 0 {
+        let n = chunk.len().min(remaining);
+        let now = Instant::now();
+        match writer.write(&chunk[..n]) {
+            Ok(0) => break,
+            Ok(n) => {
+                stats.record(n, now.elapsed());
+                remaining -= n;
+                throttle.consume(n);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => break,
+            Err(e) => return Err(e),
+        }
     }
 
-    for it in buffer.chunks(CHUNK_SIZE) {
-        chunk.copy_from_slice(it);
-        writer.write_all(&chunk)?;
+    Ok(stats)
+}
+
+/// Give up waiting for `--threshold` after this long.
+const THRESHOLD_DEADLINE: Duration = Duration::from_secs(60);
+
+/// How long stdin took to fill to `threshold`, or `None` if the producer
+/// hung up or [`THRESHOLD_DEADLINE`] passed first.
+fn wait_for_threshold(threshold: f32, capacity: usize) -> Result<Option<Duration>, cutils::PipeError> {
+    let stdin = std::io::stdin();
+    let pipe = cutils::Pipe::new(&stdin);
+    let target = (capacity as f32 * threshold.clamp(0.0, 1.0)) as usize;
+    let now = Instant::now();
+
+    loop {
+        if pipe.readable()? >= target {
+            return Ok(Some(now.elapsed()));
+        }
+        if pipe.hung_up()? || now.elapsed() >= THRESHOLD_DEADLINE {
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_micros(200));
+    }
+}
+
+fn consume(opts: Common, threshold: Option<f32>) -> std::io::Result<Stats> {
+    let stdin = std::io::stdin();
+    let capacity = resize_pipe("stdin", &stdin, opts.pipe_size)
+        .map_err(std::io::Error::other)?;
+
+    if let (Some(threshold), Some(capacity)) = (threshold, capacity) {
+        match wait_for_threshold(threshold, capacity).map_err(std::io::Error::other)? {
+            Some(waited) => eprintln!("[balloon] stdin reached {:.0}% after {:.3}s", threshold * 100.0, waited.as_secs_f64()),
+            None => eprintln!("[balloon] stdin never reached {:.0}%", threshold * 100.0),
+        }
+    }
+
+    let mut reader = stdin.lock();
+    let mut chunk = vec![0u8; opts.chunk.max(1)];
+    let mut stats = Stats::new(Duration::from_millis(opts.stall_ms));
+    let mut throttle = Throttle::new(opts.rate);
+
+    loop {
+        let now = Instant::now();
+        match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => {
+                stats.record(n, now.elapsed());
+                throttle.consume(n);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
     }
 
-    return Ok(())
-}
\ No newline at end of file
+    Ok(stats)
+}
+
+fn proxy(mut cfg: ProxyConfig, pipe_size: Option<usize>) -> std::io::Result<()> {
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+
+    for (name, fd) in [("stdin", stdin.as_fd()), ("stdout", stdout.as_fd())] {
+        let size = resize_pipe(name, &fd, pipe_size)
+            .map_err(std::io::Error::other)?;
+        // a chunk larger than the pipe can never be written atomically
+        if let Some(size) = size {
+            cfg.chunk = cfg.chunk.min(size);
+        }
+    }
+
+    // The reader runs on its own thread, give it its own handle
+    let reader = std::fs::File::from(stdin.as_fd().try_clone_to_owned()?);
+    let stats = proxy::run(reader, stdout.lock(), cfg)?;
+
+    eprintln!(
+        "[balloon] proxy: {} bytes, {} underruns, {} overruns",
+        stats.bytes_out, stats.underruns, stats.overruns
+    );
+    Ok(())
+}
+
+fn main() -> Result<(), std::io::Error> {
+    let (name, stats) = match Cli::from_args() {
+        Cli::Produce { opts, bytes } => ("produce", produce(opts, bytes)?),
+        Cli::Consume { opts, threshold } => ("consume", consume(opts, threshold)?),
+        Cli::Proxy { size, high, low, chunk, progress, pipe_size } => {
+            let mut cfg = ProxyConfig::new(size, high, low, chunk)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+            if progress > 0.0 {
+                cfg.progress = Some(Duration::from_secs_f64(progress));
+            }
+            return proxy(cfg, pipe_size);
+        }
+    };
+
+    let mut stats = stats;
+    eprintln!("{}", stats.report(name));
+    Ok(())
+}
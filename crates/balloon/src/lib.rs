@@ -0,0 +1,78 @@
+//! Pipe diagnostics used to tune mockingbird's pipelines.
+//!
+//! `balloon produce` writes to stdout and `balloon consume` reads from
+//! stdin, each at a configurable rate, and both report throughput, stall
+//! time and latency percentiles on stderr when they finish.
+//!
+//! `balloon proxy` sits between two pipeline stages and buffers the
+//! stream in memory between a high and a low watermark.
+//!
+//! ```sh
+//! balloon produce --rate 384000 --bytes 38400000 | balloon consume --pipe-size max --threshold 0.8
+//! deemix-stream "$URL" | balloon proxy --size 32M --high 0.25 | ffmpeg -i - ...
+//! ```
+
+pub mod stats;
+pub mod throttle;
+pub mod proxy;
+
+#[cfg(test)]
+mod testsuite;
+
+use std::os::fd::AsFd;
+use cutils::{Pipe, PipeError, std_max_pipe_size};
+
+/// Parse a byte count such as `4096`, `64k`, `1M` or `max`.
+/// `max` resolves to `/proc/sys/fs/pipe-max-size`.
+pub fn parse_size(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    if s.eq_ignore_ascii_case("max") {
+        return std_max_pipe_size()
+            .map(|x| x as usize)
+            .map_err(|e| e.to_string());
+    }
+
+    let (digits, unit) = match s.char_indices().find(|(_, c)| !c.is_ascii_digit() && *c != '.') {
+        Some((i, _)) => s.split_at(i),
+        None => (s, ""),
+    };
+
+    let n = digits.parse::<f64>().map_err(|e| format!("{}: {}", s, e))?;
+    let scale = match unit.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1024,
+        "m" | "mb" | "mib" => 1024 * 1024,
+        "g" | "gb" | "gib" => 1024 * 1024 * 1024,
+        _ => return Err(format!("unknown unit: {}", unit)),
+    };
+
+    Ok((n * scale as f64) as usize)
+}
+
+/// Resize `fd` if it is a pipe, and report the outcome on stderr.
+pub fn resize_pipe<F: AsFd>(name: &str, fd: &F, size: Option<usize>) -> Result<Option<usize>, PipeError> {
+    let pipe = Pipe::new(fd);
+
+    let capacity = match pipe.capacity() {
+        Ok(capacity) => capacity,
+        Err(PipeError::NotAPipe) => {
+            eprintln!("[balloon] {} is not a pipe", name);
+            return Ok(None);
+        }
+        Err(e) => return Err(e),
+    };
+
+    let capacity = match size {
+        Some(size) => {
+            let new = pipe.set_capacity(size)?;
+            eprintln!("[balloon] {} pipe: {} -> {} bytes", name, capacity, new);
+            new
+        }
+        None => {
+            eprintln!("[balloon] {} pipe: {} bytes", name, capacity);
+            capacity
+        }
+    };
+
+    Ok(Some(capacity))
+}
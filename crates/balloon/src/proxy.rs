@@ -0,0 +1,235 @@
+//! Watermark buffering between two pipeline stages, like `mbuffer`.
+//!
+//! Input is read into a fixed size in-memory buffer by a background
+//! thread. Output only starts once the buffer is filled to the high
+//! watermark (or input ends), and starts over whenever the buffer runs
+//! empty. When the buffer is full, reading pauses until it has drained
+//! below the low watermark.
+
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone)]
+pub struct ProxyConfig {
+    /// Buffer size in bytes.
+    pub size: usize,
+    /// Bytes buffered before output starts.
+    pub high: usize,
+    /// Reading resumes once a full buffer drains below this many bytes.
+    pub low: usize,
+    /// Bytes per read/write.
+    pub chunk: usize,
+    /// Interval between progress lines on stderr.
+    pub progress: Option<Duration>,
+}
+
+impl ProxyConfig {
+    /// `high` and `low` are fractions of `size`, `low` no higher than `high`.
+    pub fn new(size: usize, high: f32, low: f32, chunk: usize) -> Result<Self, String> {
+        for (name, frac) in [("high", high), ("low", low)] {
+            if !(0.0..=1.0).contains(&frac) {
+                return Err(format!("{} watermark {} is outside 0.0 - 1.0", name, frac));
+            }
+        }
+        if low > high {
+            return Err(format!("low watermark {} is above high watermark {}", low, high));
+        }
+
+        let size = size.max(chunk).max(1);
+        let at = |frac: f32| (size as f64 * frac as f64) as usize;
+
+        Ok(Self {
+            size,
+            high: at(high),
+            low: at(low),
+            chunk: chunk.max(1),
+            progress: None,
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ProxyStats {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    /// Times output waited for the high watermark after running dry.
+    pub underruns: u64,
+    /// Times input paused on a full buffer.
+    pub overruns: u64,
+}
+
+struct State {
+    buf: VecDeque<u8>,
+    eof: bool,
+    error: Option<std::io::Error>,
+    closed: bool,
+    stats: ProxyStats,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    readable: Condvar,
+    writable: Condvar,
+}
+
+fn fill<R: Read>(mut reader: R, shared: Arc<Shared>, cfg: ProxyConfig) {
+    let mut chunk = vec![0u8; cfg.chunk];
+
+    loop {
+        {
+            let mut state = shared.state.lock().unwrap();
+            if cfg.size - state.buf.len() < cfg.chunk {
+                state.stats.overruns += 1;
+                state = shared.writable
+                    .wait_while(state, |s| !s.closed && s.buf.len() > cfg.low)
+                    .unwrap();
+            }
+            if state.closed { return }
+        }
+
+        let result = reader.read(&mut chunk);
+        let mut state = shared.state.lock().unwrap();
+        match result {
+            Ok(0) => state.eof = true,
+            Ok(n) => {
+                state.buf.extend(&chunk[..n]);
+                state.stats.bytes_in += n as u64;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                state.error = Some(e);
+                state.eof = true;
+            }
+        }
+
+        let done = state.eof;
+        drop(state);
+        shared.readable.notify_all();
+        if done { return }
+    }
+}
+
+fn report(state: &State, cfg: &ProxyConfig, started: Instant) {
+    let elapsed = started.elapsed().as_secs_f64().max(f64::EPSILON);
+    eprintln!(
+        "[balloon] in {} out {} | {:.1}% full | {:.2} MiB/s out | {} underruns {} overruns",
+        state.stats.bytes_in,
+        state.stats.bytes_out,
+        state.buf.len() as f64 / cfg.size as f64 * 100.0,
+        state.stats.bytes_out as f64 / elapsed / (1024.0 * 1024.0),
+        state.stats.underruns,
+        state.stats.overruns,
+    );
+}
+
+/// Copy `reader` into `writer` through the buffer until `reader` ends.
+///
+/// A read error is returned once everything read before it has been
+/// written. A write error stops the proxy straight away.
+pub fn run<R, W>(reader: R, mut writer: W, cfg: ProxyConfig) -> std::io::Result<ProxyStats>
+where
+    R: Read + Send + 'static,
+    W: Write,
+{
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            buf: VecDeque::with_capacity(cfg.size),
+            eof: false,
+            error: None,
+            closed: false,
+            stats: ProxyStats::default(),
+        }),
+        readable: Condvar::new(),
+        writable: Condvar::new(),
+    });
+
+    let filler = {
+        let shared = shared.clone();
+        let cfg = cfg.clone();
+        std::thread::Builder::new()
+            .name("balloon fill".to_string())
+            .spawn(move || fill(reader, shared, cfg))?
+    };
+
+    let started = Instant::now();
+    let mut last_report = Instant::now();
+    let mut chunk = vec![0u8; cfg.chunk];
+    let mut primed = false;
+
+    let result = loop {
+        let n = {
+            let mut state = shared.state.lock().unwrap();
+
+            if !primed {
+                if state.buf.is_empty() && !state.eof && state.stats.bytes_out > 0 {
+                    state.stats.underruns += 1;
+                }
+
+                let wait = cfg.progress.unwrap_or(Duration::from_secs(3600));
+                while state.buf.len() < cfg.high.max(1) && !state.eof {
+                    state = shared.readable.wait_timeout(state, wait).unwrap().0;
+                    if cfg.progress.is_some() && last_report.elapsed() >= wait {
+                        report(&state, &cfg, started);
+                        last_report = Instant::now();
+                    }
+                }
+                primed = true;
+            }
+
+            if state.buf.is_empty() {
+                if state.eof {
+                    break match state.error.take() {
+                        Some(e) => Err(e),
+                        None => Ok(()),
+                    };
+                }
+                primed = false;
+                continue;
+            }
+
+            let n = chunk.len().min(state.buf.len());
+            for (dst, src) in chunk.iter_mut().zip(state.buf.drain(..n)) {
+                *dst = src;
+            }
+            n
+        };
+        shared.writable.notify_all();
+
+        if let Err(e) = writer.write_all(&chunk[..n]) {
+            break Err(e);
+        }
+
+        let mut state = shared.state.lock().unwrap();
+        state.stats.bytes_out += n as u64;
+
+        if let Some(interval) = cfg.progress {
+            if last_report.elapsed() >= interval {
+                report(&state, &cfg, started);
+                last_report = Instant::now();
+            }
+        }
+    };
+
+    let result = result.and_then(|_| writer.flush());
+
+    let stats = {
+        let mut state = shared.state.lock().unwrap();
+        state.closed = true;
+        if cfg.progress.is_some() {
+            report(&state, &cfg, started);
+        }
+        state.stats.clone()
+    };
+    shared.writable.notify_all();
+
+    // The filler may be blocked reading, only wait for it on a clean EOF.
+    if result.is_ok() {
+        let _ = filler.join();
+    }
+
+    result.map(|_| stats)
+}
@@ -0,0 +1,79 @@
+use std::time::{Duration, Instant};
+
+/// Per-call latency samples and running totals for one end of a pipe.
+pub struct Stats {
+    started: Instant,
+    samples: Vec<Duration>,
+    bytes: u64,
+    calls: u64,
+    stalled: Duration,
+    stall_threshold: Duration,
+}
+
+impl Stats {
+    pub fn new(stall_threshold: Duration) -> Self {
+        Self {
+            started: Instant::now(),
+            samples: Vec::new(),
+            bytes: 0,
+            calls: 0,
+            stalled: Duration::ZERO,
+            stall_threshold,
+        }
+    }
+
+    /// Record a single read/write which moved `bytes` in `latency`.
+    pub fn record(&mut self, bytes: usize, latency: Duration) {
+        self.bytes += bytes as u64;
+        self.calls += 1;
+        self.samples.push(latency);
+
+        if latency >= self.stall_threshold {
+            self.stalled += latency;
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// `p` in 0.0..=1.0
+    pub fn percentile(&mut self, p: f64) -> Duration {
+        if self.samples.is_empty() {
+            return Duration::ZERO;
+        }
+
+        self.samples.sort_unstable();
+        let rank = (p.clamp(0.0, 1.0) * (self.samples.len() - 1) as f64).round() as usize;
+        self.samples[rank]
+    }
+
+    pub fn report(&mut self, name: &str) -> String {
+        let (p50, p90, p99, max) = (
+            self.percentile(0.50),
+            self.percentile(0.90),
+            self.percentile(0.99),
+            self.percentile(1.0),
+        );
+        let elapsed = self.elapsed().as_secs_f64();
+        let throughput = if elapsed > 0.0 { self.bytes as f64 / elapsed } else { 0.0 };
+
+        format!(
+            "[balloon] {name}: {} bytes in {:.3}s ({:.2} MiB/s), {} calls\n\
+             [balloon] {name}: stalled {:.3}s ({:.1}%) in calls >= {:?}\n\
+             [balloon] {name}: latency p50 {:?} p90 {:?} p99 {:?} max {:?}",
+            self.bytes,
+            elapsed,
+            throughput / (1024.0 * 1024.0),
+            self.calls,
+            self.stalled.as_secs_f64(),
+            if elapsed > 0.0 { self.stalled.as_secs_f64() / elapsed * 100.0 } else { 0.0 },
+            self.stall_threshold,
+            p50, p90, p99, max,
+        )
+    }
+}
@@ -0,0 +1,88 @@
+use crate::proxy::{run, ProxyConfig};
+use std::io::{Read, Write};
+
+/// Hands out `data` a few bytes at a time, then fails if `error` is set.
+struct Trickle {
+    data: Vec<u8>,
+    pos: usize,
+    error: bool,
+}
+
+impl Read for Trickle {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos == self.data.len() {
+            return match self.error {
+                true => Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset")),
+                false => Ok(0),
+            };
+        }
+        let n = buf.len().min(7).min(self.data.len() - self.pos);
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+struct Broken;
+
+impl Write for Broken {
+    fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "closed"))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn data(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+#[test]
+fn proxy_copies_everything() {
+    let input = data(100_000);
+    let mut out = Vec::new();
+    let reader = Trickle { data: input.clone(), pos: 0, error: false };
+
+    let stats = run(reader, &mut out, ProxyConfig::new(4096, 0.5, 0.25, 512).unwrap()).unwrap();
+
+    assert_eq!(out, input);
+    assert_eq!(stats.bytes_in, input.len() as u64);
+    assert_eq!(stats.bytes_out, input.len() as u64);
+}
+
+#[test]
+fn proxy_flushes_short_input_below_high_watermark() {
+    let input = data(100);
+    let mut out = Vec::new();
+    let reader = Trickle { data: input.clone(), pos: 0, error: false };
+
+    run(reader, &mut out, ProxyConfig::new(4096, 0.9, 0.1, 512).unwrap()).unwrap();
+    assert_eq!(out, input);
+}
+
+#[test]
+fn proxy_propagates_read_errors_after_draining() {
+    let input = data(1000);
+    let mut out = Vec::new();
+    let reader = Trickle { data: input.clone(), pos: 0, error: true };
+
+    let err = run(reader, &mut out, ProxyConfig::new(4096, 0.5, 0.1, 512).unwrap()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::ConnectionReset);
+    assert_eq!(out, input);
+}
+
+#[test]
+fn proxy_propagates_write_errors() {
+    let reader = Trickle { data: data(10_000), pos: 0, error: false };
+    let err = run(reader, Broken, ProxyConfig::new(4096, 0.5, 0.1, 512).unwrap()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+}
+
+#[test]
+fn proxy_rejects_low_above_high() {
+    assert!(ProxyConfig::new(4096, 0.5, 0.25, 512).is_ok());
+    assert!(ProxyConfig::new(4096, 0.5, 0.9, 512).is_err());
+    assert!(ProxyConfig::new(4096, 1.5, 0.25, 512).is_err());
+}
@@ -0,0 +1,32 @@
+use std::time::{Duration, Instant};
+
+/// Keeps a stream at or below `rate` bytes per second.
+/// A rate of `None` never sleeps.
+pub struct Throttle {
+    rate: Option<usize>,
+    started: Instant,
+    bytes: u64,
+}
+
+impl Throttle {
+    pub fn new(rate: Option<usize>) -> Self {
+        Self {
+            rate: rate.filter(|r| *r > 0),
+            started: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    /// Account for `bytes` and sleep until the stream is back on schedule.
+    pub fn consume(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+
+        if let Some(rate) = self.rate {
+            let due = Duration::from_secs_f64(self.bytes as f64 / rate as f64);
+            let elapsed = self.started.elapsed();
+            if due > elapsed {
+                std::thread::sleep(due - elapsed);
+            }
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! The TOML file named by `CONFIG_FILE` (or `--config`).
+//!
+//! ```toml
+//! token_file = "/run/secrets/discord-token"
+//! owners = [123456789012345678]
+//! prefix = "."
+//! intents = ["non_privileged", "message_content"]
+//!
+//! [paths]
+//! settings = "/var/lib/coggiebot/settings.json"
+//! cache = "/var/cache/coggiebot"
+//!
+//! [mockingbird.timing]
+//! abandoned_timeout = 720
+//! preload_offset = 20
+//!
+//! [mockingbird.sources]
+//! ytdl = ["youtube.com", "youtu.be", "soundcloud.com"]
+//! ```
+//!
+//! Every key is optional. Command line flags and environment variables win
+//! over the file: mockingbird's settings are exported to the environment
+//! variables it reads, unless they are already set.
+
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use serenity::prelude::GatewayIntents;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub token: Option<String>,
+    /// File holding the token, for secret managers.
+    pub token_file: Option<PathBuf>,
+    pub owners: Vec<u64>,
+    /// Prefix for guilds that haven't chosen one, and DMs.
+    pub prefix: Option<String>,
+    pub intents: Option<Vec<String>>,
+    pub paths: Paths,
+    pub mockingbird: Mockingbird,
+    pub prerelease: Prerelease,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Paths {
+    pub settings: Option<PathBuf>,
+    pub cache: Option<PathBuf>,
+    pub secret_store: Option<PathBuf>,
+    pub secret_key_file: Option<PathBuf>,
+    /// Where queues are saved across a restart.
+    pub queues: Option<PathBuf>,
+}
+
+/// The second bot `prerelease` runs.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Prerelease {
+    pub token: Option<String>,
+    /// Where it is built and run.
+    pub dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Mockingbird {
+    pub timing: Timing,
+    pub pipes: Pipes,
+    pub limits: Limits,
+    pub sources: Sources,
+}
+
+/// In seconds.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timing {
+    pub abandoned_timeout: Option<u64>,
+    pub preload_offset: Option<u64>,
+    pub child_timeout: Option<u64>,
+    pub child_max_secs: Option<u64>,
+    pub arl_check_interval: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Pipes {
+    pub prebuffer_secs: Option<f64>,
+    pub prebuffer_timeout: Option<f64>,
+    pub buffer_secs: Option<f64>,
+    /// `balloon proxy` buffer size, e.g. `32M`.
+    pub balloon: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_children: Option<usize>,
+    pub max_deemix: Option<usize>,
+    pub max_ytdl: Option<usize>,
+    pub max_http: Option<usize>,
+    pub max_metadata: Option<usize>,
+}
+
+/// Which hosts each player handles, and how.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sources {
+    pub deemix: Option<Vec<String>>,
+    pub ytdl: Option<Vec<String>>,
+    pub http: Option<Vec<String>>,
+    pub deemix_quality: Option<String>,
+    pub fallback_tolerance: Option<f64>,
+    pub deezer_url: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    IOError(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(Vec<String>),
+    NoToken,
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfigError::IOError(path, e) => write!(f, "couldn't read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "invalid config {}: {}", path.display(), e),
+            ConfigError::Invalid(problems) => write!(f, "invalid config:\n  {}", problems.join("\n  ")),
+            ConfigError::NoToken => write!(f, "no token: pass --token, set DISCORD_TOKEN, or set token or token_file in the config"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Gateway intents by the names used in the config.
+fn intent(name: &str) -> Option<GatewayIntents> {
+    Some(match name.trim().to_ascii_lowercase().as_str() {
+        "all" => GatewayIntents::all(),
+        "non_privileged" => GatewayIntents::non_privileged(),
+        "guilds" => GatewayIntents::GUILDS,
+        "guild_members" => GatewayIntents::GUILD_MEMBERS,
+        "guild_presences" => GatewayIntents::GUILD_PRESENCES,
+        "guild_voice_states" => GatewayIntents::GUILD_VOICE_STATES,
+        "guild_messages" => GatewayIntents::GUILD_MESSAGES,
+        "guild_message_reactions" => GatewayIntents::GUILD_MESSAGE_REACTIONS,
+        "direct_messages" => GatewayIntents::DIRECT_MESSAGES,
+        "direct_message_reactions" => GatewayIntents::DIRECT_MESSAGE_REACTIONS,
+        "message_content" => GatewayIntents::MESSAGE_CONTENT,
+        _ => return None,
+    })
+}
+
+pub fn parse_intents(names: &[String]) -> Result<GatewayIntents, String> {
+    names.iter().try_fold(GatewayIntents::empty(), |acc, name| {
+        intent(name)
+            .map(|x| acc | x)
+            .ok_or_else(|| format!("unknown intent {:?}", name))
+    })
+}
+
+impl Config {
+    pub fn parse(path: &Path, text: &str) -> Result<Self, ConfigError> {
+        toml::from_str(text).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::IOError(path.to_path_buf(), e))?;
+        Self::parse(path, &text)
+    }
+
+    /// Every problem with the file, not just the first.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        if self.token.is_some() && self.token_file.is_some() {
+            problems.push("set token or token_file, not both".to_string());
+        }
+        if self.prerelease.token.is_some() && self.prerelease.token == self.token {
+            problems.push("prerelease.token must be a different bot's token".to_string());
+        }
+        if let Some(path) = &self.token_file {
+            if !path.is_file() {
+                problems.push(format!("token_file {} doesn't exist", path.display()));
+            }
+        }
+        if let Some(path) = &self.paths.secret_key_file {
+            if !path.is_file() {
+                problems.push(format!("paths.secret_key_file {} doesn't exist", path.display()));
+            }
+        }
+
+        if let Some(prefix) = &self.prefix {
+            let mut dummy = settings::GuildSettings::default();
+            if let Err(e) = settings::Key::Prefix.set(&mut dummy, prefix) {
+                problems.push(e.to_string());
+            }
+        }
+
+        if let Some(intents) = &self.intents {
+            if let Err(e) = parse_intents(intents) {
+                problems.push(e);
+            }
+        }
+
+        let timing = &self.mockingbird.timing;
+        if timing.abandoned_timeout == Some(0) {
+            problems.push("mockingbird.timing.abandoned_timeout must be above 0".to_string());
+        }
+        if timing.child_timeout == Some(0) {
+            problems.push("mockingbird.timing.child_timeout must be above 0".to_string());
+        }
+        if timing.arl_check_interval == Some(0) {
+            problems.push("mockingbird.timing.arl_check_interval must be above 0".to_string());
+        }
+
+        let pipes = &self.mockingbird.pipes;
+        for (name, value) in [
+            ("prebuffer_secs", pipes.prebuffer_secs),
+            ("prebuffer_timeout", pipes.prebuffer_timeout),
+            ("buffer_secs", pipes.buffer_secs),
+        ] {
+            if value.is_some_and(|x| x.is_nan() || x < 0.0) {
+                problems.push(format!("mockingbird.pipes.{} must be 0 or more", name));
+            }
+        }
+        if let (Some(prebuffer), Some(buffer)) = (pipes.prebuffer_secs, pipes.buffer_secs) {
+            if buffer < prebuffer {
+                problems.push("mockingbird.pipes.buffer_secs must be at least prebuffer_secs".to_string());
+            }
+        }
+
+        for (name, value) in self.limits() {
+            if value == Some(0) {
+                problems.push(format!("mockingbird.limits.{} must be above 0", name));
+            }
+        }
+
+        let sources = &self.mockingbird.sources;
+        for (name, hosts) in [("deemix", &sources.deemix), ("ytdl", &sources.ytdl), ("http", &sources.http)] {
+            if let Some(hosts) = hosts {
+                if hosts.iter().any(|x| x.trim().is_empty() || x.contains(',')) {
+                    problems.push(format!("mockingbird.sources.{} has an empty host or a comma", name));
+                }
+            }
+        }
+        if let Some(quality) = &sources.deemix_quality {
+            if !["flac", "320", "128"].contains(&quality.to_ascii_lowercase().as_str()) {
+                problems.push(format!("mockingbird.sources.deemix_quality {:?} must be flac, 320 or 128", quality));
+            }
+        }
+        if sources.fallback_tolerance.is_some_and(|x| x.is_nan() || x < 0.0) {
+            problems.push("mockingbird.sources.fallback_tolerance must be 0 or more".to_string());
+        }
+
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(ConfigError::Invalid(problems)),
+        }
+    }
+
+    fn limits(&self) -> [(&'static str, Option<usize>); 5] {
+        let limits = &self.mockingbird.limits;
+        [
+            ("max_children", limits.max_children),
+            ("max_deemix", limits.max_deemix),
+            ("max_ytdl", limits.max_ytdl),
+            ("max_http", limits.max_http),
+            ("max_metadata", limits.max_metadata),
+        ]
+    }
+
+    /// The environment variables the file sets.
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        fn path(x: &Option<PathBuf>) -> Option<String> {
+            x.as_ref().map(|x| x.display().to_string())
+        }
+        fn list(x: &Option<Vec<String>>) -> Option<String> {
+            x.as_ref().map(|x| x.join(","))
+        }
+
+        let timing = &self.mockingbird.timing;
+        let pipes = &self.mockingbird.pipes;
+        let limits = &self.mockingbird.limits;
+        let sources = &self.mockingbird.sources;
+
+        [
+            (crate::EnvVars::SETTINGS_FILE, path(&self.paths.settings)),
+            ("MKBIRD_CACHE_DIR", path(&self.paths.cache)),
+            ("MKBIRD_SECRET_STORE", path(&self.paths.secret_store)),
+            ("MKBIRD_SECRET_KEY_FILE", path(&self.paths.secret_key_file)),
+            ("MKBIRD_QUEUE_FILE", path(&self.paths.queues)),
+            ("COGGIEBOT_PRERELEASE_TOKEN", self.prerelease.token.clone()),
+            ("COGGIEBOT_PRERELEASE_DIR", path(&self.prerelease.dir)),
+            ("MKBIRD_ABANDONED_TIMEOUT", timing.abandoned_timeout.map(|x| x.to_string())),
+            ("MKBIRD_PRELOAD_OFFSET", timing.preload_offset.map(|x| x.to_string())),
+            ("MKBIRD_CHILD_TIMEOUT", timing.child_timeout.map(|x| x.to_string())),
+            ("MKBIRD_CHILD_MAX_SECS", timing.child_max_secs.map(|x| x.to_string())),
+            ("MKBIRD_ARL_CHECK_INTERVAL", timing.arl_check_interval.map(|x| x.to_string())),
+            ("MKBIRD_PREBUFFER_SECS", pipes.prebuffer_secs.map(|x| x.to_string())),
+            ("MKBIRD_PREBUFFER_TIMEOUT", pipes.prebuffer_timeout.map(|x| x.to_string())),
+            ("MKBIRD_BUFFER_SECS", pipes.buffer_secs.map(|x| x.to_string())),
+            ("MKBIRD_BALLOON", pipes.balloon.clone()),
+            ("MKBIRD_MAX_CHILDREN", limits.max_children.map(|x| x.to_string())),
+            ("MKBIRD_MAX_DEEMIX", limits.max_deemix.map(|x| x.to_string())),
+            ("MKBIRD_MAX_YTDL", limits.max_ytdl.map(|x| x.to_string())),
+            ("MKBIRD_MAX_HTTP", limits.max_http.map(|x| x.to_string())),
+            ("MKBIRD_MAX_METADATA", limits.max_metadata.map(|x| x.to_string())),
+            ("MKBIRD_DEEMIX_HOSTS", list(&sources.deemix)),
+            ("MKBIRD_YTDL_HOSTS", list(&sources.ytdl)),
+            ("MKBIRD_HTTP_HOSTS", list(&sources.http)),
+            ("MKBIRD_DEEMIX_QUALITY", sources.deemix_quality.clone()),
+            ("MKBIRD_FALLBACK_TOLERANCE", sources.fallback_tolerance.map(|x| x.to_string())),
+            ("MKBIRD_DEEZER_URL", sources.deezer_url.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(var, value)| value.map(|x| (var, x)))
+        .collect()
+    }
+
+    /// [`Self::env`] without the variables `is_set` says are already set,
+    /// which win over the file.
+    pub fn env_overrides(&self, is_set: impl Fn(&str) -> bool) -> Vec<(&'static str, String)> {
+        self.env()
+            .into_iter()
+            .filter(|(var, _)| !is_set(var))
+            .collect()
+    }
+
+    /// Export [`Self::env_overrides`]. Must run before anything reads
+    /// them, and before any other thread is started.
+    pub fn apply_env(&self) {
+        for (var, value) in self.env_overrides(|var| std::env::var_os(var).is_some()) {
+            std::env::set_var(var, value);
+        }
+    }
+
+    /// The token: `token`, or the contents of `token_file`.
+    pub fn token(&self) -> Result<Option<String>, ConfigError> {
+        if let Some(token) = &self.token {
+            return Ok(Some(token.clone()));
+        }
+
+        match &self.token_file {
+            Some(path) => std::fs::read_to_string(path)
+                .map(|x| Some(x.trim().to_string()))
+                .map_err(|e| ConfigError::IOError(path.clone(), e)),
+            None => Ok(None),
+        }
+    }
+}
@@ -7,7 +7,9 @@ use serenity::framework::standard::{
     CommandResult,
 };
 
-use serenity::model::channel::Message;
+use serenity::builder::{CreateBotAuthParameters, CreateEmbed};
+use serenity::http::Http;
+use serenity::model::{channel::Message, prelude::Scope};
 use serenity::prelude::*;
 
 #[group]
@@ -21,52 +23,60 @@ async fn version(ctx: &Context, msg: &Message) -> CommandResult {
 }
 
 #[command]
+#[owners_only]
 async fn reboot(ctx: &Context, msg: &Message) -> CommandResult {
     msg.channel_id.say(&ctx.http, "will kill all hu-").await?;
-    std::process::exit(3);
-    panic!("reboot request");
+    tokio::spawn(crate::shutdown::shutdown(ctx.data.clone(), "reboot"));
     Ok(())
 }
 
 #[command("rev")]
 async fn rev_cmd(ctx: &Context, msg: &Message) -> CommandResult {
+    msg.channel_id.say(&ctx.http, rev_text()).await?;
+    Ok(())
+}
+
+pub fn contribute_embed() -> CreateEmbed {
+    let tickets = format!("{}/issues", REPO);
+    let mut e = CreateEmbed::default();
+    e.title("Coggie Bot")
+        .description("Coggie Bot is an open source \"Discord\" (discord.com) bot.")
+        .url(REPO)
+        .fields(vec![
+            ("License", "BSD2", false),
+            ("Version", VERSION, false),
+            ("Revision", get_rev(), false),
+            ("Tickets", tickets.as_str(), false),
+        ]);
+    e
+}
+
+pub fn rev_text() -> String {
     match get_rev() {
-        "canary" => {
-            msg.channel_id.say(&ctx.http, "This is a canary build.").await?;
-        },
-        _ => { msg.channel_id.say(&ctx.http, format!("{REPO}/commit/{}", get_rev())).await?; }
+        "canary" => "This is a canary build.".to_string(),
+        rev => format!("{REPO}/commit/{}", rev),
     }
-    Ok(())
+}
+
+pub async fn invite_link(http: &Http) -> serenity::Result<String> {
+    let mut builder = CreateBotAuthParameters::default();
+    builder.auto_client_id(http).await?;
+    Ok(builder
+        .scopes(&[Scope::Bot, Scope::ApplicationsCommands])
+        .clone()
+        .build())
 }
 
 #[command]
 async fn contribute(ctx: &Context, msg: &Message) -> CommandResult {
     msg.channel_id
-       .send_message(&ctx.http, |m|
-           m
-            .add_embed(|e|
-                e.title("Coggie Bot")
-                   .description("Coggie Bot is an open source \"Discord\" (discord.com) bot.")
-                   .url(REPO)
-                    .fields(vec![
-                        ("License", "BSD2", false),
-                        ("Version", VERSION, false),
-                        ("Revision", get_rev(), false),
-                        ("Tickets", &format!("{}/issues", REPO), false),
-                   ])
-            )
-       ).await?;
+       .send_message(&ctx.http, |m| m.set_embed(contribute_embed()))
+       .await?;
     Ok(())
 }
+
 #[command("invite")]
 async fn invite(ctx: &Context, msg: &Message) -> CommandResult {
-    let mut builder = serenity::builder::CreateBotAuthParameters::default();
-
-    msg.channel_id.say(&ctx.http,
-        <serenity::builder::CreateBotAuthParameters as Clone>::clone(&builder
-          .auto_client_id(&ctx.http).await?
-          .scopes(&[serenity::model::prelude::Scope::Bot]))
-          .build()
-    ).await?;
+    msg.channel_id.say(&ctx.http, invite_link(&ctx.http).await?).await?;
     Ok(())
-}
\ No newline at end of file
+}
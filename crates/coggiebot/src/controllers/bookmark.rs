@@ -2,6 +2,7 @@ use crate::REPO;
 use serenity::builder::{CreateMessage};
 use serenity::model::{
     channel::{Message, ReactionType},
+    id::{GuildId, UserId},
     prelude::Reaction,
     Timestamp,
 };
@@ -15,13 +16,13 @@ use serenity::prelude::*;
 fn build_embed<'a, 'b>(
     msg: &Message,
     b: &'a mut CreateMessage<'b>,
-    ev: &Reaction
+    guild_id: Option<GuildId>,
 ) -> &'a mut CreateMessage<'b>
 {
-    let link = match ev.guild_id {
+    let link = match guild_id {
         Some(gid) => format!(
             "https://discord.com/channels/{}/{}/{}",
-            gid, ev.channel_id, ev.message_id
+            gid, msg.channel_id, msg.id
         ),
         None => String::from("N/A"),
     };
@@ -75,6 +76,25 @@ fn build_message_reply<'a, 'b>(msg: &Message, b: &'a mut CreateMessage<'b>) -> &
        )
 }
 
+/// DM `msg` to `user_id`.
+pub async fn bookmark_message(
+    ctx: &Context,
+    user_id: UserId,
+    guild_id: Option<GuildId>,
+    msg: &Message,
+) -> CommandResult
+{
+    user_id
+        .create_dm_channel(&ctx)
+        .await?
+        .send_message(&ctx, |b| {
+            build_embed(msg, b, guild_id);
+            build_message_reply(msg, b)
+        })
+        .await?;
+    Ok(())
+}
+
 pub async fn bookmark_on_react_add(ctx: &Context, ev: &Reaction) -> CommandResult {
     if let ReactionType::Unicode(x) = &ev.emoji {
 
@@ -85,20 +105,8 @@ pub async fn bookmark_on_react_add(ctx: &Context, ev: &Reaction) -> CommandResul
 
         if let Some(user_id) = ev.user_id {
             // grab message
-            let msg = ev.channel_id.message(&ctx, ev.message_id).await.unwrap();
-
-            user_id
-                .to_user(&ctx)
-                .await
-                .expect("Couldn't find user")
-                .create_dm_channel(&ctx)
-                .await
-                .unwrap()
-                .send_message(&ctx, |b| {
-                    build_embed(&msg, b, ev);
-                    build_message_reply(&msg, b)
-                })
-                .await?;
+            let msg = ev.channel_id.message(&ctx, ev.message_id).await?;
+            bookmark_message(ctx, user_id, ev.guild_id, &msg).await?;
         }
     }
     return Ok(());
@@ -0,0 +1,95 @@
+use serenity::builder::CreateEmbed;
+use serenity::framework::standard::{
+    macros::{command, group},
+    Args, CommandResult,
+};
+use serenity::model::{channel::Message, id::GuildId};
+use serenity::prelude::*;
+use settings::{Key, Settings};
+
+#[group]
+#[prefix = "config"]
+#[only_in(guilds)]
+#[required_permissions("MANAGE_GUILD")]
+#[default_command(show)]
+#[commands(show, get, set, reset)]
+pub struct Config;
+
+/// Every setting of `guild_id`, with what it does.
+pub fn settings_embed(guild_id: GuildId) -> CreateEmbed {
+    let settings = Settings::global().guild(guild_id.0);
+
+    let mut e = CreateEmbed::default();
+    e.title("Settings")
+        .fields(Key::ALL.iter().map(|key| (
+            key.name(),
+            format!(
+                "{}\n{}",
+                key.get(&settings).unwrap_or_else(|| "default".to_string()),
+                key.description()
+            ),
+            false,
+        )));
+    e
+}
+
+pub fn get_setting(guild_id: GuildId, key: &str) -> Result<String, String> {
+    let key = key.parse::<Key>().map_err(|e| e.to_string())?;
+    let settings = Settings::global().guild(guild_id.0);
+    Ok(format!("{}: {}", key, key.get(&settings).unwrap_or_else(|| "default".to_string())))
+}
+
+pub fn set_setting(guild_id: GuildId, key: &str, value: &str) -> Result<String, String> {
+    let key = key.parse::<Key>().map_err(|e| e.to_string())?;
+    Settings::global()
+        .update(guild_id.0, |s| key.set(s, value).map(|_| key.get(s)))
+        .map(|value| format!("{} set to {}", key, value.unwrap_or_default()))
+        .map_err(|e| e.to_string())
+}
+
+pub fn reset_setting(guild_id: GuildId, key: &str) -> Result<String, String> {
+    let key = key.parse::<Key>().map_err(|e| e.to_string())?;
+    Settings::global()
+        .update(guild_id.0, |s| { key.reset(s); Ok(()) })
+        .map(|_| format!("{} reset", key))
+        .map_err(|e| e.to_string())
+}
+
+async fn say_reply(ctx: &Context, msg: &Message, reply: Result<String, String>) -> CommandResult {
+    let text = match reply {
+        Ok(x) | Err(x) => x,
+    };
+    msg.channel_id.say(&ctx.http, text).await?;
+    Ok(())
+}
+
+#[command]
+async fn show(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Only available in servers")?;
+    msg.channel_id
+       .send_message(&ctx.http, |m| m.set_embed(settings_embed(guild_id)))
+       .await?;
+    Ok(())
+}
+
+/// `config get <key>`
+#[command]
+async fn get(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Only available in servers")?;
+    say_reply(ctx, msg, get_setting(guild_id, args.rest())).await
+}
+
+/// `config set <key> <value>`
+#[command]
+async fn set(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Only available in servers")?;
+    let key = args.single::<String>().unwrap_or_default();
+    say_reply(ctx, msg, set_setting(guild_id, &key, args.rest())).await
+}
+
+/// `config reset <key>`
+#[command]
+async fn reset(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Only available in servers")?;
+    say_reply(ctx, msg, reset_setting(guild_id, args.rest())).await
+}
@@ -1,9 +1,11 @@
 use crate::REPO;
-use serenity::model::prelude::Message;
+use serenity::builder::CreateEmbed;
+use serenity::model::{prelude::Message, id::GuildId};
 use serenity::framework::standard::{
     macros::{command, group},
-    CommandResult,
+    Args, CommandResult,
 };
+use settings::Settings;
 
 use std::{path::PathBuf, io::BufRead};
 use serenity::prelude::*;
@@ -21,24 +23,85 @@ pub fn feature_list() -> Vec<(String, bool)>{
 #[commands(features)]
 pub struct Features;
 
+/// What this build has, and in `guild_id`, which groups are on.
+pub fn features_embed(guild_id: Option<GuildId>) -> CreateEmbed {
+    let mut e = CreateEmbed::default();
+    e.title("Coggie Bot")
+        .description("Coggie Bot is an open source \"Discord\" (discord.com) bot.")
+        .url(REPO)
+        .fields(feature_list()
+            .into_iter()
+            .map(|(name, enabled)| (name, if enabled { "enabled" } else { "disabled" }, true))
+        );
+
+    if let Some(guild_id) = guild_id {
+        let settings = Settings::global().guild(guild_id.0);
+        let groups = super::toggles()
+            .into_iter()
+            .map(|name| format!("`{}` {}", name, if settings.is_enabled(name) { "on" } else { "off" }))
+            .collect::<Vec<_>>();
+
+        if !groups.is_empty() {
+            e.field("In this server", groups.join("\n"), false);
+        }
+    }
+    e
+}
+
+/// Turn `group` on or off in `guild_id`.
+pub fn set_group(guild_id: GuildId, group: &str, enabled: bool) -> Result<String, String> {
+    let group = group.trim().to_lowercase();
+    let toggles = super::toggles();
+    let group = *toggles.iter()
+        .find(|x| **x == group)
+        .ok_or_else(|| format!("Unknown group `{}`, expected one of {}", group, toggles.join(", ")))?;
+
+    if !enabled && super::ALWAYS_ON.contains(&group) {
+        return Err(format!("`{}` can't be turned off", group));
+    }
+
+    let changed = Settings::global()
+        .update(guild_id.0, |s| Ok(s.set_enabled(group, enabled)))
+        .map_err(|e| e.to_string())?;
+
+    let state = if enabled { "on" } else { "off" };
+    Ok(match changed {
+        true => format!("`{}` turned {}", group, state),
+        false => format!("`{}` is already {}", group, state),
+    })
+}
+
 #[command("features")]
+#[sub_commands(enable, disable)]
 async fn features(ctx: &Context, msg: &Message) -> CommandResult {
-    let features = feature_list();
-
     msg.channel_id
-       .send_message(&ctx.http, |m|
-           m.add_embed(|e|
-                e.title("Coggie Bot")
-                 .description("Coggie Bot is an open source \"Discord\" (discord.com) bot.")
-                 .url(REPO)
-                 .fields(features
-                    .iter().map(|r| match r {
-                        (name, true) => (name, "enabled", true),
-                        (name, false) => (name, "disabled", true),
-                        _ => unreachable!(),
-                    })
-                 )
-            )
-       ).await?;
+       .send_message(&ctx.http, |m| m.set_embed(features_embed(msg.guild_id)))
+       .await?;
+    Ok(())
+}
+
+/// `features enable <group>`
+#[command]
+#[only_in(guilds)]
+#[required_permissions("MANAGE_GUILD")]
+async fn enable(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Only available in servers")?;
+    let text = match set_group(guild_id, args.rest(), true) {
+        Ok(x) | Err(x) => x,
+    };
+    msg.channel_id.say(&ctx.http, text).await?;
+    Ok(())
+}
+
+/// `features disable <group>`
+#[command]
+#[only_in(guilds)]
+#[required_permissions("MANAGE_GUILD")]
+async fn disable(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or("Only available in servers")?;
+    let text = match set_group(guild_id, args.rest(), false) {
+        Ok(x) | Err(x) => x,
+    };
+    msg.channel_id.say(&ctx.http, text).await?;
     Ok(())
 }
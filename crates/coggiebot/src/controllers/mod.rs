@@ -10,40 +10,130 @@ mod basic;
 #[path = "features.rs"]
 pub mod features;
 
+#[cfg(feature = "config-cmd")]
+#[path = "config.rs"]
+pub mod config;
+
 #[cfg(feature = "prerelease")]
 #[path = "prerelease.rs"]
 pub mod prerelease;
 
+pub(crate) mod slash;
+
 use serenity::async_trait;
-use serenity::{framework::StandardFramework, client::ClientBuilder};
-use serenity::model::{channel::Reaction, gateway::Ready};
+use serenity::{
+    framework::{standard::CommandGroup, StandardFramework},
+    client::ClientBuilder,
+};
+use serenity::model::{
+    application::{command::Command, interaction::Interaction},
+    channel::{Message, Reaction},
+    gateway::Ready,
+    id::GuildId,
+};
 use serenity::prelude::*;
 
-macro_rules! add_commands {
-    ($framework:expr, { $( [ $($feature:literal),* ] => [ $($group:expr),* ]),* })
+macro_rules! command_groups {
+    ({ $( [ $($feature:literal),* ] => $name:literal => [ $($group:expr),* ]),* })
         => {
-            $(#[cfg(all( $(feature = $feature),* ))]
-              { $framework = $framework$(.group(&$group))*; })*
+            /// Every command group compiled in, with the name
+            /// guilds turn it on and off by.
+            pub fn command_groups() -> Vec<(&'static str, &'static CommandGroup)> {
+                #[allow(unused_mut)]
+                let mut groups = Vec::new();
+                $(#[cfg(all( $(feature = $feature),* ))]
+                  { $( groups.push(($name, &$group)); )* })*
+                groups
+            }
         }
 }
 
-#[allow(unused_mut)]
+command_groups!(
+    {
+        ["basic-cmds"] => "basic" => [basic::COMMANDS_GROUP],
+        ["prerelease"] => "prerelease" => [prerelease::PRERELEASE_GROUP],
+        ["list-feature-cmd"] => "features" => [features::FEATURES_GROUP],
+        ["config-cmd"] => "config" => [config::CONFIG_GROUP],
+        ["help-cmd"] => "help" => [features::HELP_GROUP],
+        ["mockingbird-arl-cmd"] => "arl" => [mockingbird::check::ARL_GROUP],
+        ["mockingbird-ctrl", "mockingbird-set-arl-cmd"] => "setarl" => [mockingbird::player::DANGEROUS_GROUP],
+        ["mockingbird-ctrl"] => "player" => [mockingbird::player::BETTERPLAYER_GROUP],
+        ["mockingbird-ctrl", "mockingbird-deemix"] => "quality" => [mockingbird::deemix::QUALITY_GROUP],
+        ["mockingbird-deemix"] => "arlpool" => [mockingbird::arl::POOL_GROUP],
+        ["mockingbird-core", "mockingbird-debug"] => "diagnostics" => [mockingbird::budget::DIAGNOSTICS_GROUP]
+    }
+);
+
+/// Groups that can't be turned off, since they turn the others back on.
+pub const ALWAYS_ON: &[&str] = &["features"];
+
+/// Everything a guild can turn on and off: the command groups, and
+/// features that aren't one (bookmarking by reaction).
+pub fn toggles() -> Vec<&'static str> {
+    #[allow(unused_mut)]
+    let mut toggles = command_groups().into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+    #[cfg(feature = "bookmark")]
+    toggles.push("bookmark");
+    toggles
+}
+
 pub fn setup_framework(mut cfg: StandardFramework) -> StandardFramework {
-    add_commands!(
-        cfg,
-        {
-            ["basic-cmds"] => [basic::COMMANDS_GROUP],
-            ["prerelease"] => [features::PRERELEASE_GROUP::PRERELEASE_GROUP],
-            ["list-feature-cmd"] => [features::FEATURES_GROUP],
-            ["help-cmd"] => [features::HELP_GROUP],
-            ["mockingbird-arl-cmd"] => [mockingbird::check::ARL_GROUP],
-            ["mockingbird-set-arl-cmd"] => [mockingbird::player::DANGEROUS_GROUP],
-            ["mockingbird-ctrl"] => [mockingbird::player::BETTERPLAYER_GROUP]
-        }
-    );
+    for (_, group) in command_groups() {
+        cfg = cfg.group(group);
+    }
     cfg
 }
 
+/// Whether `group` is on in `guild_id`. Everything is on in DMs.
+pub fn group_enabled(guild_id: Option<GuildId>, group: &str) -> bool {
+    if ALWAYS_ON.contains(&group) {
+        return true;
+    }
+    match guild_id {
+        Some(guild_id) => settings::Settings::global().guild(guild_id.0).is_enabled(group),
+        None => true,
+    }
+}
+
+/// The group `command` (the first word after the prefix) belongs to,
+/// by group prefix, command name or alias.
+pub fn group_of(command: &str) -> Option<&'static str> {
+    let matches = |names: &[&str]| names.iter().any(|x| x.eq_ignore_ascii_case(command));
+    command_groups().into_iter()
+        .find(|(_, group)| match group.options.prefixes {
+            [] => group.options.commands.iter().any(|c| matches(c.options.names)),
+            prefixes => matches(prefixes),
+        })
+        .map(|(name, _)| name)
+}
+
+/// The first word of `content` after `prefix` or a mention.
+pub(crate) fn command_word<'a>(content: &'a str, prefix: &str) -> &'a str {
+    let content = content.trim_start();
+    let rest = match content.strip_prefix(prefix) {
+        Some(rest) => rest,
+        None if content.starts_with("<@") => content.split_once('>').map_or("", |(_, rest)| rest),
+        None => content,
+    };
+    rest.split_whitespace().next().unwrap_or("")
+}
+
+/// Refuse prefix commands in groups the guild turned off.
+pub async fn allow_command(ctx: &Context, msg: &Message, prefix: &str) -> bool {
+    let group = match group_of(command_word(&msg.content, prefix)) {
+        Some(group) => group,
+        None => return true,
+    };
+
+    if group_enabled(msg.guild_id, group) {
+        return true;
+    }
+    let _ = msg.channel_id
+        .say(&ctx.http, format!("`{}` is turned off in this server", group))
+        .await;
+    false
+}
+
 #[allow(unused_mut)]
 pub async fn setup_state(mut cfg: ClientBuilder) -> ClientBuilder {
     #[cfg(feature = "mockingbird-core")]
@@ -61,16 +151,31 @@ impl EventHandler for EvHandler {
     #[allow(unused_variables)]
     async fn reaction_add(&self, ctx: Context, ev: Reaction) {
         #[cfg(feature="bookmark")]
-        tokio::spawn(async move {
-            use bookmark::bookmark_on_react_add;
-            match bookmark_on_react_add(&ctx, &ev).await {
-                Ok(_) => {},
-                Err(e) => { ev.channel_id.say(&ctx.http, format!("Error: {}", e)).await.unwrap(); },
-            };
-        });
+        if group_enabled(ev.guild_id, "bookmark") {
+            tokio::spawn(async move {
+                use bookmark::bookmark_on_react_add;
+                match bookmark_on_react_add(&ctx, &ev).await {
+                    Ok(_) => {},
+                    Err(e) => { ev.channel_id.say(&ctx.http, format!("Error: {}", e)).await.unwrap(); },
+                };
+            });
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        slash::interaction_create(&ctx, interaction).await;
     }
 
-    async fn ready(&self, _: Context, ready: Ready) {
+    #[allow(unused_variables)]
+    async fn ready(&self, ctx: Context, ready: Ready) {
         println!("{} is connected!", ready.user.name);
+
+        match Command::set_global_application_commands(&ctx.http, |c| slash::register(c)).await {
+            Ok(cmds) => tracing::info!("registered {} application commands", cmds.len()),
+            Err(e) => tracing::error!("couldn't register application commands: {}", e),
+        }
+
+        #[cfg(feature = "mockingbird-core")]
+        mockingbird::ready(&ctx).await;
     }
 }
@@ -1,39 +1,464 @@
-use crate::{REPO, pkglib::{CoggiebotError}};
-use serenity::model::prelude::Message;
+//! `prerelease`: try out features in a second coggiebot, next to this one.
+//!
+//! The child is built from a local checkout, or a branch or tag of
+//! [`REPO`], with only the requested features, none of which may be enabled
+//! here. It runs under its own token (`COGGIEBOT_PRERELEASE_TOKEN`) in
+//! `COGGIEBOT_PRERELEASE_DIR`, with its own settings and queue files, and is
+//! restarted if it crashes. Only one runs at a time.
+
+use crate::{shutdown::EXIT_RESTART, REPO};
 use serenity::framework::standard::{
     macros::{command, group},
-    CommandResult,
+    Args, CommandResult,
+};
+use serenity::http::Http;
+use serenity::model::{channel::Message, id::ChannelId};
+use serenity::prelude::Context;
+
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::{Child, Command},
+    sync::Notify,
 };
 
-use std::{path::PathBuf, io::BufRead};
-use serenity::prelude::*;
+pub const TOKEN: &str = "COGGIEBOT_PRERELEASE_TOKEN";
+pub const DIR: &str = "COGGIEBOT_PRERELEASE_DIR";
 
+/// Lines of build and run output kept for `prerelease logs`.
+const LOG_LINES: usize = 200;
+const MAX_RESTARTS: u32 = 3;
+const BUILD_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// How long `stop` waits after SIGTERM before killing the child.
+const STOP_TIMEOUT: Duration = Duration::from_secs(15);
 
+/// The only variables git, cargo and the child get from us. Anything else,
+/// ARLs, keys, owners and paths included, stays here.
+const INHERITED_ENV: [&str; 12] = [
+    "PATH", "HOME", "USER", "LANG", "TZ", "RUST_LOG", "RUST_BACKTRACE",
+    "CARGO_HOME", "RUSTUP_HOME", "RUSTUP_TOOLCHAIN", "SSL_CERT_FILE", "SSL_CERT_DIR",
+];
 
 #[group]
+#[prefixes("prerelease", "pre-release")]
+#[owners_only]
+#[default_command(start)]
+#[commands(start, status, logs, stop)]
 pub struct PreRelease;
 
-/// activate: "@botname prerelease {repo}/(branch-name)"
-/// This relies on the branch containing an up to date flake.nix file which exports
-/// `packages.<system>.coggiebot-pre-release` as a derivation.
-/// which only contains feature flags for the newly spawned running instance.
-///
-/// this feature can be used by users to test new features before they are released.
-///
-/// if the user specifies the origin of the branch else where than the default
-/// then the bot will first check meta-data for the repo owner and verify that
-/// the user is marked as a maintainer inside coggiebot's metadata.
-///
-/// before spawning the new instance the bot will check that none of its features,
-/// and the others are overlapping. if they are overlapping then the bot will
-/// refuse to spawn the new instance.
-///
-/// Example: cargo build --release \
-///             --features "prerelease-feature-name" \
-///             --no-default-features
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum State {
+    Building,
+    Running { pid: Option<u32>, since: Instant, restarts: u32 },
+    Failed(String),
+    Exited(String),
+    Stopped,
+}
+
+impl State {
+    fn active(&self) -> bool {
+        matches!(self, State::Building | State::Running { .. })
+    }
+}
+
+struct Instance {
+    source: String,
+    features: Vec<String>,
+    state: State,
+    logs: VecDeque<String>,
+    stop: Arc<Notify>,
+    done: Arc<Notify>,
+}
+
+static INSTANCE: Mutex<Option<Instance>> = Mutex::new(None);
+
+fn with_instance<T>(f: impl FnOnce(&mut Instance) -> T) -> Option<T> {
+    INSTANCE.lock().expect("prerelease lock poisoned").as_mut().map(f)
+}
+
+fn set_state(state: State) {
+    with_instance(|x| x.state = state);
+}
+
+fn log(line: impl Into<String>) {
+    with_instance(|x| {
+        if x.logs.len() == LOG_LINES {
+            x.logs.pop_front();
+        }
+        x.logs.push_back(line.into());
+    });
+}
+
+fn log_text() -> String {
+    with_instance(|x| x.logs.iter().cloned().collect::<Vec<_>>().join("\n")).unwrap_or_default()
+}
+
+fn dir() -> PathBuf {
+    std::env::var(DIR)
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| std::env::temp_dir().join("coggiebot-prerelease"))
+}
+
+/// Git refs and feature names end up in argument lists.
+pub(crate) fn safe_name(s: &str) -> bool {
+    !s.is_empty()
+        && !s.starts_with('-')
+        && !s.contains("..")
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || "-_./".contains(c))
+}
+
+/// Requested features already enabled in this instance.
+pub fn overlaps(requested: &[String], here: &[(String, bool)]) -> Vec<String> {
+    requested.iter()
+        .filter(|f| here.iter().any(|(name, enabled)| *enabled && name == *f))
+        .cloned()
+        .collect()
+}
+
+/// Stop the child, if any, waiting for it to shut down.
+pub async fn shutdown() {
+    let notifies = with_instance(|x| x.state.active().then(|| (x.stop.clone(), x.done.clone()))).flatten();
+    if let Some((stop, done)) = notifies {
+        stop.notify_one();
+        let _ = tokio::time::timeout(STOP_TIMEOUT + Duration::from_secs(5), done.notified()).await;
+    }
+}
+
+/// `cmd` with only [`INHERITED_ENV`] from our environment.
+fn clean_env(cmd: &mut Command) -> &mut Command {
+    cmd.env_clear()
+        .envs(INHERITED_ENV.iter().filter_map(|k| std::env::var_os(k).map(|v| (*k, v))))
+}
+
+/// `prerelease <path|ref> <feature>...`
 #[command]
-#[aliases("pre-release")]
-async fn prerelease(ctx: &Context, msg: &Message) -> CommandResult {
-    msg.reply(ctx, "ERROR: not implemented yet").await?;
+async fn start(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let source = args.single::<String>().unwrap_or_default();
+    let features = args.rest()
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|x| !x.is_empty())
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+
+    if source.is_empty() || features.is_empty() {
+        msg.reply(ctx, "Usage: `prerelease <path|ref> <feature>...`").await?;
+        return Ok(());
+    }
+    if let Some(bad) = features.iter().find(|f| !safe_name(f)) {
+        msg.reply(ctx, format!("Invalid feature name `{}`", bad)).await?;
+        return Ok(());
+    }
+
+    let token = match std::env::var(TOKEN) {
+        Ok(token) if !token.trim().is_empty() => token,
+        _ => {
+            msg.reply(ctx, format!("Set `{}` to the prerelease bot's token first", TOKEN)).await?;
+            return Ok(());
+        }
+    };
+
+    let overlapping = overlaps(&features, &super::features::feature_list());
+    if !overlapping.is_empty() {
+        msg.reply(ctx, format!("Already enabled here, refusing to run them twice: {}", overlapping.join(", "))).await?;
+        return Ok(());
+    }
+
+    let stop = Arc::new(Notify::new());
+    let done = Arc::new(Notify::new());
+    let claimed = {
+        let mut instance = INSTANCE.lock().expect("prerelease lock poisoned");
+        let busy = instance.as_ref().map_or(false, |x| x.state.active());
+        if !busy {
+            *instance = Some(Instance {
+                source: source.clone(),
+                features: features.clone(),
+                state: State::Building,
+                logs: VecDeque::new(),
+                stop: stop.clone(),
+                done: done.clone(),
+            });
+        }
+        !busy
+    };
+    if !claimed {
+        msg.reply(ctx, "A prerelease is already running, `prerelease stop` it first").await?;
+        return Ok(());
+    }
+
+    tracing::info!("[{}::{}] started prerelease {} with {}", msg.author.id, msg.author.name, source, features.join(","));
+    msg.channel_id
+        .say(&ctx.http, format!("Building `{}` with `{}`...", source, features.join(",")))
+        .await?;
+
+    let http = ctx.http.clone();
+    let channel = msg.channel_id;
+    tokio::spawn(async move {
+        run(&http, channel, &source, &features, &token, &stop).await;
+        done.notify_one();
+    });
+    Ok(())
+}
+
+#[command]
+async fn status(ctx: &Context, msg: &Message) -> CommandResult {
+    let text = with_instance(|x| {
+        let state = match &x.state {
+            State::Building => "building".to_string(),
+            State::Running { pid, since, restarts } => format!(
+                "running (pid {}) for {}s, restarted {} time(s)",
+                pid.map_or("?".to_string(), |x| x.to_string()),
+                since.elapsed().as_secs(),
+                restarts
+            ),
+            State::Failed(e) => format!("failed: {}", e),
+            State::Exited(status) => format!("exited: {}", status),
+            State::Stopped => "stopped".to_string(),
+        };
+        format!("`{}` with `{}`: {}", x.source, x.features.join(","), state)
+    });
+
+    msg.channel_id
+        .say(&ctx.http, text.unwrap_or_else(|| "No prerelease has run".to_string()))
+        .await?;
     Ok(())
 }
+
+#[command]
+async fn logs(ctx: &Context, msg: &Message) -> CommandResult {
+    send_logs(&ctx.http, msg.channel_id, "Prerelease logs").await;
+    Ok(())
+}
+
+#[command]
+async fn stop(ctx: &Context, msg: &Message) -> CommandResult {
+    let active = with_instance(|x| x.state.active()).unwrap_or(false);
+    if !active {
+        msg.reply(ctx, "No prerelease is running").await?;
+        return Ok(());
+    }
+    shutdown().await;
+    Ok(())
+}
+
+async fn send_logs(http: &Http, channel: ChannelId, content: &str) {
+    let text = log_text();
+    let sent = match text.is_empty() {
+        true => channel.say(http, format!("{} (no output)", content)).await.map(|_| ()),
+        false => channel
+            .send_files(http, vec![(text.as_bytes(), "prerelease.log")], |m| m.content(content))
+            .await
+            .map(|_| ()),
+    };
+
+    if let Err(e) = sent {
+        tracing::error!("couldn't send prerelease logs: {}", e);
+    }
+}
+
+/// Build, then run until stopped, it exits cleanly, or it runs out of restarts.
+async fn run(http: &Http, channel: ChannelId, source: &str, features: &[String], token: &str, stop: &Notify) {
+    let built = tokio::select! {
+        built = build(source, features) => built,
+        _ = stop.notified() => Err("stopped".to_string()),
+    };
+
+    let binary = match built {
+        Ok(binary) => binary,
+        Err(e) => {
+            set_state(State::Failed(e.clone()));
+            send_logs(http, channel, &format!("Prerelease build failed: {}", e)).await;
+            return;
+        }
+    };
+
+    let mut restarts = 0;
+    loop {
+        let mut child = match spawn(&binary, token) {
+            Ok(child) => child,
+            Err(e) => {
+                set_state(State::Failed(e.to_string()));
+                let _ = channel.say(http, format!("Couldn't start the prerelease: {}", e)).await;
+                return;
+            }
+        };
+
+        let pid = child.id();
+        set_state(State::Running { pid, since: Instant::now(), restarts });
+        let _ = channel.say(http, format!("Prerelease running (pid {})", pid.map_or("?".to_string(), |x| x.to_string()))).await;
+
+        let status = tokio::select! {
+            status = child.wait() => status,
+            _ = stop.notified() => {
+                terminate(&mut child).await;
+                set_state(State::Stopped);
+                let _ = channel.say(http, "Prerelease stopped").await;
+                return;
+            }
+        };
+
+        let code = status.as_ref().ok().and_then(ExitStatus::code);
+        let status = describe(status);
+        log(format!("exited: {}", status));
+
+        // only crashes count against MAX_RESTARTS
+        match code {
+            Some(0) => {
+                set_state(State::Exited(status.clone()));
+                let _ = channel.say(http, format!("Prerelease exited ({})", status)).await;
+                return;
+            }
+            Some(EXIT_RESTART) => {
+                let _ = channel.say(http, "Prerelease rebooted, restarting").await;
+            }
+            _ if restarts == MAX_RESTARTS => {
+                set_state(State::Exited(status.clone()));
+                send_logs(http, channel, &format!("Prerelease exited ({}), not restarting", status)).await;
+                return;
+            }
+            _ => {
+                restarts += 1;
+                let _ = channel
+                    .say(http, format!("Prerelease exited ({}), restarting ({}/{})", status, restarts, MAX_RESTARTS))
+                    .await;
+            }
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(5 * restarts.max(1) as u64)) => {}
+            _ = stop.notified() => {
+                set_state(State::Stopped);
+                let _ = channel.say(http, "Prerelease stopped").await;
+                return;
+            }
+        }
+    }
+}
+
+/// SIGTERM, so the child shuts down gracefully, then SIGKILL if it
+/// hasn't exited within [`STOP_TIMEOUT`].
+async fn terminate(child: &mut Child) {
+    if let Some(pid) = child.id() {
+        let mut kill = Command::new("kill");
+        kill.args(["-TERM", &pid.to_string()]).stdin(Stdio::null());
+        match kill.status().await {
+            Ok(sent) if sent.success() => match tokio::time::timeout(STOP_TIMEOUT, child.wait()).await {
+                Ok(status) => {
+                    log(format!("stopped: {}", describe(status)));
+                    return;
+                }
+                Err(_) => log(format!("still running {}s after SIGTERM, killing it", STOP_TIMEOUT.as_secs())),
+            },
+            Ok(sent) => log(format!("couldn't send SIGTERM: kill {}", sent)),
+            Err(e) => log(format!("couldn't send SIGTERM: {}", e)),
+        }
+    }
+    let _ = child.kill().await;
+}
+
+fn describe(status: std::io::Result<ExitStatus>) -> String {
+    match status {
+        Ok(status) => status.to_string(),
+        Err(e) => format!("couldn't wait for it: {}", e),
+    }
+}
+
+/// A checkout of `source`, cloning it if it isn't a directory.
+async fn checkout(source: &str) -> Result<PathBuf, String> {
+    let path = Path::new(source);
+    if path.is_dir() {
+        return Ok(path.to_path_buf());
+    }
+    if !safe_name(source) {
+        return Err(format!("`{}` is neither a directory nor a branch or tag", source));
+    }
+
+    let target = dir().join("checkout");
+    if target.exists() {
+        tokio::fs::remove_dir_all(&target)
+            .await
+            .map_err(|e| format!("couldn't remove the old checkout: {}", e))?;
+    }
+
+    let mut git = Command::new("git");
+    clean_env(&mut git).args(["clone", "--depth", "1", "--branch", source, REPO]).arg(&target);
+    output(git, "git clone").await?;
+    Ok(target)
+}
+
+/// Build `coggiebot` from `source` with only `features`.
+async fn build(source: &str, features: &[String]) -> Result<PathBuf, String> {
+    let checkout = checkout(source).await?;
+    let target_dir = dir().join("target");
+
+    let mut cargo = Command::new("cargo");
+    clean_env(&mut cargo)
+        .current_dir(&checkout)
+        .args(["build", "--release", "-p", "coggiebot", "--no-default-features", "--features"])
+        .arg(features.join(","))
+        .env("CARGO_TARGET_DIR", &target_dir)
+        .env("COGGIEBOT_FEATURES", features.iter().map(|f| format!("{}=1", f)).collect::<Vec<_>>().join(","));
+
+    tokio::time::timeout(BUILD_TIMEOUT, output(cargo, "cargo build"))
+        .await
+        .map_err(|_| format!("the build took longer than {} minutes", BUILD_TIMEOUT.as_secs() / 60))??;
+
+    Ok(target_dir.join("release").join("coggiebot"))
+}
+
+/// Run `cmd` to completion, logging what it prints.
+async fn output(mut cmd: Command, name: &str) -> Result<(), String> {
+    log(format!("$ {}", name));
+    let out = cmd.stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| format!("couldn't run {}: {}", name, e))?;
+
+    for line in String::from_utf8_lossy(&out.stdout).lines().chain(String::from_utf8_lossy(&out.stderr).lines()) {
+        log(line);
+    }
+
+    match out.status.success() {
+        true => Ok(()),
+        false => Err(format!("{} failed ({})", name, out.status)),
+    }
+}
+
+/// Start the child with its own token and files, and none of ours.
+fn spawn(binary: &Path, token: &str) -> std::io::Result<Child> {
+    let dir = dir();
+    std::fs::create_dir_all(&dir)?;
+
+    let mut cmd = Command::new(binary);
+    let mut child = clean_env(&mut cmd)
+        .current_dir(&dir)
+        .env(crate::EnvVars::DISCORD_TOKEN, token)
+        .env(crate::EnvVars::SETTINGS_FILE, dir.join("settings.json"))
+        .env("MKBIRD_QUEUE_FILE", dir.join("queues.json"))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    if let Some(stdout) = child.stdout.take() {
+        tokio::spawn(collect(stdout));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(collect(stderr));
+    }
+    Ok(child)
+}
+
+async fn collect(pipe: impl AsyncRead + Unpin) {
+    let mut lines = BufReader::new(pipe).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        log(line);
+    }
+}
@@ -0,0 +1,351 @@
+//! Application commands: registration and dispatch.
+//!
+//! Commands are registered globally once the bot is ready. Mockingbird's are
+//! tried first, then coggiebot's own. Prefix commands keep working alongside.
+
+use std::{collections::HashMap, sync::OnceLock};
+
+use serenity::{
+    builder::{CreateApplicationCommands, CreateEmbed},
+    model::{
+        application::interaction::{
+            application_command::ApplicationCommandInteraction,
+            autocomplete::AutocompleteInteraction,
+            message_component::MessageComponentInteraction,
+            Interaction,
+            InteractionResponseType,
+        },
+    },
+    prelude::*,
+};
+
+#[allow(unused_imports)]
+use serenity::model::{
+    application::command::{CommandOptionType, CommandType},
+    application::interaction::application_command::CommandDataOption,
+    Permissions,
+};
+
+macro_rules! slash_commands {
+    ({ $( [ $($feature:literal),* ] => $name:literal => [ $($register:path),* ]),* })
+        => {
+            /// Every application command compiled in, registered by
+            /// the group guilds turn it on and off by.
+            fn slash_commands() -> Vec<(&'static str, fn(&mut CreateApplicationCommands))> {
+                #[allow(unused_mut)]
+                let mut commands: Vec<(&'static str, fn(&mut CreateApplicationCommands))> = Vec::new();
+                $(#[cfg(all( $(feature = $feature),* ))]
+                  commands.push(($name, |c: &mut CreateApplicationCommands| { $( $register(c); )* }));)*
+                commands
+            }
+        }
+}
+
+slash_commands!(
+    {
+        ["basic-cmds"] => "basic" => [register_basic],
+        ["list-feature-cmd"] => "features" => [register_features],
+        ["bookmark"] => "bookmark" => [register_bookmark],
+        ["config-cmd"] => "config" => [register_config],
+        ["mockingbird-core", "mockingbird-ctrl"] => "player" => [mockingbird::slash::register_player],
+        ["mockingbird-core", "mockingbird-arl-cmd"] => "arl" => [mockingbird::slash::register_arl]
+    }
+);
+
+#[allow(dead_code)]
+enum Reply {
+    Text(String),
+    Embed(CreateEmbed),
+}
+
+pub fn register(cmds: &mut CreateApplicationCommands) -> &mut CreateApplicationCommands {
+    for (_, register) in slash_commands() {
+        register(cmds);
+    }
+    cmds
+}
+
+#[cfg(feature = "basic-cmds")]
+fn register_basic(cmds: &mut CreateApplicationCommands) {
+    cmds.create_application_command(|c| c.name("version").description("Show the version"))
+        .create_application_command(|c| c.name("rev").description("Show the commit this was built from"))
+        .create_application_command(|c| c.name("contribute").description("Where to find the source"))
+        .create_application_command(|c| c.name("invite").description("Invite link for the bot"))
+        .create_application_command(|c| c
+            .name("reboot")
+            .description("Restart the bot")
+            .default_member_permissions(Permissions::ADMINISTRATOR));
+}
+
+#[cfg(feature = "list-feature-cmd")]
+fn register_features(cmds: &mut CreateApplicationCommands) {
+    fn group(o: &mut serenity::builder::CreateApplicationCommandOption) -> &mut serenity::builder::CreateApplicationCommandOption {
+        o.name("group").description("Command group").kind(CommandOptionType::String).required(true);
+        for name in super::toggles() {
+            o.add_string_choice(name, name);
+        }
+        o
+    }
+
+    cmds.create_application_command(|c| c
+        .name("features")
+        .description("Features this build has, and which are on here")
+        .create_option(|o| o.name("show").description("List the features").kind(CommandOptionType::SubCommand))
+        .create_option(|o| o
+            .name("enable").description("Turn a command group on in this server").kind(CommandOptionType::SubCommand)
+            .create_sub_option(group))
+        .create_option(|o| o
+            .name("disable").description("Turn a command group off in this server").kind(CommandOptionType::SubCommand)
+            .create_sub_option(group)));
+}
+
+#[cfg(feature = "bookmark")]
+fn register_bookmark(cmds: &mut CreateApplicationCommands) {
+    cmds.create_application_command(|c| c.name("Bookmark").kind(CommandType::Message));
+}
+
+#[cfg(feature = "config-cmd")]
+fn register_config(cmds: &mut CreateApplicationCommands) {
+    use settings::Key;
+
+    fn key(o: &mut serenity::builder::CreateApplicationCommandOption) -> &mut serenity::builder::CreateApplicationCommandOption {
+        o.name("key").description("Setting").kind(CommandOptionType::String).required(true);
+        for key in Key::ALL {
+            o.add_string_choice(key.name(), key.name());
+        }
+        o
+    }
+
+    cmds.create_application_command(|c| c
+        .name("config")
+        .description("Server settings")
+        .dm_permission(false)
+        .default_member_permissions(Permissions::MANAGE_GUILD)
+        .create_option(|o| o.name("show").description("Show every setting").kind(CommandOptionType::SubCommand))
+        .create_option(|o| o
+            .name("get").description("Show a setting").kind(CommandOptionType::SubCommand)
+            .create_sub_option(key))
+        .create_option(|o| o
+            .name("set").description("Change a setting").kind(CommandOptionType::SubCommand)
+            .create_sub_option(key)
+            .create_sub_option(|v| v
+                .name("value").description("New value").kind(CommandOptionType::String).required(true)))
+        .create_option(|o| o
+            .name("reset").description("Go back to the default").kind(CommandOptionType::SubCommand)
+            .create_sub_option(key)));
+}
+
+pub async fn interaction_create(ctx: &Context, interaction: Interaction) {
+    let result = match &interaction {
+        Interaction::ApplicationCommand(cmd) => command(ctx, cmd).await,
+        Interaction::Autocomplete(ac) => autocomplete(ctx, ac).await,
+        Interaction::MessageComponent(mci) => component(ctx, mci).await,
+        _ => return,
+    };
+
+    if let Err(e) = result {
+        tracing::error!("interaction failed: {}", e);
+    }
+}
+
+#[allow(unused_variables)]
+async fn autocomplete(ctx: &Context, ac: &AutocompleteInteraction) -> serenity::Result<()> {
+    #[cfg(all(feature = "mockingbird-core", any(feature = "mockingbird-ctrl", feature = "mockingbird-arl-cmd")))]
+    if let Some(result) = mockingbird::slash::autocomplete(ctx, ac).await {
+        return result;
+    }
+
+    Ok(())
+}
+
+#[allow(unused_variables)]
+async fn component(ctx: &Context, mci: &MessageComponentInteraction) -> serenity::Result<()> {
+    #[cfg(all(feature = "mockingbird-core", feature = "mockingbird-ctrl"))]
+    if mci.data.custom_id.starts_with(mockingbird::controls::PREFIX) && !super::group_enabled(mci.guild_id, "player") {
+        return mci.create_interaction_response(&ctx.http, |r| r
+            .kind(InteractionResponseType::ChannelMessageWithSource)
+            .interaction_response_data(|d| d.content("`player` is turned off in this server").ephemeral(true)))
+            .await;
+    }
+
+    #[cfg(all(feature = "mockingbird-core", any(feature = "mockingbird-ctrl", feature = "mockingbird-arl-cmd")))]
+    if let Some(result) = mockingbird::slash::component(ctx, mci).await {
+        return result;
+    }
+
+    tracing::warn!("unknown component {}", mci.data.custom_id);
+    Ok(())
+}
+
+/// The group an application command was registered by, for turning it off.
+pub(crate) fn slash_group(name: &str) -> Option<&'static str> {
+    static GROUPS: OnceLock<HashMap<String, &'static str>> = OnceLock::new();
+
+    let groups = GROUPS.get_or_init(|| {
+        let mut groups = HashMap::new();
+        for (group, register) in slash_commands() {
+            let mut cmds = CreateApplicationCommands::default();
+            register(&mut cmds);
+            for cmd in cmds.0 {
+                if let Some(name) = cmd.get("name").and_then(|x| x.as_str()) {
+                    groups.insert(name.to_string(), group);
+                }
+            }
+        }
+        groups
+    });
+    groups.get(name).copied()
+}
+
+async fn command(ctx: &Context, cmd: &ApplicationCommandInteraction) -> serenity::Result<()> {
+    if let Some(group) = slash_group(&cmd.data.name) {
+        if !super::group_enabled(cmd.guild_id, group) {
+            return respond(ctx, cmd, Err(format!("`{}` is turned off in this server", group)), true).await;
+        }
+    }
+
+    #[cfg(all(feature = "mockingbird-core", any(feature = "mockingbird-ctrl", feature = "mockingbird-arl-cmd")))]
+    if let Some(result) = mockingbird::slash::handle(ctx, cmd).await {
+        return result;
+    }
+
+    let (reply, ephemeral) = match cmd.data.name.as_str() {
+        #[cfg(feature = "basic-cmds")]
+        "version" => (Ok(Reply::Text(crate::VERSION.to_string())), false),
+
+        #[cfg(feature = "basic-cmds")]
+        "rev" => (Ok(Reply::Text(super::basic::rev_text())), false),
+
+        #[cfg(feature = "basic-cmds")]
+        "contribute" => (Ok(Reply::Embed(super::basic::contribute_embed())), false),
+
+        #[cfg(feature = "basic-cmds")]
+        "invite" => (super::basic::invite_link(&ctx.http).await.map(Reply::Text).map_err(|e| e.to_string()), false),
+
+        #[cfg(feature = "basic-cmds")]
+        "reboot" if !settings::privileges::is_owner(cmd.user.id.0) => {
+            (Err(denied(cmd, "Only bot owners can use this")), true)
+        }
+
+        #[cfg(feature = "basic-cmds")]
+        "reboot" => {
+            respond(ctx, cmd, Ok(Reply::Text("will kill all hu-".to_string())), false).await?;
+            tokio::spawn(crate::shutdown::shutdown(ctx.data.clone(), "reboot"));
+            return Ok(());
+        }
+
+        #[cfg(feature = "list-feature-cmd")]
+        "features" => features(cmd),
+
+        #[cfg(feature = "bookmark")]
+        "Bookmark" => (bookmark(ctx, cmd).await, true),
+
+        #[cfg(feature = "config-cmd")]
+        "config" => (config(cmd), true),
+
+        name => {
+            tracing::warn!("unknown application command /{}", name);
+            (Err("Unknown command".to_string()), true)
+        }
+    };
+
+    respond(ctx, cmd, reply, ephemeral).await
+}
+
+#[cfg(feature = "bookmark")]
+async fn bookmark(ctx: &Context, cmd: &ApplicationCommandInteraction) -> Result<Reply, String> {
+    let msg = cmd.data.resolved.messages
+        .values()
+        .next()
+        .ok_or_else(|| "Message not found".to_string())?;
+
+    super::bookmark::bookmark_message(ctx, cmd.user.id, cmd.guild_id, msg)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(Reply::Text("Bookmarked, check your DMs".to_string()))
+}
+
+/// `/features show` is public, turning groups on and off isn't.
+#[cfg(feature = "list-feature-cmd")]
+fn features(cmd: &ApplicationCommandInteraction) -> (Result<Reply, String>, bool) {
+    match cmd.data.options.first() {
+        Some(sub) if sub.name != "show" => (toggle_group(cmd, sub), true),
+        _ => (Ok(Reply::Embed(super::features::features_embed(cmd.guild_id))), false),
+    }
+}
+
+#[cfg(feature = "list-feature-cmd")]
+fn toggle_group(cmd: &ApplicationCommandInteraction, sub: &CommandDataOption) -> Result<Reply, String> {
+    let guild_id = cmd.guild_id.ok_or_else(|| "Only available in servers".to_string())?;
+    let allowed = cmd.member.as_ref()
+        .and_then(|m| m.permissions)
+        .map_or(false, |p| p.manage_guild());
+    if !allowed {
+        return Err(denied(cmd, "Requires the Manage Server permission"));
+    }
+
+    let group = sub.options.iter()
+        .find(|x| x.name == "group")
+        .and_then(|x| x.value.as_ref())
+        .and_then(|x| x.as_str())
+        .unwrap_or_default();
+    super::features::set_group(guild_id, group, sub.name == "enable").map(Reply::Text)
+}
+
+#[cfg(feature = "config-cmd")]
+fn config(cmd: &ApplicationCommandInteraction) -> Result<Reply, String> {
+    use super::config;
+
+    fn string(options: &[CommandDataOption], name: &str) -> String {
+        options.iter()
+            .find(|x| x.name == name)
+            .and_then(|x| x.value.as_ref())
+            .and_then(|x| x.as_str())
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    let guild_id = cmd.guild_id.ok_or_else(|| "Only available in servers".to_string())?;
+    let allowed = cmd.member.as_ref()
+        .and_then(|m| m.permissions)
+        .map_or(false, |p| p.manage_guild());
+    if !allowed {
+        return Err(denied(cmd, "Requires the Manage Server permission"));
+    }
+
+    let sub = cmd.data.options.first().ok_or_else(|| "Missing subcommand".to_string())?;
+    match sub.name.as_str() {
+        "show" => Ok(Reply::Embed(config::settings_embed(guild_id))),
+        "get" => config::get_setting(guild_id, &string(&sub.options, "key")).map(Reply::Text),
+        "set" => config::set_setting(guild_id, &string(&sub.options, "key"), &string(&sub.options, "value")).map(Reply::Text),
+        "reset" => config::reset_setting(guild_id, &string(&sub.options, "key")).map(Reply::Text),
+        name => Err(format!("Unknown subcommand {}", name)),
+    }
+}
+
+/// Audit-log that `cmd` was refused, and hand back `reason`.
+#[allow(dead_code)]
+fn denied(cmd: &ApplicationCommandInteraction, reason: &str) -> String {
+    let place = cmd.guild_id.map_or_else(|| "DM".to_string(), |x| x.to_string());
+    settings::privileges::denied(cmd.user.id.0, &cmd.user.tag(), &format!("/{}", cmd.data.name), &place, reason);
+    reason.to_string()
+}
+
+/// Errors are only shown to whoever ran the command.
+async fn respond(
+    ctx: &Context,
+    cmd: &ApplicationCommandInteraction,
+    reply: Result<Reply, String>,
+    ephemeral: bool,
+) -> serenity::Result<()>
+{
+    cmd.create_interaction_response(&ctx.http, |r| r
+        .kind(InteractionResponseType::ChannelMessageWithSource)
+        .interaction_response_data(|d| match reply {
+            Ok(Reply::Text(text)) => d.content(text).ephemeral(ephemeral),
+            Ok(Reply::Embed(embed)) => d.add_embed(embed).ephemeral(ephemeral),
+            Err(e) => d.content(format!("Error: {}", e)).ephemeral(true),
+        })
+    ).await
+}
@@ -1,16 +1,22 @@
+mod config;
 mod controllers;
+mod shutdown;
 
-use std::env;
+#[cfg(test)]
+mod testsuite;
+
+use std::{collections::HashSet, env, path::PathBuf, sync::OnceLock};
 use serenity::{
     http::Http,
     framework::standard::{
         StandardFramework,
         DispatchError,
+        Reason,
         macros::hook
     }
 };
 
-use serenity::model::channel::Message;
+use serenity::model::{channel::Message, id::{GuildId, UserId}};
 use serenity::prelude::*;
 use structopt::StructOpt;
 
@@ -30,6 +36,10 @@ pub fn get_rev() -> &'static str {
 pub mod EnvVars {
     pub const DISCORD_TOKEN: &'static str = "DISCORD_TOKEN";
     pub const CONFIG_FILE: &'static str = "CONFIG_FILE";
+    pub const SETTINGS_FILE: &'static str = settings::SETTINGS_FILE;
+    pub const PREFIX: &'static str = "COGGIEBOT_PREFIX";
+    pub const OWNERS: &'static str = "COGGIEBOT_OWNERS";
+    pub const INTENTS: &'static str = "COGGIEBOT_INTENTS";
 }
 
 #[derive(Debug, StructOpt)]
@@ -54,16 +64,120 @@ struct CLI {
 
     /// Access Token
     #[structopt(long = "token", env = EnvVars::DISCORD_TOKEN)]
-    token: String,
+    token: Option<String>,
+
+    /// TOML configuration file
+    #[structopt(long = "config", env = EnvVars::CONFIG_FILE, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Prefix for guilds that haven't chosen one
+    #[structopt(long = "prefix", env = EnvVars::PREFIX)]
+    prefix: Option<String>,
+
+    /// User allowed to use owner commands, may be repeated
+    #[structopt(long = "owner", env = EnvVars::OWNERS, use_delimiter = true)]
+    owners: Vec<u64>,
+
+    /// Gateway intents, e.g. non_privileged,message_content
+    #[structopt(long = "intents", env = EnvVars::INTENTS, use_delimiter = true)]
+    intents: Vec<String>,
+}
+
+static DEFAULT_PREFIX: OnceLock<String> = OnceLock::new();
+
+fn default_prefix() -> &'static str {
+    DEFAULT_PREFIX.get().map(String::as_str).unwrap_or(settings::DEFAULT_PREFIX)
+}
+
+/// The guild's prefix, or the default one.
+fn prefix_for(guild_id: Option<GuildId>) -> String {
+    guild_id
+        .and_then(|guild_id| settings::Settings::global().guild(guild_id.0).prefix)
+        .unwrap_or_else(|| default_prefix().to_string())
 }
 
 #[hook]
-async fn dispatch_error(_ctx: &Context, _msg: &Message, error: DispatchError, _command_name: &str) {
-    tracing::error!("Error: {:?}]", error);
+async fn guild_prefix(_ctx: &Context, msg: &Message) -> Option<String> {
+    Some(prefix_for(msg.guild_id))
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>>
+/// Refuse commands in groups the guild turned off.
+#[hook]
+async fn before(ctx: &Context, msg: &Message, _command_name: &str) -> bool {
+    controllers::allow_command(ctx, msg, &prefix_for(msg.guild_id)).await
+}
+
+/// The application's owner, or every member of its team.
+async fn application_owners(http: &Http) -> serenity::Result<HashSet<UserId>> {
+    let info = http.get_current_application_info().await?;
+    Ok(match info.team {
+        Some(team) => team.members.iter().map(|m| m.user.id).collect(),
+        None => HashSet::from([info.owner.id]),
+    })
+}
+
+#[hook]
+async fn dispatch_error(ctx: &Context, msg: &Message, error: DispatchError, command_name: &str) {
+    let reason = match &error {
+        DispatchError::OnlyForOwners => "Only bot owners can use this".to_string(),
+        DispatchError::LackingPermissions(perms) => format!("Requires {}", perms),
+        DispatchError::LackingRole => "You don't have the role for this".to_string(),
+        // checks audit-log their own denials
+        DispatchError::CheckFailed(_, Reason::User(reason)) => {
+            let _ = msg.channel_id.say(&ctx.http, reason).await;
+            return;
+        }
+        _ => {
+            tracing::error!("Error: {:?}]", error);
+            return;
+        }
+    };
+
+    let place = msg.guild_id.map_or_else(|| "DM".to_string(), |x| x.to_string());
+    settings::privileges::denied(msg.author.id.0, &msg.author.tag(), command_name, &place, &reason);
+    let _ = msg.channel_id.say(&ctx.http, reason).await;
+}
+
+/// Read the configuration file, let flags and environment variables
+/// override it, and export what mockingbird reads from the environment.
+fn startup_config(cli: CLI) -> Result<(config::Config, String, GatewayIntents), config::ConfigError> {
+    let mut config = match &cli.config {
+        Some(path) => config::Config::load(path)?,
+        None => config::Config::default(),
+    };
+
+    if cli.prefix.is_some() {
+        config.prefix = cli.prefix;
+    }
+    if !cli.owners.is_empty() {
+        config.owners = cli.owners;
+    }
+    if !cli.intents.is_empty() {
+        config.intents = Some(cli.intents);
+    }
+    if cli.token.is_some() {
+        config.token = cli.token;
+        config.token_file = None;
+    }
+
+    config.validate()?;
+    config.apply_env();
+
+    // the ARLs leave the environment before any child process is spawned,
+    // and before the runtime starts, since removing variables isn't thread safe
+    #[cfg(all(feature = "mockingbird-core", feature = "mockingbird-deemix"))]
+    mockingbird::arl::ArlPool::init(mockingbird::arl::take_env());
+
+    let token = config.token()?.ok_or(config::ConfigError::NoToken)?;
+    let intents = match &config.intents {
+        Some(names) => config::parse_intents(names).map_err(|e| config::ConfigError::Invalid(vec![e]))?,
+        None => GatewayIntents::non_privileged() | GatewayIntents::MESSAGE_CONTENT,
+    };
+
+    Ok((config, token, intents))
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>>
 {
     let cli = CLI::from_args();
     if cli.version {
@@ -91,29 +205,66 @@ async fn main() -> Result<(), Box<dyn std::error::Error>>
 
     tracing_subscriber::fmt::init();
 
-    let http = Http::new(&cli.token);
+    // before the runtime starts, setting the environment isn't thread safe
+    let (config, token, intents) = match startup_config(cli) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(config, token, intents))
+}
+
+async fn run(config: config::Config, token: String, intents: GatewayIntents) -> Result<(), Box<dyn std::error::Error>>
+{
+    if let Some(prefix) = config.prefix.clone() {
+        let _ = DEFAULT_PREFIX.set(prefix);
+    }
+
+    // load guild settings now, so a broken file is reported at startup
+    settings::Settings::global();
+
+    let http = Http::new(&token);
     let bot_id = http.get_current_user().await?.id;
 
+    let owners = match config.owners.is_empty() {
+        false => config.owners.iter().copied().map(UserId).collect(),
+        true => application_owners(&http).await?,
+    };
+    tracing::info!("owners: {:?}", owners);
+    settings::privileges::set_owners(owners.iter().map(|x| x.0));
+
     let framework = StandardFramework::new()
         .configure(|c| {
             c.with_whitespace(true)
                 .ignore_bots(true)
-                .prefix(".")
+                .prefix("")
+                .dynamic_prefix(guild_prefix)
                 .on_mention(Some(bot_id))
                 .delimiters(vec![", ", ","])
-                .owners(std::collections::HashSet::new())
+                .owners(owners)
         })
+        .before(before)
         .on_dispatch_error(dispatch_error);
 
     let framework = controllers::setup_framework(framework);
 
     let mut client = controllers::setup_state(
-        Client::builder(&cli.token, GatewayIntents::non_privileged() | GatewayIntents::MESSAGE_CONTENT)
+        Client::builder(&token, intents)
         .framework(framework)
         .event_handler(controllers::EvHandler))
         .await
         .await?;
 
+    client.data.write().await.insert::<shutdown::ShardManagerKey>(client.shard_manager.clone());
+    shutdown::listen(client.data.clone());
+
     client.start().await?;
-    unreachable!();
+    // the shards only stop when shutting down
+    std::process::exit(shutdown::EXIT_RESTART);
 }
@@ -0,0 +1,80 @@
+//! Graceful shutdown, for `reboot`, SIGTERM and SIGINT.
+//!
+//! Mockingbird announces the restart, saves the queues and leaves voice,
+//! then every shard disconnects and `main` exits with [`EXIT_RESTART`],
+//! which the systemd units restart on.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use serenity::client::bridge::gateway::ShardManager;
+use serenity::prelude::*;
+
+/// Exit code asking the service manager for a restart.
+pub const EXIT_RESTART: i32 = 3;
+
+pub struct ShardManagerKey;
+impl TypeMapKey for ShardManagerKey {
+    type Value = Arc<Mutex<ShardManager>>;
+}
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Only the first call does anything.
+pub async fn shutdown(data: Arc<RwLock<TypeMap>>, reason: &str) {
+    if SHUTTING_DOWN.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    tracing::warn!("shutting down: {}", reason);
+
+    #[cfg(feature = "mockingbird-core")]
+    mockingbird::shutdown(&data, reason).await;
+
+    #[cfg(feature = "prerelease")]
+    crate::controllers::prerelease::shutdown().await;
+
+    let shard_manager = data.read().await.get::<ShardManagerKey>().cloned();
+    match shard_manager {
+        Some(shard_manager) => shard_manager.lock().await.shutdown_all().await,
+        None => std::process::exit(EXIT_RESTART),
+    }
+}
+
+/// Shut down on SIGTERM or SIGINT.
+pub fn listen(data: Arc<RwLock<TypeMap>>) {
+    tokio::spawn(async move {
+        let reason = signal().await;
+        shutdown(data, reason).await;
+    });
+}
+
+#[cfg(unix)]
+async fn signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let (mut term, mut int) = match (signal(SignalKind::terminate()), signal(SignalKind::interrupt())) {
+        (Ok(term), Ok(int)) => (term, int),
+        (Err(e), _) | (_, Err(e)) => {
+            tracing::error!("couldn't listen for signals: {}", e);
+            return std::future::pending().await;
+        }
+    };
+
+    tokio::select! {
+        _ = term.recv() => "SIGTERM",
+        _ = int.recv() => "SIGINT",
+    }
+}
+
+#[cfg(not(unix))]
+async fn signal() -> &'static str {
+    match tokio::signal::ctrl_c().await {
+        Ok(()) => "Ctrl-C",
+        Err(e) => {
+            tracing::error!("couldn't listen for Ctrl-C: {}", e);
+            std::future::pending().await
+        }
+    }
+}
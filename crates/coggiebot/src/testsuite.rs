@@ -0,0 +1,193 @@
+use crate::config::{Config, ConfigError};
+use std::path::Path;
+
+fn parse(text: &str) -> Result<Config, ConfigError> {
+    Config::parse(Path::new("test.toml"), text)
+}
+
+fn problems(config: &Config) -> Vec<String> {
+    match config.validate() {
+        Ok(()) => Vec::new(),
+        Err(ConfigError::Invalid(problems)) => problems,
+        Err(e) => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn config_parses() {
+    let config = parse(r#"
+        token = "abc"
+        owners = [1, 2]
+        prefix = "!"
+        intents = ["non_privileged", "message_content"]
+
+        [paths]
+        cache = "/var/cache/coggiebot"
+
+        [mockingbird.timing]
+        abandoned_timeout = 720
+
+        [mockingbird.sources]
+        ytdl = ["youtube.com", "youtu.be"]
+    "#).unwrap();
+
+    assert_eq!(config.owners, vec![1, 2]);
+    assert_eq!(config.prefix.as_deref(), Some("!"));
+    assert_eq!(config.mockingbird.timing.abandoned_timeout, Some(720));
+    assert!(config.validate().is_ok());
+    assert_eq!(config.token().unwrap().as_deref(), Some("abc"));
+
+    assert!(parse("").unwrap().validate().is_ok());
+}
+
+#[test]
+fn config_rejects_unknown_keys() {
+    assert!(matches!(parse("tokne = \"abc\""), Err(ConfigError::Parse(..))));
+    assert!(matches!(parse("[mockingbird.timing]\nabandoned = 720"), Err(ConfigError::Parse(..))));
+    assert!(matches!(parse("[mockingbird.pipes]\nbuffer_secs = \"ten\""), Err(ConfigError::Parse(..))));
+}
+
+#[test]
+fn config_reports_every_problem() {
+    let config = parse(r#"
+        token = "abc"
+        token_file = "/nonexistent/coggiebot-token"
+        intents = ["guilds", "telepathy"]
+
+        [mockingbird.pipes]
+        prebuffer_secs = 5.0
+        buffer_secs = 2.0
+
+        [mockingbird.limits]
+        max_children = 0
+
+        [mockingbird.sources]
+        deemix_quality = "lossless"
+    "#).unwrap();
+
+    let problems = problems(&config);
+    for expected in [
+        "set token or token_file, not both",
+        "token_file /nonexistent/coggiebot-token doesn't exist",
+        "unknown intent \"telepathy\"",
+        "mockingbird.pipes.buffer_secs must be at least prebuffer_secs",
+        "mockingbird.limits.max_children must be above 0",
+    ] {
+        assert!(problems.iter().any(|x| x == expected), "{:?} not in {:?}", expected, problems);
+    }
+    assert!(problems.iter().any(|x| x.starts_with("mockingbird.sources.deemix_quality")));
+    assert_eq!(problems.len(), 6);
+}
+
+#[test]
+fn config_token_file() {
+    let path = std::env::temp_dir().join(format!("coggiebot-{}-token", std::process::id()));
+    std::fs::write(&path, "abc\n").unwrap();
+
+    let config = parse(&format!("token_file = {:?}", path.display().to_string())).unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(config.token().unwrap().as_deref(), Some("abc"));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn config_env_is_overridden() {
+    let config = parse(r#"
+        [paths]
+        cache = "/var/cache/coggiebot"
+
+        [mockingbird.timing]
+        abandoned_timeout = 720
+    "#).unwrap();
+
+    assert_eq!(config.env_overrides(|_| false), vec![
+        ("MKBIRD_CACHE_DIR", "/var/cache/coggiebot".to_string()),
+        ("MKBIRD_ABANDONED_TIMEOUT", "720".to_string()),
+    ]);
+    assert_eq!(
+        config.env_overrides(|var| var == "MKBIRD_CACHE_DIR"),
+        vec![("MKBIRD_ABANDONED_TIMEOUT", "720".to_string())]
+    );
+}
+
+#[test]
+#[cfg(feature = "prerelease")]
+fn prerelease_names() {
+    use crate::controllers::prerelease::{overlaps, safe_name};
+
+    for ok in ["main", "v0.4.1", "feature/slash-commands", "mockingbird-deemix", "list_feature_cmd"] {
+        assert!(safe_name(ok), "{:?} rejected", ok);
+    }
+    // options to git or cargo, paths out of the checkout, and anything a shell would read
+    for bad in ["", "-x", "--upload-pack=touch /tmp/x", "..", "../coggiebot", "main/../..", "a b", "a;b", "$(id)"] {
+        assert!(!safe_name(bad), "{:?} accepted", bad);
+    }
+
+    let here = [
+        ("basic-cmds".to_string(), true),
+        ("mockingbird-core".to_string(), true),
+        ("mockingbird-deemix".to_string(), false),
+    ];
+    let requested = ["mockingbird-core", "mockingbird-deemix", "prerelease"].map(str::to_string);
+    assert_eq!(overlaps(&requested, &here), vec!["mockingbird-core".to_string()]);
+    assert!(overlaps(&["mockingbird-deemix".to_string()], &here).is_empty());
+}
+
+#[test]
+fn command_words() {
+    use crate::controllers::command_word;
+
+    assert_eq!(command_word(".play https://deezer.com/track/1", "."), "play");
+    assert_eq!(command_word("  !!np", "!!"), "np");
+    assert_eq!(command_word("<@123> p https://deezer.com/track/1", "."), "p");
+    assert_eq!(command_word("<@!123>queue", "."), "queue");
+    assert_eq!(command_word("<@123>", "."), "");
+    assert_eq!(command_word("", "."), "");
+}
+
+#[test]
+fn command_groups() {
+    use crate::controllers::group_of;
+
+    assert_eq!(group_of("nope"), None);
+    #[cfg(feature = "basic-cmds")]
+    for name in ["version", "VERSION", "rev", "reboot"] {
+        assert_eq!(group_of(name), Some("basic"), "{}", name);
+    }
+    // groups with a prefix are found by it, not by their commands
+    #[cfg(feature = "config-cmd")]
+    {
+        assert_eq!(group_of("config"), Some("config"));
+        assert_eq!(group_of("show"), None);
+    }
+    #[cfg(feature = "prerelease")]
+    assert_eq!(group_of("pre-release"), Some("prerelease"));
+    #[cfg(all(feature = "mockingbird-core", feature = "mockingbird-ctrl"))]
+    for name in ["p", "q", "queue", "np", "now_playing", "skip"] {
+        assert_eq!(group_of(name), Some("player"), "{}", name);
+    }
+}
+
+#[test]
+fn slash_groups() {
+    use crate::controllers::slash::slash_group;
+
+    assert_eq!(slash_group("nope"), None);
+    #[cfg(feature = "basic-cmds")]
+    for name in ["version", "rev", "contribute", "invite", "reboot"] {
+        assert_eq!(slash_group(name), Some("basic"), "{}", name);
+    }
+    #[cfg(feature = "bookmark")]
+    assert_eq!(slash_group("Bookmark"), Some("bookmark"));
+    #[cfg(feature = "config-cmd")]
+    assert_eq!(slash_group("config"), Some("config"));
+    #[cfg(all(feature = "mockingbird-core", feature = "mockingbird-ctrl"))]
+    for name in ["join", "leave", "play", "now-playing", "skip", "shuffle", "player"] {
+        assert_eq!(slash_group(name), Some("player"), "{}", name);
+    }
+    #[cfg(all(feature = "mockingbird-core", feature = "mockingbird-arl-cmd"))]
+    for name in ["arl", "arl-raw"] {
+        assert_eq!(slash_group(name), Some("arl"), "{}", name);
+    }
+}
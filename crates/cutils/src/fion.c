@@ -1,39 +1,45 @@
 #define _GNU_SOURCE
 #include <fcntl.h>
 #include <sys/ioctl.h>
+#include <sys/uio.h>
 #include <unistd.h>
 
-int availbytes(int fd) {
-    int bytes_available = 0;
+/*
+ * All functions return -1 and leave errno set on failure.
+ */
 
-    if (!fd) {
-        return -1;
-    }
+int readable_bytes(int fd) {
+    int bytes_available = 0;
 
     if (ioctl(fd, FIONREAD, &bytes_available) == -1) {
-        return -2;
+        return -1;
     }
 
     return bytes_available;
 }
 
-int bigpipe(int fd, int size) {
-    // Get the current pipe size limit
-    int current_limit = 0;
-    
-    current_limit = fcntl(fd, F_GETPIPE_SZ);
-    if (current_limit > size) {
-        return 0;
-    }
+int pipe_capacity(int fd) {
+    return fcntl(fd, F_GETPIPE_SZ);
+}
 
-    if (fcntl(fd, F_SETPIPE_SZ, size) != 0) {
-        return -1;
-    }
-    current_limit = fcntl(fd, F_GETPIPE_SZ);
-    
-    if (current_limit != size) {
-        return -2;
-    }
+/* returns the capacity the kernel settled on, which may be rounded up */
+int set_pipe_capacity(int fd, int size) {
+    return fcntl(fd, F_SETPIPE_SZ, size);
+}
 
-    return 0;
-}
\ No newline at end of file
+int mkpipe(int fds[2]) {
+    return pipe2(fds, O_CLOEXEC);
+}
+
+ssize_t splice_fd(int fd_in, int fd_out, size_t len, unsigned int flags) {
+    return splice(fd_in, NULL, fd_out, NULL, len, flags);
+}
+
+ssize_t tee_fd(int fd_in, int fd_out, size_t len, unsigned int flags) {
+    return tee(fd_in, fd_out, len, flags);
+}
+
+ssize_t vmsplice_buf(int fd, const void *buf, size_t len, unsigned int flags) {
+    struct iovec iov = { (void *) buf, len };
+    return vmsplice(fd, &iov, 1, flags);
+}
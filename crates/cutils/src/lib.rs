@@ -1,12 +1,37 @@
-#[link(name = "fion")]
-extern {
-    pub fn availbytes(fd: std::ffi::c_int) -> std::ffi::c_int;
-    pub fn bigpipe(fd: std::ffi::c_int, size: std::ffi::c_int) -> std::ffi::c_int;
+//! Small helpers for working with linux pipes.
+
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+
+pub mod splice;
+
+mod ffi {
+    use std::ffi::{c_int, c_uint, c_void};
+
+    #[link(name = "fion")]
+    extern "C" {
+        pub fn readable_bytes(fd: c_int) -> c_int;
+        pub fn pipe_capacity(fd: c_int) -> c_int;
+        pub fn set_pipe_capacity(fd: c_int, size: c_int) -> c_int;
+        pub fn mkpipe(fds: *mut c_int) -> c_int;
+        pub fn splice_fd(fd_in: c_int, fd_out: c_int, len: usize, flags: c_uint) -> isize;
+        pub fn tee_fd(fd_in: c_int, fd_out: c_int, len: usize, flags: c_uint) -> isize;
+        pub fn vmsplice_buf(fd: c_int, buf: *const c_void, len: usize, flags: c_uint) -> isize;
+    }
 }
+
+#[cfg(test)]
+mod testsuite;
+
 #[derive(Debug)]
 pub enum PipeError {
+    /// The pipe holds more data than the requested capacity.
     ImmutableSize,
     InvalidSize,
+    /// The requested capacity is larger than `/proc/sys/fs/pipe-max-size`
+    /// and the process lacks `CAP_SYS_RESOURCE`.
+    ExceedsMax,
+    /// The file descriptor does not refer to a pipe.
+    NotAPipe,
     IOError(std::io::Error),
     ParseIntError(std::num::ParseIntError),
     NoMaxPipeSize,
@@ -17,6 +42,8 @@ impl std::fmt::Display for PipeError {
         match self {
             PipeError::ImmutableSize => write!(f, "Pipe size is immutable"),
             PipeError::InvalidSize => write!(f, "Invalid pipe size"),
+            PipeError::ExceedsMax => write!(f, "Pipe size exceeds pipe-max-size"),
+            PipeError::NotAPipe => write!(f, "Not a pipe"),
             PipeError::IOError(e) => write!(f, "IO error: {}", e),
             PipeError::ParseIntError(e) => write!(f, "Parse int error: {}", e),
             PipeError::NoMaxPipeSize => write!(f, "No max pipe size"),
@@ -24,6 +51,7 @@ impl std::fmt::Display for PipeError {
     }
 }
 
+impl std::error::Error for PipeError {}
 
 impl From<std::io::Error> for PipeError {
     fn from(err: std::io::Error) -> Self {
@@ -37,6 +65,107 @@ impl From<std::num::ParseIntError> for PipeError {
     }
 }
 
+/// Map a failed call's errno onto a [`PipeError`].
+fn last_error() -> PipeError {
+    let err = std::io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EBADF) => PipeError::NotAPipe,
+        Some(libc::EBUSY) => PipeError::ImmutableSize,
+        Some(libc::EPERM) => PipeError::ExceedsMax,
+        Some(libc::EINVAL) => PipeError::InvalidSize,
+        _ => PipeError::IOError(err),
+    }
+}
+
+/// Safe view over one end of a pipe.
+#[derive(Debug, Clone, Copy)]
+pub struct Pipe<'fd> {
+    fd: BorrowedFd<'fd>,
+}
+
+impl<'fd> Pipe<'fd> {
+    pub fn new<F: AsFd>(fd: &'fd F) -> Self {
+        Self { fd: fd.as_fd() }
+    }
+
+    /// Capacity of the pipe's kernel buffer in bytes.
+    pub fn capacity(&self) -> Result<usize, PipeError> {
+        match unsafe { ffi::pipe_capacity(self.fd.as_raw_fd()) } {
+            -1 => Err(last_error()),
+            n => Ok(n as usize),
+        }
+    }
+
+    /// Resize the pipe's kernel buffer.
+    /// Returns the new capacity, which the kernel may round up.
+    /// Never shrinks the pipe.
+    pub fn set_capacity(&self, size: usize) -> Result<usize, PipeError> {
+        let size = i32::try_from(size).map_err(|_| PipeError::InvalidSize)?;
+        let current = self.capacity()?;
+
+        if current >= size as usize {
+            return Ok(current);
+        }
+
+        match unsafe { ffi::set_pipe_capacity(self.fd.as_raw_fd(), size) } {
+            -1 => Err(last_error()),
+            n => Ok(n as usize),
+        }
+    }
+
+    /// Bytes that can be read without blocking.
+    pub fn readable(&self) -> Result<usize, PipeError> {
+        match unsafe { ffi::readable_bytes(self.fd.as_raw_fd()) } {
+            -1 => Err(last_error()),
+            n => Ok(n as usize),
+        }
+    }
+
+    /// Whether every write end has been closed. Data may still be
+    /// left to read.
+    pub fn hung_up(&self) -> Result<bool, PipeError> {
+        let mut fd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: 0,
+            revents: 0,
+        };
+        match unsafe { libc::poll(&mut fd, 1, 0) } {
+            -1 => Err(last_error()),
+            _ if fd.revents & libc::POLLNVAL != 0 => Err(PipeError::NotAPipe),
+            _ => Ok(fd.revents & libc::POLLHUP != 0),
+        }
+    }
+
+    /// Grow the pipe to `/proc/sys/fs/pipe-max-size`.
+    #[cfg(feature="stdio")]
+    pub fn grow(&self) -> Result<usize, PipeError> {
+        self.set_capacity(std_max_pipe_size()? as usize)
+    }
+
+    /// Grow the pipe to `/proc/sys/fs/pipe-max-size`.
+    #[cfg(feature="tokio")]
+    pub async fn grow_async(&self) -> Result<usize, PipeError> {
+        self.set_capacity(max_pipe_size().await? as usize)
+    }
+}
+
+/// Create a new pipe, returning `(read, write)` ends.
+pub fn pipe() -> Result<(OwnedFd, OwnedFd), PipeError> {
+    let mut fds = [-1; 2];
+    match unsafe { ffi::mkpipe(fds.as_mut_ptr()) } {
+        -1 => Err(PipeError::IOError(std::io::Error::last_os_error())),
+        _ => Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) }),
+    }
+}
+
+fn parse_max_pipe_size(buf: &str) -> Result<i32, PipeError> {
+    let data = buf.trim();
+    if data.is_empty() {
+        return Err(PipeError::NoMaxPipeSize);
+    }
+    Ok(data.parse::<i32>()?)
+}
+
 #[cfg(feature="tokio")]
 pub async fn max_pipe_size() -> Result<i32, PipeError> {
     use tokio::io::AsyncReadExt;
@@ -49,8 +178,7 @@ pub async fn max_pipe_size() -> Result<i32, PipeError> {
     let mut buf = String::new();
     file.read_to_string(&mut buf).await?; 
     
-    let data = buf.trim();
-    Ok(data.parse::<i32>()?)
+    parse_max_pipe_size(&buf)
 }
 
 #[cfg(feature="stdio")]
@@ -64,6 +192,5 @@ pub fn std_max_pipe_size() -> Result<i32, PipeError> {
     let mut buf = String::new();
     file.read_to_string(&mut buf)?; 
     
-    let data = buf.trim();
-    Ok(data.parse::<i32>()?)
-}
\ No newline at end of file
+    parse_max_pipe_size(&buf)
+}
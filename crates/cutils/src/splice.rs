@@ -0,0 +1,169 @@
+//! Zero-copy plumbing with `splice(2)`, `tee(2)` and `vmsplice(2)`.
+//!
+//! Data moved with these calls stays in the kernel's pipe buffers, so a
+//! stream can be forwarded or duplicated between processes without being
+//! copied through userspace. At least one side of `splice`, and both
+//! sides of `tee`, must be pipes.
+
+use std::os::fd::{AsFd, AsRawFd};
+use crate::{ffi, last_error, PipeError};
+
+/// Bytes requested per call by [`pump`] and [`tee_to_file`].
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// `SPLICE_F_*` flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Flags(u32);
+
+impl Flags {
+    pub const NONE: Flags = Flags(0);
+    /// Move pages instead of copying, a hint the kernel may ignore.
+    pub const MOVE: Flags = Flags(libc::SPLICE_F_MOVE);
+    /// Don't block on the pipes (the other file may still block).
+    pub const NONBLOCK: Flags = Flags(libc::SPLICE_F_NONBLOCK);
+    /// More data will follow, see `TCP_CORK`.
+    pub const MORE: Flags = Flags(libc::SPLICE_F_MORE);
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+}
+
+impl std::ops::BitOr for Flags {
+    type Output = Flags;
+
+    fn bitor(self, rhs: Flags) -> Flags {
+        Flags(self.0 | rhs.0)
+    }
+}
+
+fn check(ret: isize) -> Result<usize, PipeError> {
+    match ret {
+        -1 => Err(match last_error() {
+            // EINVAL here means neither side is a pipe, or the target
+            // doesn't support splicing
+            PipeError::InvalidSize => PipeError::NotAPipe,
+            e => e,
+        }),
+        n => Ok(n as usize),
+    }
+}
+
+/// Move up to `len` bytes from `input` to `output`.
+/// Returns 0 once `input` is a pipe with no writers left.
+pub fn splice<I: AsFd, O: AsFd>(input: &I, output: &O, len: usize, flags: Flags) -> Result<usize, PipeError> {
+    check(unsafe {
+        ffi::splice_fd(input.as_fd().as_raw_fd(), output.as_fd().as_raw_fd(), len, flags.bits())
+    })
+}
+
+/// Duplicate up to `len` bytes from pipe `input` into pipe `output`
+/// without consuming them from `input`.
+pub fn tee<I: AsFd, O: AsFd>(input: &I, output: &O, len: usize, flags: Flags) -> Result<usize, PipeError> {
+    check(unsafe {
+        ffi::tee_fd(input.as_fd().as_raw_fd(), output.as_fd().as_raw_fd(), len, flags.bits())
+    })
+}
+
+/// Map `buf` into the pipe `pipe`.
+///
+/// The kernel may reference `buf`'s pages instead of copying them, so
+/// the reader can observe changes made to `buf` before it is read.
+/// Only hand it memory that stays unchanged until the data is consumed.
+pub fn vmsplice<P: AsFd>(pipe: &P, buf: &[u8], flags: Flags) -> Result<usize, PipeError> {
+    check(unsafe {
+        ffi::vmsplice_buf(pipe.as_fd().as_raw_fd(), buf.as_ptr().cast(), buf.len(), flags.bits())
+    })
+}
+
+/// Write all of `buf` into the pipe `pipe`, see [`vmsplice`].
+pub fn vmsplice_all<P: AsFd>(pipe: &P, mut buf: &[u8]) -> Result<(), PipeError> {
+    while !buf.is_empty() {
+        let n = vmsplice(pipe, buf, Flags::NONE)?;
+        buf = &buf[n..];
+    }
+    Ok(())
+}
+
+/// Move `len` bytes, looping over short transfers.
+/// Fails with `UnexpectedEof` if `input` ends early.
+fn splice_exact<I: AsFd, O: AsFd>(input: &I, output: &O, mut len: usize) -> Result<(), PipeError> {
+    while len > 0 {
+        match splice(input, output, len, Flags::MOVE)? {
+            0 => return Err(PipeError::IOError(std::io::ErrorKind::UnexpectedEof.into())),
+            n => len -= n,
+        }
+    }
+    Ok(())
+}
+
+/// Forward `input` to `output` until `input` ends.
+/// Returns the number of bytes moved.
+pub fn pump<I: AsFd, O: AsFd>(input: &I, output: &O) -> Result<u64, PipeError> {
+    let mut total = 0;
+    loop {
+        match splice(input, output, CHUNK_SIZE, Flags::MOVE | Flags::MORE)? {
+            0 => return Ok(total),
+            n => total += n as u64,
+        }
+    }
+}
+
+/// Why [`tee_to_file`] stopped before `input` ended.
+#[derive(Debug)]
+pub struct TeeError {
+    pub error: PipeError,
+    /// Bytes forwarded to both `primary` and `copy`.
+    pub forwarded: u64,
+    /// Bytes at the front of `input` that already reached `primary`, but
+    /// not `copy`. [`skip`] them before forwarding `input` any further,
+    /// or `primary` gets them twice.
+    pub pending: usize,
+}
+
+impl std::fmt::Display for TeeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} (after {} bytes, {} pending)", self.error, self.forwarded, self.pending)
+    }
+}
+
+impl std::error::Error for TeeError {}
+
+/// Forward pipe `input` into pipe `primary` and duplicate every byte
+/// into `copy` (normally a file), until `input` ends.
+///
+/// Each chunk is `tee`d into `primary` first and then spliced (consumed)
+/// into `copy`. Returns the number of bytes forwarded.
+pub fn tee_to_file<I: AsFd, P: AsFd, C: AsFd>(input: &I, primary: &P, copy: &C) -> Result<u64, TeeError> {
+    let mut forwarded = 0;
+    loop {
+        let n = match tee(input, primary, CHUNK_SIZE, Flags::NONE) {
+            Ok(0) => return Ok(forwarded),
+            Ok(n) => n,
+            Err(error) => return Err(TeeError { error, forwarded, pending: 0 }),
+        };
+
+        let mut pending = n;
+        while pending > 0 {
+            match splice(input, copy, pending, Flags::MOVE) {
+                Ok(0) => return Err(TeeError {
+                    error: PipeError::IOError(std::io::ErrorKind::UnexpectedEof.into()),
+                    forwarded,
+                    pending,
+                }),
+                Ok(moved) => pending -= moved,
+                Err(error) => return Err(TeeError { error, forwarded, pending }),
+            }
+        }
+        forwarded += n as u64;
+    }
+}
+
+/// Discard the next `len` bytes of pipe `input`.
+pub fn skip<I: AsFd>(input: &I, len: usize) -> Result<(), PipeError> {
+    if len == 0 {
+        return Ok(());
+    }
+    let null = std::fs::OpenOptions::new().write(true).open("/dev/null")?;
+    splice_exact(input, &null, len)
+}
@@ -0,0 +1,205 @@
+use crate::{pipe, Pipe, PipeError};
+use std::io::Write;
+
+const PAGE: usize = 4096;
+
+#[test]
+fn capacity_of_new_pipe() {
+    let (rx, _tx) = pipe().unwrap();
+    // linux defaults to 16 pages
+    assert_eq!(Pipe::new(&rx).capacity().unwrap(), 16 * PAGE);
+}
+
+#[test]
+fn set_capacity_grows() {
+    let (rx, tx) = pipe().unwrap();
+    let size = Pipe::new(&tx).set_capacity(64 * PAGE).unwrap();
+
+    assert_eq!(size, 64 * PAGE);
+    // both ends share a buffer
+    assert_eq!(Pipe::new(&rx).capacity().unwrap(), 64 * PAGE);
+}
+
+#[test]
+fn set_capacity_never_shrinks() {
+    let (rx, _tx) = pipe().unwrap();
+    let pipe = Pipe::new(&rx);
+    pipe.set_capacity(32 * PAGE).unwrap();
+
+    assert_eq!(pipe.set_capacity(PAGE).unwrap(), 32 * PAGE);
+}
+
+#[test]
+fn set_capacity_too_large() {
+    let (rx, _tx) = pipe().unwrap();
+    let err = Pipe::new(&rx).set_capacity(usize::MAX).unwrap_err();
+    assert!(matches!(err, PipeError::InvalidSize));
+}
+
+#[test]
+fn hung_up_once_writer_closes() {
+    let (rx, mut tx) = pipe().map(|(rx, tx)| (rx, std::fs::File::from(tx))).unwrap();
+    let pipe = Pipe::new(&rx);
+    assert!(!pipe.hung_up().unwrap());
+
+    tx.write_all(b"left over").unwrap();
+    drop(tx);
+    assert!(pipe.hung_up().unwrap());
+    assert_eq!(pipe.readable().unwrap(), 9);
+}
+
+#[test]
+fn readable_bytes() {
+    let (rx, tx) = pipe().unwrap();
+    let mut tx = std::fs::File::from(tx);
+
+    assert_eq!(Pipe::new(&rx).readable().unwrap(), 0);
+    tx.write_all(&[0u8; 1234]).unwrap();
+    assert_eq!(Pipe::new(&rx).readable().unwrap(), 1234);
+}
+
+#[test]
+fn not_a_pipe() {
+    let file = std::fs::File::open("/proc/sys/fs/pipe-max-size").unwrap();
+    let err = Pipe::new(&file).capacity().unwrap_err();
+    assert!(matches!(err, PipeError::NotAPipe));
+}
+
+#[test]
+#[cfg(feature = "stdio")]
+fn grow_to_max() {
+    let (rx, _tx) = pipe().unwrap();
+    let max = crate::std_max_pipe_size().unwrap() as usize;
+
+    match Pipe::new(&rx).grow() {
+        Ok(size) => assert!(size >= max),
+        // unprivileged users may be capped by pipe-user-pages-soft
+        Err(PipeError::ExceedsMax) => {},
+        Err(e) => panic!("{}", e),
+    }
+}
+
+#[tokio::test]
+#[cfg(feature = "tokio")]
+async fn max_pipe_size_async() {
+    let max = crate::max_pipe_size().await.unwrap();
+    assert!(max as usize >= PAGE);
+}
+
+mod splice {
+    use crate::pipe;
+    use crate::splice::{pump, skip, splice, tee, tee_to_file, vmsplice_all, Flags};
+    use std::io::{Read, Write};
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn splice_between_pipes() {
+        let (a_rx, a_tx) = pipe().unwrap();
+        let (b_rx, b_tx) = pipe().unwrap();
+        std::fs::File::from(a_tx).write_all(b"hello").unwrap();
+
+        assert_eq!(splice(&a_rx, &b_tx, 64, Flags::MOVE).unwrap(), 5);
+        drop(b_tx);
+
+        let mut out = String::new();
+        std::fs::File::from(b_rx).read_to_string(&mut out).unwrap();
+        assert_eq!(out, "hello");
+    }
+
+    #[test]
+    fn tee_does_not_consume() {
+        let (a_rx, a_tx) = pipe().unwrap();
+        let (b_rx, b_tx) = pipe().unwrap();
+        std::fs::File::from(a_tx).write_all(b"hello").unwrap();
+
+        assert_eq!(tee(&a_rx, &b_tx, 64, Flags::NONE).unwrap(), 5);
+        drop(b_tx);
+
+        let mut teed = String::new();
+        std::fs::File::from(b_rx).read_to_string(&mut teed).unwrap();
+        let mut original = [0u8; 5];
+        std::fs::File::from(a_rx).read_exact(&mut original).unwrap();
+
+        assert_eq!(teed, "hello");
+        assert_eq!(&original, b"hello");
+    }
+
+    #[test]
+    fn splice_needs_a_pipe() {
+        let a = std::fs::File::open("/proc/self/status").unwrap();
+        let b = std::fs::OpenOptions::new().write(true).open("/dev/null").unwrap();
+        assert!(splice(&a, &b, 64, Flags::NONE).is_err());
+    }
+
+    #[test]
+    fn vmsplice_then_pump() {
+        let input = data(200_000);
+        let (a_rx, a_tx) = pipe().unwrap();
+        let (b_rx, b_tx) = pipe().unwrap();
+
+        let writer = {
+            let input = input.clone();
+            std::thread::spawn(move || vmsplice_all(&a_tx, &input).unwrap())
+        };
+        let pumper = std::thread::spawn(move || pump(&a_rx, &b_tx).unwrap());
+
+        let mut out = Vec::new();
+        std::fs::File::from(b_rx).read_to_end(&mut out).unwrap();
+        writer.join().unwrap();
+
+        assert_eq!(pumper.join().unwrap(), input.len() as u64);
+        assert_eq!(out, input);
+    }
+
+    #[test]
+    fn tee_into_pipe_and_file() {
+        let input = data(300_000);
+        let path = std::env::temp_dir().join(format!("cutils-tee-{}", std::process::id()));
+        let file = std::fs::File::create(&path).unwrap();
+
+        let (src_rx, src_tx) = pipe().unwrap();
+        let (dst_rx, dst_tx) = pipe().unwrap();
+
+        let writer = {
+            let input = input.clone();
+            std::thread::spawn(move || std::fs::File::from(src_tx).write_all(&input).unwrap())
+        };
+        let teer = std::thread::spawn(move || tee_to_file(&src_rx, &dst_tx, &file).unwrap());
+
+        let mut out = Vec::new();
+        std::fs::File::from(dst_rx).read_to_end(&mut out).unwrap();
+        writer.join().unwrap();
+
+        assert_eq!(teer.join().unwrap(), input.len() as u64);
+        assert_eq!(out, input);
+        assert_eq!(std::fs::read(&path).unwrap(), input);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn tee_to_failing_copy_leaves_nothing_twice() {
+        let (src_rx, src_tx) = pipe().unwrap();
+        let (dst_rx, dst_tx) = pipe().unwrap();
+        let mut src_tx = std::fs::File::from(src_tx);
+        src_tx.write_all(b"hello").unwrap();
+
+        // a copy that can't be written to
+        let copy = std::fs::File::open("/dev/null").unwrap();
+        let err = tee_to_file(&src_rx, &dst_tx, &copy).unwrap_err();
+        assert_eq!((err.forwarded, err.pending), (0, 5));
+
+        // what the fallback does: skip what primary already has, then pump
+        skip(&src_rx, err.pending).unwrap();
+        src_tx.write_all(b" world").unwrap();
+        drop(src_tx);
+        assert_eq!(pump(&src_rx, &dst_tx).unwrap(), 6);
+        drop(dst_tx);
+
+        let mut out = String::new();
+        std::fs::File::from(dst_rx).read_to_string(&mut out).unwrap();
+        assert_eq!(out, "hello world");
+    }
+}
@@ -0,0 +1,523 @@
+//! Pool of deezer ARLs.
+//!
+//! ARLs are read from `DEEMIX_ARL` and `DEEMIX_ARLS` (separated by commas
+//! or whitespace) by [`take_env`], which also removes them from the
+//! environment so child processes don't inherit them. Removing variables is
+//! only safe while the process has a single thread, so the bot does this
+//! before starting the runtime and hands them to [`ArlPool::init`], along
+//! with the ARLs in the [secret store](crate::secrets). `setarl` adds more at runtime and seals them into the
+//! store. Only `deemix-stream`/`deemix-metadata` are handed an ARL. Every
+//! `MKBIRD_ARL_CHECK_INTERVAL` seconds (default 3600) each one is validated
+//! with [`DeezerGateway::check_arl`]. An ARL is taken out of
+//! rotation when it is not premium, has expired, its license country is not
+//! in `MKBIRD_ARL_COUNTRIES` (if set), it is no longer logged in, or
+//! `MKBIRD_ARL_MAX_FAILURES` (default 2) streams in a row failed with it.
+//! The next health check puts it back if it passes. A check that can't
+//! reach deezer leaves the ARL as it was, and is retried within 5 minutes.
+//!
+//! Streams take ARLs round robin from the healthy ones, and a stream that
+//! fails is retried with the next.
+//!
+//! Checks also raise [`ArlAlert`]s (expiring within `MKBIRD_ARL_EXPIRY_DAYS`,
+//! lost premium or lossless, license country changed), which the
+//! [monitor](crate::monitor) delivers.
+
+use std::{
+    sync::{
+        Mutex,
+        OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serenity::{
+    framework::standard::{
+        macros::{command, group},
+        CommandResult,
+    },
+    model::channel::Message,
+    prelude::*,
+};
+
+use crate::check::{ARLError, DeezerGateway, ExtractChecks};
+use crate::deemix::StreamQuality;
+use crate::secrets::{SecretError, SecretStore};
+
+const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+const RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const DEFAULT_MAX_FAILURES: u32 = 2;
+const DEFAULT_EXPIRY_DAYS: i64 = 7;
+const DAY: i64 = 24 * 60 * 60;
+
+static POOL: OnceLock<ArlPool> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArlStatus {
+    /// Not checked yet, used optimistically.
+    Unchecked,
+    Healthy,
+    Unhealthy(String),
+}
+
+/// What the last health check found.
+#[derive(Debug, Clone)]
+pub struct ArlHealth {
+    pub premium: bool,
+    pub lossless: bool,
+    pub country: String,
+    pub expiration: Option<i64>,
+    pub quality: StreamQuality,
+}
+
+/// Something an owner should hear about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArlAlert {
+    Expiring { days: i64 },
+    Expired,
+    LostPremium,
+    LostLossless,
+    CountryChanged { from: String, to: String },
+}
+
+impl std::fmt::Display for ArlAlert {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ArlAlert::Expiring { days } => write!(f, "expires in {} day(s)", days),
+            ArlAlert::Expired => write!(f, "has expired"),
+            ArlAlert::LostPremium => write!(f, "is no longer premium"),
+            ArlAlert::LostLossless => write!(f, "can no longer stream lossless"),
+            ArlAlert::CountryChanged { from, to }
+                => write!(f, "license country changed from {} to {}", from, to),
+        }
+    }
+}
+
+impl From<&ExtractChecks> for ArlHealth {
+    fn from(check: &ExtractChecks) -> Self {
+        let mut quality = StreamQuality::from_sound_quality(&check.mobile_sq);
+        if check.lossless() {
+            quality = StreamQuality::Flac;
+        }
+
+        Self {
+            premium: check.premium(),
+            lossless: check.lossless(),
+            country: check.country.clone(),
+            expiration: check.expiration,
+            quality,
+        }
+    }
+}
+
+impl ArlHealth {
+    /// What got worse since the `old` check. Nothing is raised on the
+    /// first check, only on a known change.
+    pub fn changes_since(&self, old: Option<&ArlHealth>) -> Vec<ArlAlert> {
+        let mut alerts = Vec::new();
+        let old = match old {
+            Some(old) => old,
+            None => return alerts,
+        };
+
+        if old.premium && !self.premium {
+            alerts.push(ArlAlert::LostPremium);
+        }
+
+        if old.lossless && !self.lossless {
+            alerts.push(ArlAlert::LostLossless);
+        }
+
+        if old.country != self.country {
+            alerts.push(ArlAlert::CountryChanged {
+                from: old.country.clone(),
+                to: self.country.clone(),
+            });
+        }
+
+        alerts
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ArlEntry {
+    pub arl: String,
+    pub status: ArlStatus,
+    pub health: Option<ArlHealth>,
+    pub checked: Option<Instant>,
+    pub failures: u32,
+    /// Kept in the secret store, rather than the environment.
+    pub stored: bool,
+    /// Expiry was already alerted on, until the ARL is renewed.
+    expiry_alerted: bool,
+}
+
+impl ArlEntry {
+    fn new(arl: String, stored: bool) -> Self {
+        Self {
+            arl,
+            status: ArlStatus::Unchecked,
+            health: None,
+            checked: None,
+            failures: 0,
+            stored,
+            expiry_alerted: false,
+        }
+    }
+
+    pub fn usable(&self) -> bool {
+        !matches!(self.status, ArlStatus::Unhealthy(_))
+    }
+
+    /// Enough of the ARL to tell entries apart without leaking it.
+    pub fn masked(&self) -> String {
+        mask(&self.arl)
+    }
+}
+
+pub fn mask(arl: &str) -> String {
+    match arl.len() {
+        0..=12 => "*".repeat(arl.len()),
+        n => format!(
+            "{}…{}",
+            arl.get(..6).unwrap_or_default(),
+            arl.get(n - 4..).unwrap_or_default()
+        ),
+    }
+}
+
+/// Read the ARLs in `DEEMIX_ARL` and `DEEMIX_ARLS` and remove both from the
+/// environment. Only call this before any other thread is started.
+pub fn take_env() -> Vec<String> {
+    let mut arls = Vec::new();
+    for var in ["DEEMIX_ARL", "DEEMIX_ARLS"] {
+        let value = std::env::var(var).unwrap_or_default();
+        arls.extend(value
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|x| !x.is_empty())
+            .map(str::to_string));
+        std::env::remove_var(var);
+    }
+    arls
+}
+
+pub struct ArlPool {
+    entries: Mutex<Vec<ArlEntry>>,
+    next: AtomicUsize,
+    interval: Duration,
+    max_failures: u32,
+    countries: Vec<String>,
+    store: Option<SecretStore>,
+    expiry_days: i64,
+    alerts: Mutex<Vec<(String, ArlAlert)>>,
+    gateway: DeezerGateway,
+}
+
+fn now_unix() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+impl ArlPool {
+    fn from_env(arls: Vec<String>) -> Self {
+        let pool = Self {
+            entries: Mutex::new(Vec::new()),
+            next: AtomicUsize::new(0),
+            interval: std::env::var("MKBIRD_ARL_CHECK_INTERVAL")
+                .ok()
+                .and_then(|x| x.parse::<u64>().ok())
+                .filter(|x| *x > 0)
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_CHECK_INTERVAL),
+            max_failures: std::env::var("MKBIRD_ARL_MAX_FAILURES")
+                .ok()
+                .and_then(|x| x.parse::<u32>().ok())
+                .filter(|x| *x > 0)
+                .unwrap_or(DEFAULT_MAX_FAILURES),
+            countries: std::env::var("MKBIRD_ARL_COUNTRIES")
+                .unwrap_or_default()
+                .split(',')
+                .map(|x| x.trim().to_ascii_lowercase())
+                .filter(|x| !x.is_empty())
+                .collect(),
+            expiry_days: std::env::var("MKBIRD_ARL_EXPIRY_DAYS")
+                .ok()
+                .and_then(|x| x.parse::<i64>().ok())
+                .filter(|x| *x >= 0)
+                .unwrap_or(DEFAULT_EXPIRY_DAYS),
+            alerts: Mutex::new(Vec::new()),
+            gateway: DeezerGateway::from_env(),
+            store: SecretStore::from_env()
+                .map_err(|e| tracing::error!("secret store disabled: {}", e))
+                .ok()
+                .flatten(),
+        };
+
+        for arl in arls {
+            pool.insert(&arl, false);
+        }
+
+        match pool.store.as_ref().map(SecretStore::load) {
+            Some(Ok(arls)) => for arl in arls {
+                pool.insert(&arl, true);
+            },
+            Some(Err(e)) => tracing::error!("couldn't load stored ARLs: {}", e),
+            None => tracing::warn!("no secret key configured, ARLs added at runtime won't be kept"),
+        }
+
+        tracing::info!("ARL pool: {} ARL(s)", pool.len());
+        pool
+    }
+
+    /// Build the pool from `arls` (see [`take_env`]) and the secret store.
+    /// Does nothing if it was already built.
+    pub fn init(arls: Vec<String>) {
+        POOL.get_or_init(|| Self::from_env(arls));
+    }
+
+    /// The pool, with only the stored ARLs if [`ArlPool::init`] wasn't called.
+    pub fn global() -> &'static ArlPool {
+        POOL.get_or_init(|| Self::from_env(Vec::new()))
+    }
+
+    /// Returns false if `arl` is empty or already present.
+    fn insert(&self, arl: &str, stored: bool) -> bool {
+        let arl = arl.trim();
+        if arl.is_empty() {
+            return false;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.iter().any(|e| e.arl == arl) {
+            return false;
+        }
+
+        entries.push(ArlEntry::new(arl.to_string(), stored));
+        true
+    }
+
+    /// Whether ARLs added at runtime survive a restart.
+    pub fn persistent(&self) -> bool {
+        self.store.is_some()
+    }
+
+    /// Add `arl` to the pool and seal it into the secret store, if one
+    /// is configured. Returns false if it was already present.
+    pub fn add(&self, arl: &str) -> Result<bool, SecretError> {
+        if !self.insert(arl, true) {
+            return Ok(false);
+        }
+
+        if let Some(store) = &self.store {
+            let stored = self.entries()
+                .into_iter()
+                .filter(|e| e.stored)
+                .map(|e| e.arl)
+                .collect::<Vec<_>>();
+
+            store.save(&stored)?;
+        }
+
+        Ok(true)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn entries(&self) -> Vec<ArlEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    /// Next usable ARL, round robin.
+    pub fn next(&self) -> Option<ArlEntry> {
+        let entries = self.entries.lock().unwrap();
+        let usable = entries.iter().filter(|e| e.usable()).collect::<Vec<_>>();
+
+        match usable.len() {
+            0 => None,
+            n => Some(usable[self.next.fetch_add(1, Ordering::Relaxed) % n].clone()),
+        }
+    }
+
+    /// Usable ARL for one-off lookups, without advancing the rotation.
+    pub fn current(&self) -> Option<ArlEntry> {
+        self.entries.lock().unwrap()
+            .iter()
+            .find(|e| e.usable())
+            .cloned()
+    }
+
+    pub fn report_success(&self, arl: &str) {
+        if let Some(entry) = self.entries.lock().unwrap().iter_mut().find(|e| e.arl == arl) {
+            entry.failures = 0;
+        }
+    }
+
+    pub fn report_failure(&self, arl: &str, reason: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.iter_mut().find(|e| e.arl == arl) else { return };
+
+        entry.failures += 1;
+        if entry.failures >= self.max_failures && entry.usable() {
+            tracing::warn!("ARL {} taken out of rotation: {}", entry.masked(), reason);
+            entry.status = ArlStatus::Unhealthy(format!("{} failed streams: {}", entry.failures, reason));
+        }
+    }
+
+    /// Why `health` should be kept out of rotation, if it should.
+    fn verdict(&self, health: &ArlHealth) -> Option<String> {
+        if !health.premium {
+            return Some("not premium".to_string());
+        }
+
+        if health.expiration.map_or(false, |x| x < now_unix()) {
+            return Some("expired".to_string());
+        }
+
+        let country = health.country.to_ascii_lowercase();
+        if !self.countries.is_empty() && !self.countries.contains(&country) {
+            return Some(format!("license country {}", health.country));
+        }
+
+        None
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Alerts raised since the last call, with the masked ARL they're for.
+    pub fn take_alerts(&self) -> Vec<(String, ArlAlert)> {
+        std::mem::take(&mut *self.alerts.lock().unwrap())
+    }
+
+    /// Compare a check against the previous one.
+    fn alerts_for(&self, entry: &mut ArlEntry, health: &ArlHealth) -> Vec<ArlAlert> {
+        let mut alerts = Vec::new();
+
+        let left = health.expiration.map(|x| x - now_unix());
+        if let Some(left) = left.filter(|x| *x < self.expiry_days * DAY) {
+            if !entry.expiry_alerted {
+                alerts.push(match left {
+                    x if x <= 0 => ArlAlert::Expired,
+                    x => ArlAlert::Expiring { days: x / DAY },
+                });
+                entry.expiry_alerted = true;
+            }
+        } else {
+            entry.expiry_alerted = false;
+        }
+
+        alerts.extend(health.changes_since(entry.health.as_ref()));
+        alerts
+    }
+
+    /// How soon to check again after a check was inconclusive.
+    pub fn retry_interval(&self) -> Duration {
+        self.interval.min(RETRY_INTERVAL)
+    }
+
+    /// Validate every ARL in the pool. Returns `false` if any check was
+    /// inconclusive (a timeout, a network error, an unexpected response),
+    /// in which case that ARL keeps its previous status.
+    pub async fn check_all(&self) -> bool {
+        let arls = self.entries()
+            .into_iter()
+            .map(|e| e.arl)
+            .collect::<Vec<_>>();
+
+        let mut conclusive = true;
+        for arl in arls {
+            let check = self.gateway.check_arl(&arl).await;
+
+            let (status, health) = match check {
+                Ok(check) => {
+                    let health = ArlHealth::from(&check);
+                    match self.verdict(&health) {
+                        Some(reason) => (ArlStatus::Unhealthy(reason), Some(health)),
+                        None => (ArlStatus::Healthy, Some(health)),
+                    }
+                }
+                Err(e @ ARLError::NotLoggedIn) => (ArlStatus::Unhealthy(e.to_string()), None),
+                // deezer or the network had a blip, not the ARL's fault
+                Err(e) => {
+                    let masked = self.entries().into_iter().find(|x| x.arl == arl).map(|x| x.masked());
+                    tracing::warn!("couldn't check ARL {}: {}", masked.unwrap_or_default(), e);
+                    conclusive = false;
+                    continue;
+                }
+            };
+
+            let mut entries = self.entries.lock().unwrap();
+            if let Some(entry) = entries.iter_mut().find(|e| e.arl == arl) {
+                if entry.status != status {
+                    tracing::info!("ARL {}: {:?}", entry.masked(), status);
+                }
+                entry.status = status;
+                entry.failures = 0;
+                entry.checked = Some(Instant::now());
+
+                if let Some(health) = health {
+                    let alerts = self.alerts_for(entry, &health);
+                    let masked = entry.masked();
+                    self.alerts.lock().unwrap().extend(
+                        alerts.into_iter().map(|a| (masked.clone(), a))
+                    );
+                    entry.health = Some(health);
+                }
+            }
+        }
+
+        conclusive
+    }
+}
+
+#[group]
+#[commands(arlpool)]
+struct Pool;
+
+#[command]
+#[owners_only]
+#[aliases("arls")]
+async fn arlpool(ctx: &Context, msg: &Message) -> CommandResult {
+    let entries = ArlPool::global().entries();
+
+    if entries.is_empty() {
+        msg.channel_id.say(&ctx.http, "ARL pool is empty").await?;
+        return Ok(());
+    }
+
+    let lines = entries.iter()
+        .map(|e| {
+            let status = match &e.status {
+                ArlStatus::Unchecked => "unchecked".to_string(),
+                ArlStatus::Healthy => "healthy".to_string(),
+                ArlStatus::Unhealthy(reason) => format!("unhealthy ({})", reason),
+            };
+
+            let health = e.health.as_ref()
+                .map(|h| format!(
+                    " {} {} premium={} lossless={} expires={}",
+                    h.quality, h.country, h.premium, h.lossless,
+                    h.expiration.map(|x| x.to_string()).unwrap_or("unknown".to_string())
+                ))
+                .unwrap_or_default();
+
+            let checked = e.checked
+                .map(|t| format!(" checked {}s ago", t.elapsed().as_secs()))
+                .unwrap_or_default();
+
+            format!("{} {}{}{} failures={}", e.masked(), status, health, checked, e.failures)
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    msg.channel_id
+       .say(&ctx.http, format!("```\n{}\n```", lines))
+       .await?;
+
+    Ok(())
+}
@@ -0,0 +1,333 @@
+//! Process budget shared by every guild.
+//!
+//! Every track load spawns child processes (`deemix-stream` + `ffmpeg`,
+//! `yt-dlp` + `ffmpeg`, ...), and preloads spawn more. Without an upper
+//! bound a handful of guilds starting at once is enough to exhaust memory
+//! on a small host. Before spawning, a load requests a [`BudgetPermit`]
+//! covering the number of children it intends to run. Requests wait
+//! in FIFO order when the budget is exhausted, and the permit is released
+//! once the children have been dropped.
+//!
+//! Limits are read from the environment once, on first use:
+//!     - `MKBIRD_MAX_CHILDREN` total child processes (default 8)
+//!     - `MKBIRD_MAX_DEEMIX` concurrent deemix pipelines (default 3)
+//!     - `MKBIRD_MAX_YTDL` concurrent yt-dlp pipelines (default 3)
+//!     - `MKBIRD_MAX_HTTP` concurrent http-get decoders (default 3)
+//!     - `MKBIRD_MAX_METADATA` concurrent metadata lookups (default 2)
+
+use std::{
+    io::Read,
+    sync::{
+        Arc,
+        OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use tokio::sync::{Semaphore, OwnedSemaphorePermit, AcquireError};
+
+#[cfg(feature = "debug")]
+use serenity::{
+    framework::standard::{
+        macros::{command, group},
+        CommandResult,
+    },
+    model::channel::Message,
+    prelude::*,
+};
+
+static BUDGET: OnceLock<ProcessBudget> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Deemix,
+    Ytdl,
+    HttpGet,
+    Metadata,
+}
+
+impl Source {
+    const ALL: [Source; 4] = [Source::Deemix, Source::Ytdl, Source::HttpGet, Source::Metadata];
+
+    fn index(&self) -> usize {
+        match self {
+            Self::Deemix => 0,
+            Self::Ytdl => 1,
+            Self::HttpGet => 2,
+            Self::Metadata => 3,
+        }
+    }
+
+    fn envvar(&self) -> &'static str {
+        match self {
+            Self::Deemix => "MKBIRD_MAX_DEEMIX",
+            Self::Ytdl => "MKBIRD_MAX_YTDL",
+            Self::HttpGet => "MKBIRD_MAX_HTTP",
+            Self::Metadata => "MKBIRD_MAX_METADATA",
+        }
+    }
+
+    fn default_limit(&self) -> usize {
+        match self {
+            Self::Metadata => 2,
+            _ => 3,
+        }
+    }
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Deemix => write!(f, "deemix"),
+            Self::Ytdl => write!(f, "yt-dlp"),
+            Self::HttpGet => write!(f, "http-get"),
+            Self::Metadata => write!(f, "metadata"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BudgetError {
+    /// More children were requested than the budget can ever grant.
+    TooManyChildren { requested: usize, limit: usize },
+    Closed(AcquireError),
+}
+
+impl std::fmt::Display for BudgetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BudgetError::TooManyChildren { requested, limit }
+                => write!(f, "requested {} child processes, limit is {}", requested, limit),
+            BudgetError::Closed(e) => write!(f, "process budget closed: {}", e),
+        }
+    }
+}
+
+impl From<AcquireError> for BudgetError {
+    fn from(e: AcquireError) -> Self {
+        BudgetError::Closed(e)
+    }
+}
+
+impl std::error::Error for BudgetError {}
+
+#[derive(Debug, Clone)]
+pub struct SourceStats {
+    pub source: Source,
+    pub active: usize,
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct BudgetStats {
+    pub children: usize,
+    pub max_children: usize,
+    pub waiting: usize,
+    pub spawned: usize,
+    pub sources: Vec<SourceStats>,
+}
+
+pub struct ProcessBudget {
+    children: Arc<Semaphore>,
+    max_children: usize,
+    sources: [(Arc<Semaphore>, usize); 4],
+
+    live: AtomicUsize,
+    waiting: AtomicUsize,
+    spawned: AtomicUsize,
+}
+
+fn env_limit(var: &str, default: usize) -> usize {
+    std::env::var(var)
+        .ok()
+        .and_then(|x| x.parse::<usize>().ok())
+        .filter(|x| *x > 0)
+        .unwrap_or(default)
+}
+
+impl ProcessBudget {
+    /// A budget of `max_children` processes, and `limit(source)`
+    /// concurrent loads of each source.
+    pub fn new(max_children: usize, limit: impl Fn(Source) -> usize) -> Self {
+        Self {
+            children: Arc::new(Semaphore::new(max_children)),
+            max_children,
+            sources: Source::ALL.map(|s| {
+                let limit = limit(s);
+                (Arc::new(Semaphore::new(limit)), limit)
+            }),
+            live: AtomicUsize::new(0),
+            waiting: AtomicUsize::new(0),
+            spawned: AtomicUsize::new(0),
+        }
+    }
+
+    fn from_env() -> Self {
+        let budget = Self::new(
+            env_limit("MKBIRD_MAX_CHILDREN", 8),
+            |s| env_limit(s.envvar(), s.default_limit()),
+        );
+
+        tracing::info!(
+            "process budget: {} children [{}]",
+            budget.max_children,
+            Source::ALL.iter()
+                .map(|s| format!("{}={}", s, budget.sources[s.index()].1))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        budget
+    }
+
+    pub fn global() -> &'static ProcessBudget {
+        BUDGET.get_or_init(Self::from_env)
+    }
+
+    /// Wait for room to run `children` processes for `source`.
+    /// Requests are served in the order they arrive.
+    pub async fn acquire(&'static self, source: Source, children: usize) -> Result<BudgetPermit, BudgetError> {
+        if children > self.max_children {
+            return Err(BudgetError::TooManyChildren { requested: children, limit: self.max_children });
+        }
+
+        let waiting = Waiting::new(&self.waiting);
+        if self.children.available_permits() < children {
+            tracing::info!("process budget full, {} load(s) waiting", self.waiting.load(Ordering::SeqCst));
+        }
+
+        let permits = async {
+            let source_permit = self.sources[source.index()].0.clone().acquire_owned().await?;
+            let children_permit = self.children.clone().acquire_many_owned(children as u32).await?;
+            Ok::<_, AcquireError>((source_permit, children_permit))
+        }.await;
+
+        drop(waiting);
+        let (source_permit, children_permit) = permits?;
+
+        self.live.fetch_add(children, Ordering::SeqCst);
+        self.spawned.fetch_add(children, Ordering::SeqCst);
+        tracing::debug!("[{}] acquired {} child permit(s)", source, children);
+
+        Ok(BudgetPermit {
+            source,
+            children,
+            budget: self,
+            _source: source_permit,
+            _children: children_permit,
+        })
+    }
+
+    pub fn stats(&self) -> BudgetStats {
+        BudgetStats {
+            children: self.live.load(Ordering::SeqCst),
+            max_children: self.max_children,
+            waiting: self.waiting.load(Ordering::SeqCst),
+            spawned: self.spawned.load(Ordering::SeqCst),
+            sources: Source::ALL.iter()
+                .map(|s| {
+                    let (sem, limit) = &self.sources[s.index()];
+                    SourceStats {
+                        source: *s,
+                        active: limit - sem.available_permits(),
+                        limit: *limit,
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Counts a load as waiting until dropped, even if the load is cancelled.
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl<'a> Waiting<'a> {
+    fn new(count: &'a AtomicUsize) -> Self {
+        count.fetch_add(1, Ordering::SeqCst);
+        Self(count)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Proof that a load may run its children.
+/// Dropping it returns the slots to the budget.
+pub struct BudgetPermit {
+    source: Source,
+    children: usize,
+    budget: &'static ProcessBudget,
+    _source: OwnedSemaphorePermit,
+    _children: OwnedSemaphorePermit,
+}
+
+impl Drop for BudgetPermit {
+    fn drop(&mut self) {
+        self.budget.live.fetch_sub(self.children, Ordering::SeqCst);
+        tracing::debug!("[{}] released {} child permit(s)", self.source, self.children);
+    }
+}
+
+/// Holds a [`BudgetPermit`] for as long as the audio reader is alive,
+/// so the slots are only returned once songbird drops the track.
+pub struct Budgeted<R> {
+    inner: R,
+    _permit: BudgetPermit,
+}
+
+impl<R: Read> Budgeted<R> {
+    pub fn new(inner: R, permit: BudgetPermit) -> Self {
+        Self { inner, _permit: permit }
+    }
+}
+
+impl<R: Read> Read for Budgeted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(feature = "debug")]
+#[group]
+#[commands(procs)]
+struct Diagnostics;
+
+#[cfg(feature = "debug")]
+#[command]
+#[owners_only]
+#[aliases("children")]
+async fn procs(ctx: &Context, msg: &Message) -> CommandResult {
+    let stats = ProcessBudget::global().stats();
+
+    let sources = stats.sources.iter()
+        .map(|s| format!("{}: {}/{}", s.source, s.active, s.limit))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let pipelines = crate::supervisor::Supervisor::global()
+        .pipelines()
+        .iter()
+        .map(|p| format!("#{} {} {:?} [{}s]", p.id, p.label, p.pids, p.started.elapsed().as_secs()))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let loads = crate::prebuffer::LoadMetrics::global();
+
+    msg.channel_id
+       .say(
+            &ctx.http,
+            format!(
+                "```\nchildren: {}/{}\nwaiting: {}\nspawned: {}\n{}\n\nloads: {} (timeouts: {}, underruns: {})\nload time: mean {:.2}s, max {:.2}s\n\n{}\n```",
+                stats.children, stats.max_children, stats.waiting, stats.spawned, sources,
+                loads.loads(), loads.timeouts(), loads.underruns(),
+                loads.mean_load_time().as_secs_f64(), loads.max_load_time().as_secs_f64(),
+                pipelines
+            )
+       )
+       .await?;
+
+    Ok(())
+}
@@ -0,0 +1,237 @@
+//! Checking many ARLs at once.
+//!
+//! ARLs come from command arguments or attached `.txt` files, one or more
+//! per line. Checks run concurrently, at most `MKBIRD_ARL_BULK_CONCURRENCY`
+//! (default 4) in flight and starting no more than `MKBIRD_ARL_BULK_RATE`
+//! (default 4) a second, so an audit doesn't get the bot rate limited by
+//! Deezer. Results are summarised in an embed, with every check in a CSV
+//! or JSON report.
+
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::Utc;
+use serde_json::{json, Value};
+use serenity::{
+    builder::CreateEmbed,
+    model::channel::Attachment,
+};
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::check::{santitize_arl, ARLError, DeezerGateway, ExtractChecks};
+use crate::render::{CsvRenderer, Format, JsonRenderer, Render};
+
+/// Attachments larger than this aren't downloaded.
+pub const MAX_ATTACHMENT: u64 = 1 << 20;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub concurrency: usize,
+    pub per_second: f64,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self { concurrency: 4, per_second: 4.0 }
+    }
+}
+
+impl RateLimit {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            concurrency: std::env::var("MKBIRD_ARL_BULK_CONCURRENCY")
+                .ok()
+                .and_then(|x| x.parse::<usize>().ok())
+                .filter(|x| *x > 0)
+                .unwrap_or(default.concurrency),
+            per_second: std::env::var("MKBIRD_ARL_BULK_RATE")
+                .ok()
+                .and_then(|x| x.parse::<f64>().ok())
+                .filter(|x| *x > 0.0)
+                .unwrap_or(default.per_second),
+        }
+    }
+}
+
+/// ARLs found in `text`, in order and without duplicates,
+/// and the number of tokens that weren't ARLs.
+pub fn parse_arls(text: &str) -> (Vec<String>, usize) {
+    let mut arls: Vec<String> = Vec::new();
+    let mut invalid = 0;
+
+    for token in text.split(|c: char| c.is_whitespace() || c == ',' || c == ';') {
+        let token = token.trim();
+        if token.is_empty() {
+            continue;
+        }
+
+        match santitize_arl(token) {
+            Ok(()) if !arls.iter().any(|x| x == token) => arls.push(token.to_string()),
+            Ok(()) => {}
+            Err(()) => invalid += 1,
+        }
+    }
+
+    (arls, invalid)
+}
+
+/// Text of an attached ARL list.
+pub async fn read_attachment(attachment: &Attachment) -> Result<String, String> {
+    if !attachment.filename.to_ascii_lowercase().ends_with(".txt") {
+        return Err(format!("{} is not a .txt file", attachment.filename));
+    }
+
+    if attachment.size > MAX_ATTACHMENT {
+        return Err(format!("{} is larger than {} bytes", attachment.filename, MAX_ATTACHMENT));
+    }
+
+    let bytes = attachment.download()
+        .await
+        .map_err(|e| format!("couldn't download {}: {}", attachment.filename, e))?;
+
+    String::from_utf8(bytes)
+        .map_err(|_| format!("{} is not UTF-8", attachment.filename))
+}
+
+pub struct BulkResult {
+    pub arl: String,
+    pub check: Result<ExtractChecks, ARLError>,
+}
+
+/// Check every ARL in `arls` against `gateway`, in the order given.
+pub async fn check_many(gateway: &DeezerGateway, arls: Vec<String>, limit: RateLimit) -> Vec<BulkResult> {
+    let permits = Arc::new(Semaphore::new(limit.concurrency));
+    let mut ticker = tokio::time::interval(Duration::from_secs_f64(1.0 / limit.per_second));
+    let mut tasks = JoinSet::new();
+    let count = arls.len();
+
+    for (i, arl) in arls.into_iter().enumerate() {
+        ticker.tick().await;
+        let permit = permits.clone()
+            .acquire_owned()
+            .await
+            .expect("bulk check semaphore closed");
+
+        let gateway = gateway.clone();
+        tasks.spawn(async move {
+            let check = gateway.check_arl(&arl).await;
+            drop(permit);
+            (i, BulkResult { arl, check })
+        });
+    }
+
+    let mut results = Vec::with_capacity(count);
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok(x) => results.push(x),
+            Err(e) => tracing::error!("bulk ARL check panicked: {}", e),
+        }
+    }
+
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, x)| x).collect()
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub total: usize,
+    pub failed: usize,
+    pub premium: usize,
+    pub lossless: usize,
+    pub explicit: usize,
+    pub expired: usize,
+    pub countries: BTreeMap<String, usize>,
+}
+
+impl Summary {
+    pub fn new(results: &[BulkResult]) -> Self {
+        let now = Utc::now().timestamp();
+        let mut summary = Self { total: results.len(), ..Default::default() };
+
+        for result in results {
+            let check = match &result.check {
+                Ok(check) => check,
+                Err(_) => { summary.failed += 1; continue }
+            };
+
+            summary.premium += check.premium() as usize;
+            summary.lossless += check.lossless() as usize;
+            summary.explicit += check.explicit() as usize;
+            summary.expired += check.expiration.is_some_and(|x| x <= now) as usize;
+            *summary.countries.entry(check.country.to_ascii_uppercase()).or_default() += 1;
+        }
+
+        summary
+    }
+
+    pub fn embed(&self, invalid: usize) -> CreateEmbed {
+        let countries = match self.countries.is_empty() {
+            true => "none".to_string(),
+            false => self.countries.iter()
+                .map(|(country, n)| format!("{}: {}", country, n))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        };
+
+        let mut e = CreateEmbed::default();
+        e.title("ARL Bulk Check")
+            .description(format!(
+                "{} checked, {} failed{}",
+                self.total,
+                self.failed,
+                if invalid > 0 { format!(", {} invalid skipped", invalid) } else { String::new() }
+            ))
+            .fields(vec![
+                ("Premium", self.premium.to_string(), true),
+                ("Lossless", self.lossless.to_string(), true),
+                ("Explicit", self.explicit.to_string(), true),
+                ("Expired", self.expired.to_string(), true),
+                ("Countries", countries, false),
+            ]);
+        e
+    }
+}
+
+/// Every result as CSV, errors in the last column.
+pub fn csv_report(results: &[BulkResult]) -> String {
+    let columns = CsvRenderer::HEADER.split(',').count();
+    let mut out = format!("{},error\n", CsvRenderer::HEADER);
+
+    for result in results {
+        match &result.check {
+            Ok(check) => out.push_str(&format!("{},\n", CsvRenderer.row(&result.arl, check))),
+            Err(e) => out.push_str(&format!(
+                "{}{},{}\n",
+                CsvRenderer::escape(&result.arl),
+                ",".repeat(columns - 1),
+                CsvRenderer::escape(&e.to_string())
+            )),
+        }
+    }
+
+    out
+}
+
+pub fn json_report(results: &[BulkResult]) -> Value {
+    Value::Array(
+        results.iter()
+            .map(|result| match &result.check {
+                Ok(check) => JsonRenderer.render(&result.arl, check),
+                Err(e) => json!({ "arl": result.arl, "error": e.to_string() }),
+            })
+            .collect()
+    )
+}
+
+/// The report for `format`: JSON if asked for, CSV otherwise.
+/// Returns the file name and its contents.
+pub fn report(results: &[BulkResult], format: Format) -> serde_json::Result<(&'static str, String)> {
+    Ok(match format {
+        Format::Json => ("arl-report.json", serde_json::to_string_pretty(&json_report(results))?),
+        _ => ("arl-report.csv", csv_report(results)),
+    })
+}
@@ -1,8 +1,6 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use reqwest::{cookie::Jar, Url};
-use tokio::process::Command;
-use tokio::io::AsyncWriteExt;
 
 use serenity::framework::standard::{
     macros::{command, group},
@@ -12,12 +10,21 @@ use serenity::framework::standard::{
 use serenity::model::channel::Message;
 use serenity::prelude::*;
 
+const DEEZER_URL: &str = "https://www.deezer.com";
 const USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/112.0";
 
 #[derive(Debug)]
 pub enum ARLError {
     ParseError(serde_json::Error),
     HTTP(reqwest::Error),
+    /// Deezer answered with an error instead of user data.
+    Api(String),
+    /// The ARL isn't logged in, it's invalid or has expired.
+    NotLoggedIn,
+    /// A field the check needs is absent, e.g. `results.USER.OPTIONS`.
+    MissingField(&'static str),
+    /// A field is present but has an unexpected shape.
+    InvalidField { field: String, error: String },
 }
 
 impl From<serde_json::Error> for ARLError {
@@ -32,11 +39,24 @@ impl From<reqwest::Error> for ARLError {
     }
 }
 
+impl From<serde_path_to_error::Error<serde_json::Error>> for ARLError {
+    fn from(e: serde_path_to_error::Error<serde_json::Error>) -> Self {
+        Self::InvalidField {
+            field: e.path().to_string(),
+            error: e.inner().to_string(),
+        }
+    }
+}
+
 impl std::fmt::Display for ARLError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::ParseError(e) => write!(f, "Parse error: {}", e),
             Self::HTTP(e) => write!(f, "HTTP error: {}", e),
+            Self::Api(e) => write!(f, "Deezer error: {}", e),
+            Self::NotLoggedIn => write!(f, "ARL is not logged in (invalid or expired)"),
+            Self::MissingField(field) => write!(f, "Missing field: {}", field),
+            Self::InvalidField { field, error } => write!(f, "Invalid field {}: {}", field, error),
         }
     }
 }
@@ -45,22 +65,24 @@ impl std::error::Error for ARLError {}
 
 #[derive(Debug)]
 pub struct ExtractChecks {
+    pub user_id: i64,
     pub name: String,
-    pub email: String,
-    pub explicit: String,
-    pub offer_name: String,
+    pub email: Option<String>,
+    pub explicit: Option<String>,
+    pub offer_name: Option<String>,
     pub offer_id: i64,
     pub country: String,
-    pub expiration: i64,
-    pub inscription: String,
-    pub default_sound_quality: String,
+    pub expiration: Option<i64>,
+    pub inscription: Option<String>,
+    pub default_sound_quality: Option<String>,
     pub lossless: bool,
     pub mobile_sq: SoundQuality,
     pub tablet_sq: SoundQuality,
     pub web_sq: SoundQuality,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
 pub struct SoundQuality {
     pub high: bool,
     pub lossless: bool,
@@ -69,6 +91,98 @@ pub struct SoundQuality {
     pub standard: bool,
 }
 
+/// `deezer.getUserData` response, only the parts checks use.
+#[derive(Debug, Deserialize)]
+struct UserDataResponse {
+    #[serde(default)]
+    error: Value,
+    results: Option<UserDataResults>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserDataResults {
+    #[serde(rename = "USER")]
+    user: Option<User>,
+    #[serde(rename = "OFFER_ID", default, deserialize_with = "lenient_i64")]
+    offer_id: Option<i64>,
+    #[serde(rename = "OFFER_NAME")]
+    offer_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+struct User {
+    #[serde(default, deserialize_with = "lenient_i64")]
+    user_id: Option<i64>,
+    firstname: Option<String>,
+    blog_name: Option<String>,
+    email: Option<String>,
+    explicit_content_level: Option<String>,
+    inscription_date: Option<String>,
+    #[serde(default, deserialize_with = "object_or_empty")]
+    options: Option<UserOptions>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserOptions {
+    license_country: Option<String>,
+    #[serde(default, deserialize_with = "lenient_i64")]
+    expiration_timestamp: Option<i64>,
+    mobile_lossless: Option<bool>,
+    audio_quality_default_preset: Option<String>,
+    mobile_sound_quality: Option<SoundQuality>,
+    tablet_sound_quality: Option<SoundQuality>,
+    web_sound_quality: Option<SoundQuality>,
+}
+
+/// Deezer sends some numbers as strings.
+fn lenient_i64<'de, D>(de: D) -> Result<Option<i64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error;
+    match Value::deserialize(de)? {
+        Value::Null => Ok(None),
+        Value::Number(n) => n.as_i64()
+            .map(Some)
+            .ok_or_else(|| D::Error::custom(format!("{} is not an integer", n))),
+        Value::String(s) if s.trim().is_empty() => Ok(None),
+        Value::String(s) => s.trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| D::Error::custom(format!("{:?} is not an integer", s))),
+        x => Err(D::Error::custom(format!("expected an integer, got {}", x))),
+    }
+}
+
+/// PHP serializes an empty object as `[]`.
+fn object_or_empty<'de, D, T>(de: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::de::DeserializeOwned,
+{
+    use serde::de::Error;
+    match Value::deserialize(de)? {
+        Value::Null => Ok(None),
+        Value::Array(a) if a.is_empty() => Ok(None),
+        x => serde_json::from_value(x).map(Some).map_err(D::Error::custom),
+    }
+}
+
+/// Treat `""` like a missing value.
+fn non_empty(s: Option<String>) -> Option<String> {
+    s.map(|x| x.trim().to_string()).filter(|x| !x.is_empty())
+}
+
+fn api_error(error: &Value) -> Option<String> {
+    match error {
+        Value::Null => None,
+        Value::Array(a) if a.is_empty() => None,
+        Value::Object(o) if o.is_empty() => None,
+        e => Some(e.to_string()),
+    }
+}
+
 impl ExtractChecks {
     #[inline(always)]
     pub fn premium(&self) -> bool {
@@ -77,7 +191,8 @@ impl ExtractChecks {
 
     #[inline(always)]
     pub fn explicit(&self) -> bool {
-        self.explicit.to_lowercase() == "explicit_display"
+        self.explicit.as_deref()
+            .is_some_and(|x| x.eq_ignore_ascii_case("explicit_display"))
     }
 
     #[inline(always)]
@@ -86,53 +201,6 @@ impl ExtractChecks {
         // self.mobile_sq.lossless || self.tablet_sq.lossless || self.web_sq.lossless
     }
 
-    pub async fn tabulize(&self) -> Result<String, Box<dyn std::error::Error>> {
-        let mut child = Command::new("column")
-            .arg("-t")
-            .arg("-s")
-            .arg(",")
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .spawn()
-            .expect("failed to spawn command");
-
-        fn f(x: bool) -> char {
-            x.then(|| '✓').unwrap_or('✗')
-        }
-
-        child.stdin
-            .as_mut()
-            .expect("failed to get stdin")
-            .write_all(
-                format!(
-                    "Quality,Mobile,Tablet,Web\nReality,{},{},{}\nLossless,{},{},{}\nHigh,{},{},{}\nStandard,{},{},{}\nLow,{},{},{}\n",
-
-                    f(self.mobile_sq.reality),
-                    f(self.tablet_sq.reality),
-                    f(self.web_sq.reality),
-
-                    f(self.mobile_sq.lossless),
-                    f(self.tablet_sq.lossless),
-                    f(self.web_sq.lossless),
-
-                    f(self.mobile_sq.high),
-                    f(self.tablet_sq.high),
-                    f(self.web_sq.high),
-
-                    f(self.mobile_sq.standard),
-                    f(self.tablet_sq.standard),
-                    f(self.web_sq.standard),
-
-                    f(self.mobile_sq.low),
-                    f(self.tablet_sq.low),
-                    f(self.web_sq.low),
-               ).as_bytes()
-            )
-            .await
-            .expect("failed to write to stdin");
-        return Ok(String::from_utf8(child.wait_with_output().await?.stdout)?);
-    }
-
     #[inline(always)]
     pub fn dank(&self) -> bool {
         self.premium()
@@ -141,55 +209,132 @@ impl ExtractChecks {
     }
 }
 
-pub async fn get_arl_data(arl: &str) -> Result<Value, reqwest::Error> {
-    let url = "https://www.deezer.com".parse::<Url>().unwrap();
-    let jar = Jar::default();
-    jar.add_cookie_str(&format!("arl={}; Domain=deezer.com", arl), &url);
-    let resp = reqwest::Client::builder()
-        .cookie_store(true)
-        .cookie_provider(std::sync::Arc::new(jar))
-        .build()?
-        .post("https://www.deezer.com/ajax/gw-light.php?method=deezer.getUserData&input=3&api_version=1.0&api_token=&cid=433085605")
-        .header("Origin", "https://www.deezer.com")
-        .header("Referer", "https://www.deezer.com/us/")
-        .header("User-Agent", USER_AGENT)
-        .body("{}")
-        .send()
-        .await?;
+/// Client for the parts of Deezer's private API used by checks.
+///
+/// The base URL defaults to `https://www.deezer.com` and can be moved with
+/// `MKBIRD_DEEZER_URL`, e.g. to a local stand-in for tests.
+#[derive(Debug, Clone)]
+pub struct DeezerGateway {
+    base: Url,
+}
 
-    Ok(resp.json().await?)
+impl Default for DeezerGateway {
+    fn default() -> Self {
+        Self::new(DEEZER_URL.parse().unwrap())
+    }
 }
 
+impl DeezerGateway {
+    pub fn new(base: Url) -> Self {
+        Self { base }
+    }
 
-pub async fn check_arl(arl: &str) -> Result<ExtractChecks, ARLError> {
-    let data = get_arl_data(arl).await?;
+    pub fn from_env() -> Self {
+        std::env::var("MKBIRD_DEEZER_URL")
+            .ok()
+            .and_then(|x| x.parse::<Url>().ok())
+            .map(Self::new)
+            .unwrap_or_default()
+    }
+
+    pub fn base(&self) -> &Url {
+        &self.base
+    }
 
-    let mut name: String = String::from("Anonymous");
-    let firstname = data["results"]["USER"]["FIRSTNAME"].to_string().trim().to_string();
-    let blogname = data["results"]["USER"]["BLOG_NAME"].to_string().trim().to_string();
+    fn client(&self, arl: &str) -> Result<reqwest::Client, reqwest::Error> {
+        let jar = Jar::default();
+        jar.add_cookie_str(&format!("arl={}", arl.trim()), &self.base);
 
-    if !firstname.is_empty() {
-        name = firstname.to_string();
+        reqwest::Client::builder()
+            .cookie_store(true)
+            .cookie_provider(std::sync::Arc::new(jar))
+            .build()
     }
-    else if !blogname.is_empty() {
-        name = blogname.to_string();
+
+    /// Raw `deezer.getUserData` response for `arl`.
+    pub async fn user_data(&self, arl: &str) -> Result<Value, ARLError> {
+        let mut url = self.base.join("/ajax/gw-light.php").unwrap();
+        url.query_pairs_mut()
+            .append_pair("method", "deezer.getUserData")
+            .append_pair("input", "3")
+            .append_pair("api_version", "1.0")
+            .append_pair("api_token", "")
+            .append_pair("cid", "433085605");
+
+        let origin = self.base.origin().ascii_serialization();
+        let body = self.client(arl)?
+            .post(url)
+            .header("Origin", &origin)
+            .header("Referer", format!("{}/us/", origin))
+            .header("User-Agent", USER_AGENT)
+            .body("{}")
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        Ok(serde_json::from_str(&body)?)
     }
 
-    Ok(ExtractChecks {
-        name,
-        explicit: data["results"]["USER"]["EXPLICIT_CONTENT_LEVEL"].as_str().unwrap().to_string(),
-        offer_name: data["results"]["OFFER_NAME"].to_string(),
-        offer_id: data["results"]["OFFER_ID"].as_i64().unwrap(),
-        email: data["results"]["USER"]["EMAIL"].to_string(),
-        country: data["results"]["USER"]["OPTIONS"]["license_country"].as_str().unwrap().to_string(),
-        default_sound_quality: data["results"]["USER"]["OPTIONS"]["audio_quality_default_preset"].to_string(),
-        expiration: data["results"]["USER"]["OPTIONS"]["expiration_timestamp"].as_i64().unwrap(),
-        inscription: data["results"]["USER"]["INSCRIPTION_DATE"].to_string(),
-        lossless: data["results"]["USER"]["OPTIONS"]["mobile_lossless"].as_bool().unwrap(),
-        mobile_sq: serde_json::from_value::<SoundQuality>(data["results"]["USER"]["OPTIONS"]["mobile_sound_quality"].clone())?,
-        tablet_sq: serde_json::from_value::<SoundQuality>(data["results"]["USER"]["OPTIONS"]["tablet_sound_quality"].clone())?,
-        web_sq: serde_json::from_value::<SoundQuality>(data["results"]["USER"]["OPTIONS"]["web_sound_quality"].clone())?,
-      })
+    pub async fn check_arl(&self, arl: &str) -> Result<ExtractChecks, ARLError> {
+        ExtractChecks::from_user_data(self.user_data(arl).await?)
+    }
+}
+
+pub async fn get_arl_data(arl: &str) -> Result<Value, ARLError> {
+    DeezerGateway::from_env().user_data(arl).await
+}
+
+pub async fn check_arl(arl: &str) -> Result<ExtractChecks, ARLError> {
+    DeezerGateway::from_env().check_arl(arl).await
+}
+
+impl ExtractChecks {
+    /// Parse a `deezer.getUserData` response.
+    pub fn from_user_data(data: Value) -> Result<Self, ARLError> {
+        let response: UserDataResponse = serde_path_to_error::deserialize(data)?;
+
+        if let Some(e) = api_error(&response.error) {
+            return Err(ARLError::Api(e));
+        }
+
+        let results = response.results.ok_or(ARLError::MissingField("results"))?;
+        let user = results.user.ok_or(ARLError::MissingField("results.USER"))?;
+
+        let user_id = user.user_id.ok_or(ARLError::MissingField("results.USER.USER_ID"))?;
+        if user_id == 0 {
+            return Err(ARLError::NotLoggedIn);
+        }
+
+        let options = user.options.ok_or(ARLError::MissingField("results.USER.OPTIONS"))?;
+
+        let name = non_empty(user.firstname)
+            .or(non_empty(user.blog_name))
+            .unwrap_or("Anonymous".to_string());
+
+        Ok(ExtractChecks {
+            user_id,
+            name,
+            email: non_empty(user.email),
+            explicit: non_empty(user.explicit_content_level),
+            offer_name: non_empty(results.offer_name),
+            offer_id: results.offer_id
+                .ok_or(ARLError::MissingField("results.OFFER_ID"))?,
+            country: non_empty(options.license_country)
+                .ok_or(ARLError::MissingField("results.USER.OPTIONS.license_country"))?,
+            expiration: options.expiration_timestamp.filter(|x| *x > 0),
+            inscription: non_empty(user.inscription_date),
+            default_sound_quality: non_empty(options.audio_quality_default_preset),
+            lossless: options.mobile_lossless.unwrap_or(false),
+            mobile_sq: options.mobile_sound_quality
+                .ok_or(ARLError::MissingField("results.USER.OPTIONS.mobile_sound_quality"))?,
+            tablet_sq: options.tablet_sound_quality
+                .ok_or(ARLError::MissingField("results.USER.OPTIONS.tablet_sound_quality"))?,
+            web_sq: options.web_sound_quality
+                .ok_or(ARLError::MissingField("results.USER.OPTIONS.web_sound_quality"))?,
+        })
+    }
 }
 
 
@@ -204,6 +349,26 @@ pub fn santitize_arl(arl: &str) -> Result<(), ()> {
     Err(())
 }
 
+/// Deezer's response for `arl` as is, and the parsed check (or why it
+/// couldn't be parsed) as JSON. Returns `(file name, contents)` pairs.
+pub async fn raw_report(arl: &str) -> Result<Vec<(String, String)>, ARLError> {
+    use crate::render::{JsonRenderer, Render};
+
+    let data = get_arl_data(arl).await?;
+    let raw = serde_json::to_string_pretty(&data)?;
+
+    let parsed = match ExtractChecks::from_user_data(data) {
+        Ok(check) => serde_json::to_string_pretty(&JsonRenderer.render(arl, &check))?,
+        Err(e) => serde_json::to_string_pretty(&serde_json::json!({ "arl": arl, "error": e.to_string() }))?,
+    };
+
+    Ok(vec![
+        (format!("{}.json", arl), raw),
+        (format!("{}.check.json", arl), parsed),
+    ])
+}
+
+/// `arl-raw <arl>...`: Deezer's response as is, plus the parsed check as JSON.
 #[command("arl-raw")]
 #[cfg(feature = "arl-cmd")]
 async fn arl_raw(
@@ -219,19 +384,21 @@ async fn arl_raw(
             continue;
         }
 
-        let check = crate::check::get_arl_data(&arl).await?;
-
+        let files = raw_report(arl.trim()).await?;
         msg.channel_id.send_files(
             &ctx.http,
-            vec![
-                (serde_json::to_string_pretty(&check)?.as_bytes(), format!("{}.json", arl).as_str())
-            ],
+            files.iter().map(|(name, data)| (data.as_bytes(), name.as_str())),
             |m| m
         ).await?;
     }
     Ok(())
 }
 
+/// `arl [embed|md|json|csv] <arl>...`, embed by default.
+///
+/// With an attached `.txt` of ARLs, or more than [`BULK_THRESHOLD`]
+/// arguments, the ARLs are checked together and summarised, with a CSV
+/// report (JSON if asked for).
 #[command("arl")]
 #[cfg(feature = "arl-cmd")]
 pub async fn arl_check(
@@ -240,30 +407,27 @@ pub async fn arl_check(
     mut args: Args
 ) -> CommandResult
 {
-    use chrono::prelude::*;
+    use crate::render::Format;
+
+    let format = match args.parse::<Format>() {
+        Ok(format) => { args.advance(); format }
+        Err(_) => Format::Embed,
+    };
 
-    const BLANKSPACE: &'static str = "\x20"; // 0x20
-    const RED: u32    = 0x00FF0000;
-    const GREEN: u32  = 0x0000FF00;
-    const YELLOW: u32 = 0x00FFFF00;
+    if !msg.attachments.is_empty() || args.remaining() > BULK_THRESHOLD {
+        return arl_bulk(ctx, msg, args.rest(), format).await;
+    }
 
     let mut iargs = args.iter::<String>();
 
     while let Some(Ok(arl)) = iargs.next() {
         if let Err(()) = santitize_arl(&arl) {
-                msg.channel_id.say(&ctx.http, "Invalid ARL").await?;
+            msg.channel_id.say(&ctx.http, "Invalid ARL").await?;
             continue;
         }
 
         let arl = arl.trim();
-        if arl.len() != 192 {
-            msg.channel_id
-                    .say(&ctx.http, "Must provide an ARL")
-                    .await?;
-            continue
-        }
-
-        let check = match crate::check::check_arl(&arl).await {
+        let check = match crate::check::check_arl(arl).await {
             Ok(check) => check,
             Err(e) => {
                 msg.channel_id
@@ -273,54 +437,57 @@ pub async fn arl_check(
             }
         };
 
-        let is_usa = check.country.to_ascii_lowercase() == "us";
+        crate::render::send(&ctx.http, msg.channel_id, arl, &check, format).await?;
+    }
 
-        let color = if check.dank()
-          { GREEN }
-        else if check.lossless() && check.explicit()
-          { YELLOW }
-        else
-          { RED };
+    Ok(())
+}
+
+/// More ARL arguments than this are checked in bulk.
+#[cfg(feature = "arl-cmd")]
+pub const BULK_THRESHOLD: usize = 3;
 
-        fn checkmark(check: bool) -> &'static str {
-            if check { ":white_check_mark:" }
-            else { ":x:" }
+#[cfg(feature = "arl-cmd")]
+async fn arl_bulk(
+    ctx: &Context,
+    msg: &Message,
+    text: &str,
+    format: crate::render::Format,
+) -> CommandResult
+{
+    use crate::bulk::{self, RateLimit, Summary};
+
+    let mut text = text.to_string();
+    for attachment in &msg.attachments {
+        match bulk::read_attachment(attachment).await {
+            Ok(x) => { text.push('\n'); text.push_str(&x); }
+            Err(e) => { msg.channel_id.say(&ctx.http, format!("Skipped {}", e)).await?; }
         }
+    }
 
-        let explicit = checkmark(check.explicit());
-        let lossless = checkmark(check.lossless());
-        let country_checkmark = checkmark(is_usa);
-        let sound_quality_table = check.tabulize().await.expect("Failed to collect column -t");
-
-
-        let naive = NaiveDateTime::from_timestamp_opt(check.expiration, 0).unwrap();
-        let datetime: DateTime<Utc> = DateTime::from_utc(naive, Utc);
-        let expiredate = datetime.format("%Y-%m-%d %H:%M:%S");
-        let expire_checkmark = checkmark(datetime > Utc::now());
-
-        msg.channel_id
-           .send_message(&ctx.http, |m|
-                {
-                  let m =
-                    m.add_embed(|e|
-                        e.title("ARL Check")
-                            .color(color)
-                            .description(&arl)
-                            .fields(vec![
-                                (format!("Allows Explicit: {explicit}").as_str(), BLANKSPACE, false),
-                                (format!("Allows Lossless: {lossless}").as_str(), BLANKSPACE, false),
-                                (format!("Country: {} {}", check.country, country_checkmark).as_str(), BLANKSPACE, false),
-                                (format!("Inscription date: {}", check.inscription).as_str(), BLANKSPACE, false),
-                                (format!("Expiration: {expiredate} {expire_checkmark}").as_str(), BLANKSPACE, false),
-                                (format!("Email: {}", check.email).as_str(), BLANKSPACE, false),
-                                (format!("Offer {} ({})", check.offer_name, check.offer_id).as_str(), BLANKSPACE, false),
-                                (BLANKSPACE, &format!("```\n{}\n```", sound_quality_table), false),
-                            ])
-                            .footer(|f| f.text(format!("**Deezer uses Mobile API.**")))
-                        );
-                    m
-                }).await?;
+    let (arls, invalid) = bulk::parse_arls(&text);
+    if arls.is_empty() {
+        msg.channel_id.say(&ctx.http, "No valid ARLs found").await?;
+        return Ok(())
     }
 
+    let limit = RateLimit::from_env();
+    msg.channel_id
+        .say(&ctx.http, format!("Checking {} ARLs, {} at a time...", arls.len(), limit.concurrency))
+        .await?;
+
+    let typing = msg.channel_id.start_typing(&ctx.http);
+    let results = bulk::check_many(&DeezerGateway::from_env(), arls, limit).await;
+    if let Ok(typing) = typing { let _ = typing.stop(); }
+
+    let embed = Summary::new(&results).embed(invalid);
+    let (name, report) = bulk::report(&results, format)?;
+
+    msg.channel_id.send_files(
+        &ctx.http,
+        vec![(report.as_bytes(), name)],
+        |m| m.set_embed(embed)
+    ).await?;
+
     Ok(())
 }
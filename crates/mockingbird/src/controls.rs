@@ -0,0 +1,322 @@
+//! The player message: what is playing, and buttons to control it.
+//!
+//! It is posted where the bot was invited from when the first track starts,
+//! or wherever `player` is used, edited whenever a track starts or ends, and
+//! deleted by `leave_routine`. Presses are checked with
+//! [`can_control`](crate::player::can_control), like the player commands.
+
+use std::sync::{atomic::Ordering, Arc};
+
+use serenity::{
+    builder::{CreateActionRow, CreateComponents},
+    model::application::{
+        component::ButtonStyle,
+        interaction::{message_component::MessageComponentInteraction, InteractionResponseType},
+    },
+    model::id::{ChannelId, MessageId},
+    prelude::*,
+};
+use settings::MAX_VOLUME;
+use songbird::tracks::{LoopState, PlayMode};
+
+use crate::player::{self, QueueContext};
+
+/// Every button's custom id starts with this.
+pub const PREFIX: &str = "mkbird-player:";
+/// Volume change per press, in percent.
+pub const VOLUME_STEP: u8 = 10;
+
+/// Where a guild's player message is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ControlsMessage {
+    #[default]
+    Unposted,
+    Posted(ChannelId, MessageId),
+    /// The bot left, nothing may be posted anymore.
+    Removed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    Pause,
+    Skip,
+    Shuffle,
+    Loop,
+    Stop,
+    VolumeDown,
+    VolumeUp,
+}
+
+impl Control {
+    pub const ALL: [Control; 7] = [
+        Self::Pause, Self::Skip, Self::Shuffle, Self::Loop,
+        Self::Stop, Self::VolumeDown, Self::VolumeUp,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Pause => "pause",
+            Self::Skip => "skip",
+            Self::Shuffle => "shuffle",
+            Self::Loop => "loop",
+            Self::Stop => "stop",
+            Self::VolumeDown => "volume-down",
+            Self::VolumeUp => "volume-up",
+        }
+    }
+
+    pub fn custom_id(&self) -> String {
+        format!("{}{}", PREFIX, self.name())
+    }
+
+    pub fn from_custom_id(id: &str) -> Option<Self> {
+        let name = id.strip_prefix(PREFIX)?;
+        Self::ALL.into_iter().find(|x| x.name() == name)
+    }
+}
+
+/// What the player message shows.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlayerState {
+    pub title: Option<String>,
+    pub url: Option<String>,
+    pub paused: bool,
+    pub looping: bool,
+    /// In percent.
+    pub volume: u8,
+    pub queued: usize,
+}
+
+impl PlayerState {
+    pub async fn read(qctx: &QueueContext) -> Self {
+        let mut state = Self {
+            volume: qctx.volume.load(Ordering::Relaxed),
+            queued: qctx.cold_queue.read().await.len(),
+            ..Default::default()
+        };
+
+        let current = match qctx.manager.get(qctx.guild_id) {
+            Some(call) => call.lock().await.queue().current(),
+            None => return state,
+        };
+
+        if let Some(track) = current {
+            let metadata = track.metadata();
+            state.title = Some(player::metadata_title(metadata)
+                .or_else(|| metadata.source_url.clone())
+                .unwrap_or_else(|| "Unknown".to_string()));
+            state.url = metadata.source_url.clone();
+
+            if let Ok(info) = track.get_info().await {
+                state.paused = matches!(info.playing, PlayMode::Pause);
+                state.looping = matches!(info.loops, LoopState::Infinite);
+            }
+        }
+
+        state
+    }
+
+    pub fn content(&self) -> String {
+        let mut status = Vec::new();
+        if self.paused {
+            status.push("Paused".to_string());
+        }
+        if self.looping {
+            status.push("Looping".to_string());
+        }
+        status.push(format!("Volume {}%", self.volume));
+        status.push(format!("{} queued", self.queued));
+
+        let playing = match (&self.title, &self.url) {
+            (Some(title), Some(url)) => format!("**Now playing:** {}\n<{}>", title, url),
+            (Some(title), None) => format!("**Now playing:** {}", title),
+            _ => "Nothing is playing".to_string(),
+        };
+
+        format!("{}\n{}", playing, status.join(" · "))
+    }
+
+    pub fn components(&self) -> CreateComponents {
+        fn button(row: &mut CreateActionRow, control: Control, label: &str, style: ButtonStyle, disabled: bool) {
+            row.create_button(|b| b
+                .custom_id(control.custom_id())
+                .label(label)
+                .style(style)
+                .disabled(disabled));
+        }
+
+        let idle = self.title.is_none();
+        let mut components = CreateComponents::default();
+        components
+            .create_action_row(|r| {
+                button(r, Control::Pause, if self.paused { "Resume" } else { "Pause" }, ButtonStyle::Primary, idle);
+                button(r, Control::Skip, "Skip", ButtonStyle::Secondary, idle);
+                button(r, Control::Shuffle, "Shuffle", ButtonStyle::Secondary, self.queued == 0);
+                button(
+                    r, Control::Loop, "Loop",
+                    if self.looping { ButtonStyle::Success } else { ButtonStyle::Secondary },
+                    idle,
+                );
+                button(r, Control::Stop, "Stop", ButtonStyle::Danger, idle && self.queued == 0);
+                r
+            })
+            .create_action_row(|r| {
+                button(r, Control::VolumeDown, "Volume -", ButtonStyle::Secondary, self.volume == 0);
+                button(r, Control::VolumeUp, "Volume +", ButtonStyle::Secondary, self.volume >= MAX_VOLUME);
+                r
+            });
+        components
+    }
+}
+
+/// Edit the player message, posting it where the bot was invited from
+/// if it isn't yet.
+pub async fn show(qctx: &QueueContext) {
+    let state = PlayerState::read(qctx).await;
+    let mut message = qctx.controls.lock().await;
+
+    let result = match *message {
+        ControlsMessage::Removed => return,
+        ControlsMessage::Posted(channel, id) => channel
+            .edit_message(&qctx.http, id, |m| m.content(state.content()).set_components(state.components()))
+            .await
+            .map(|_| ()),
+        ControlsMessage::Unposted => qctx.invited_from
+            .send_message(&qctx.http, |m| m.content(state.content()).set_components(state.components()))
+            .await
+            .map(|m| *message = ControlsMessage::Posted(m.channel_id, m.id)),
+    };
+
+    if let Err(e) = result {
+        // most likely deleted, post a new one next time
+        tracing::warn!("couldn't update the player message: {}", e);
+        *message = ControlsMessage::Unposted;
+    }
+}
+
+/// Post the player message in `channel`, removing the old one.
+pub async fn post(qctx: &QueueContext, channel: ChannelId) -> serenity::Result<()> {
+    let state = PlayerState::read(qctx).await;
+    let mut message = qctx.controls.lock().await;
+
+    if let ControlsMessage::Posted(old_channel, id) = *message {
+        let _ = old_channel.delete_message(&qctx.http, id).await;
+    }
+
+    let posted = channel
+        .send_message(&qctx.http, |m| m.content(state.content()).set_components(state.components()))
+        .await?;
+    *message = ControlsMessage::Posted(posted.channel_id, posted.id);
+    Ok(())
+}
+
+/// Delete the player message for good.
+pub async fn remove(qctx: &QueueContext) {
+    let mut message = qctx.controls.lock().await;
+    if let ControlsMessage::Posted(channel, id) = *message {
+        if let Err(e) = channel.delete_message(&qctx.http, id).await {
+            tracing::warn!("couldn't delete the player message: {}", e);
+        }
+    }
+    *message = ControlsMessage::Removed;
+}
+
+/// Refreshes the player message when tracks start and end.
+pub(crate) struct ControlsRefresher(pub(crate) Arc<QueueContext>);
+
+#[serenity::async_trait]
+impl songbird::EventHandler for ControlsRefresher {
+    async fn act(&self, _ctx: &songbird::events::EventContext<'_>) -> Option<songbird::events::Event> {
+        // don't hold up songbird's events waiting on the call
+        let qctx = self.0.clone();
+        tokio::spawn(async move { show(&qctx).await });
+        None
+    }
+}
+
+/// Answer `mci` if it is a player button.
+pub async fn handle(ctx: &Context, mci: &MessageComponentInteraction) -> Option<serenity::Result<()>> {
+    let control = Control::from_custom_id(&mci.data.custom_id)?;
+    tracing::info!("[{}::{}] pressed {:?}", mci.user.id, mci.user.name, control);
+
+    let result = match player::can_control(ctx, mci.guild_id, mci.user.id).await {
+        Ok(guild_id) => apply(ctx, guild_id, control).await,
+        Err(e) => Err(e),
+    };
+
+    Some(match result {
+        Ok(qctx) => {
+            let state = PlayerState::read(&qctx).await;
+            mci.create_interaction_response(&ctx.http, |r| r
+                .kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|d| d
+                    .content(state.content())
+                    .set_components(state.components())))
+                .await
+        }
+        Err(e) => mci.create_interaction_response(&ctx.http, |r| r
+            .kind(InteractionResponseType::ChannelMessageWithSource)
+            .interaction_response_data(|d| d.content(e).ephemeral(true)))
+            .await,
+    })
+}
+
+async fn apply(ctx: &Context, guild_id: serenity::model::id::GuildId, control: Control) -> Result<Arc<QueueContext>, String> {
+    let qctx = player::queue_context(ctx, guild_id)
+        .await
+        .ok_or_else(|| "Not in a voice channel".to_string())?;
+
+    match control {
+        Control::Skip => { player::skip_tracks(ctx, guild_id, 1).await?; }
+        Control::Shuffle => { player::shuffle_queue(ctx, guild_id).await?; }
+        control => {
+            let call = qctx.manager
+                .get(guild_id)
+                .ok_or_else(|| "Not in a voice channel".to_string())?;
+            let call = call.lock().await;
+            let queue = call.queue();
+
+            match control {
+                Control::Stop => {
+                    qctx.cold_queue.write().await.clear();
+                    queue.stop();
+                }
+                Control::Pause => {
+                    let track = queue.current().ok_or_else(|| "Nothing is playing".to_string())?;
+                    let paused = track.get_info()
+                        .await
+                        .map(|x| matches!(x.playing, PlayMode::Pause))
+                        .unwrap_or(false);
+
+                    let result = if paused { queue.resume() } else { queue.pause() };
+                    result.map_err(|e| format!("Couldn't pause: {}", e))?;
+                }
+                Control::Loop => {
+                    let track = queue.current().ok_or_else(|| "Nothing is playing".to_string())?;
+                    let looping = track.get_info()
+                        .await
+                        .map(|x| matches!(x.loops, LoopState::Infinite))
+                        .unwrap_or(false);
+
+                    let result = if looping { track.disable_loop() } else { track.enable_loop() };
+                    result.map_err(|_| "This track can't loop".to_string())?;
+                }
+                Control::VolumeDown | Control::VolumeUp => {
+                    let volume = qctx.volume.load(Ordering::Relaxed);
+                    let volume = match control {
+                        Control::VolumeUp => volume.saturating_add(VOLUME_STEP).min(MAX_VOLUME),
+                        _ => volume.saturating_sub(VOLUME_STEP),
+                    };
+                    qctx.volume.store(volume, Ordering::Relaxed);
+
+                    for track in queue.current_queue() {
+                        let _ = track.set_volume(volume as f32 / 100.0);
+                    }
+                }
+                Control::Skip | Control::Shuffle => unreachable!(),
+            }
+        }
+    }
+
+    Ok(qctx)
+}
@@ -2,23 +2,158 @@ use std::io::{BufReader, BufRead, Read};
 use songbird::{
     constants::SAMPLE_RATE_RAW,
     input::{
-        children_to_reader,
         error::Error as SongbirdError,
         Codec,
         Container,
         Metadata,
         Input,
+        Reader,
         restartable::Restart
     },
 };
 use std::{
-    process::Stdio,
-    time::Duration
+    path::PathBuf,
+    process::{ChildStdin, ChildStdout, Stdio},
+    time::Duration,
 };
 use serde_json::Value;
-use std::os::fd::AsRawFd;
+use std::os::fd::AsFd;
 use tokio::io::AsyncReadExt;
-use cutils::{availbytes, bigpipe, max_pipe_size, PipeError};
+use cutils::{max_pipe_size, Pipe};
+use crate::budget::{BudgetError, ProcessBudget, Source};
+use crate::supervisor::Pipeline;
+use crate::prebuffer::{Prebuffered, PrebufferConfig};
+use crate::check::SoundQuality;
+use crate::arl::{ArlEntry, ArlPool};
+
+#[cfg(feature = "controller")]
+use serenity::{
+    framework::standard::{
+        macros::{command, group},
+        Args, CommandResult,
+    },
+    model::channel::Message,
+    prelude::*,
+};
+
+/// Deezer stream tiers, best first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StreamQuality {
+    Mp3_128,
+    Mp3_320,
+    Flac,
+}
+
+impl StreamQuality {
+    /// Value of `deemix-stream --quality`.
+    fn arg(&self) -> &'static str {
+        match self {
+            Self::Flac => "flac",
+            Self::Mp3_320 => "320",
+            Self::Mp3_128 => "128",
+        }
+    }
+
+    /// The next tier down, if any.
+    pub fn step_down(&self) -> Option<Self> {
+        match self {
+            Self::Flac => Some(Self::Mp3_320),
+            Self::Mp3_320 => Some(Self::Mp3_128),
+            Self::Mp3_128 => None,
+        }
+    }
+
+    /// Best tier an account's sound quality flags allow.
+    pub fn from_sound_quality(sq: &SoundQuality) -> Self {
+        if sq.lossless { Self::Flac }
+        else if sq.high { Self::Mp3_320 }
+        else { Self::Mp3_128 }
+    }
+
+    /// Parse deemix's `selectedFormat` (`FLAC`, `MP3_320`, ...).
+    fn from_format(format: &str) -> Option<Self> {
+        match format {
+            "FLAC" => Some(Self::Flac),
+            "MP3_320" => Some(Self::Mp3_320),
+            "MP3_128" => Some(Self::Mp3_128),
+            _ => None,
+        }
+    }
+
+    /// Highest tier the operator allows, from `MKBIRD_DEEMIX_QUALITY`.
+    pub fn operator_max() -> Self {
+        std::env::var("MKBIRD_DEEMIX_QUALITY")
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(Self::Flac)
+    }
+}
+
+impl std::str::FromStr for StreamQuality {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "flac" | "lossless" => Ok(Self::Flac),
+            "320" | "mp3_320" | "high" => Ok(Self::Mp3_320),
+            "128" | "mp3_128" | "standard" => Ok(Self::Mp3_128),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for StreamQuality {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Flac => write!(f, "FLAC"),
+            Self::Mp3_320 => write!(f, "MP3 320"),
+            Self::Mp3_128 => write!(f, "MP3 128"),
+        }
+    }
+}
+
+impl StreamQuality {
+    /// How it's kept in the guild's settings.
+    fn setting(&self) -> &'static str {
+        match self {
+            Self::Flac => "flac",
+            Self::Mp3_320 => "320",
+            Self::Mp3_128 => "128",
+        }
+    }
+}
+
+/// Quality a guild asked for, if it chose one.
+pub fn guild_quality(guild_id: u64) -> Option<StreamQuality> {
+    settings::Settings::global()
+        .guild(guild_id)
+        .quality
+        .and_then(|x| x.parse().ok())
+}
+
+/// Saved in the settings file, so it outlives a restart.
+pub fn set_guild_quality(guild_id: u64, quality: Option<StreamQuality>) -> Result<(), settings::SettingsError> {
+    settings::Settings::global().update(guild_id, |x| {
+        x.quality = quality.map(|q| q.setting().to_string());
+        Ok(())
+    })
+}
+
+/// Best tier to try first for `guild_id` with `arl`: the lowest of what
+/// the operator, the guild, and the ARL's account allow.
+pub fn preferred_quality(guild_id: u64, arl: Option<&ArlEntry>) -> StreamQuality {
+    let mut quality = StreamQuality::operator_max();
+
+    if let Some(guild) = guild_quality(guild_id) {
+        quality = quality.min(guild);
+    }
+
+    if let Some(health) = arl.and_then(|e| e.health.as_ref()) {
+        quality = quality.min(health.quality);
+    }
+
+    quality
+}
 
 #[derive(Debug)]
 pub enum DeemixError {
@@ -28,6 +163,21 @@ pub enum DeemixError {
     ParseInt(core::num::ParseIntError),
     Songbird(SongbirdError),
     Tokio(tokio::task::JoinError),
+    Budget(BudgetError),
+    /// Every ARL in the pool is out of rotation.
+    NoArl,
+}
+
+impl DeemixError {
+    /// Whether the failure is the ARL's fault (login, license, region)
+    /// rather than the track's or the tier's.
+    fn is_arl_failure(&self) -> bool {
+        const ARL_ERRORS: [&str; 4] = ["Invalid ARL", "notLoggedIn", "wrongLicense", "wrongGeolocation"];
+        match self {
+            DeemixError::BadJson(text) => ARL_ERRORS.iter().any(|e| text.contains(e)),
+            _ => false,
+        }
+    }
 }
 
 impl Into<SongbirdError> for DeemixError {
@@ -44,6 +194,14 @@ impl Into<SongbirdError> for DeemixError {
             => SongbirdError::Io(
                 std::io::Error::new(std::io::ErrorKind::Other, e)
             ),
+            DeemixError::Budget(e)
+            => SongbirdError::Io(
+                std::io::Error::new(std::io::ErrorKind::Other, e)
+            ),
+            DeemixError::NoArl
+            => SongbirdError::Io(
+                std::io::Error::new(std::io::ErrorKind::Other, "no healthy ARL")
+            ),
         }
     }
 }
@@ -57,6 +215,8 @@ impl std::fmt::Display for DeemixError {
             DeemixError::ParseInt(e) => write!(f, "Parse int error: {}", e),
             DeemixError::Songbird(e) => write!(f, "Songbird error: {}", e),
             DeemixError::Tokio(e) => write!(f, "Tokio error: {}", e),
+            DeemixError::Budget(e) => write!(f, "Budget error: {}", e),
+            DeemixError::NoArl => write!(f, "No healthy ARL available"),
         }
     }
 }
@@ -79,6 +239,12 @@ impl From<tokio::task::JoinError> for DeemixError {
     }
 }
 
+impl From<BudgetError> for DeemixError {
+    fn from(e: BudgetError) -> Self {
+        DeemixError::Budget(e)
+    }
+}
+
 impl From<core::num::ParseIntError> for DeemixError {
     fn from(e: core::num::ParseIntError) -> Self {
         DeemixError::ParseInt(e)
@@ -89,6 +255,8 @@ impl std::error::Error for DeemixError {}
 
 struct DeemixRestarter<P> {
     uri: P,
+    quality: StreamQuality,
+    arl: String,
 }
 
 #[serenity::async_trait]
@@ -99,12 +267,14 @@ where
     async fn call_restart(&mut self, time: Option<Duration>) -> Result<Input, SongbirdError> {
         if let Some(time) = time {
             let ts = format!("{:.3}", time.as_secs_f64());
-            _deemix(self.uri.as_ref(), &["-ss", &ts])
+            _deemix(self.uri.as_ref(), &["-ss", &ts], self.quality, &self.arl)
                 .await
+                .map(|(input, _)| input)
                 .map_err(DeemixError::into)
         } else {
-            deemix(self.uri.as_ref())
+            _deemix(self.uri.as_ref(), &[], self.quality, &self.arl)
                 .await
+                .map(|(input, _)| input)
                 .map_err(DeemixError::into)
         }
     }
@@ -115,7 +285,22 @@ where
 }
 
 pub async fn deemix_metadata(uri: &str) -> std::io::Result<Metadata> {
-    let deemix = tokio::process::Command::new("deemix-metadata")
+    Ok(metadata_from_deemix_output(&deemix_track_info(uri).await?))
+}
+
+/// Deezer's track object for `uri`, as printed by `deemix-metadata`.
+pub async fn deemix_track_info(uri: &str) -> std::io::Result<Value> {
+    let _permit = ProcessBudget::global()
+        .acquire(Source::Metadata, 1)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    let mut cmd = tokio::process::Command::new("deemix-metadata");
+    if let Some(entry) = ArlPool::global().current() {
+        cmd.env("DEEMIX_ARL", entry.arl);
+    }
+
+    let deemix = cmd
         .arg(uri.trim())
         .stdin(Stdio::null())
         .stdout(Stdio::piped())
@@ -124,7 +309,49 @@ pub async fn deemix_metadata(uri: &str) -> std::io::Result<Metadata> {
 
     let output = deemix.wait_with_output().await?;
     
-    Ok(metadata_from_deemix_output(&serde_json::from_slice(&output.stdout[..])?))
+    Ok(serde_json::from_slice(&output.stdout[..])?)
+}
+
+/// The deezer track id in a track link, e.g. `https://www.deezer.com/en/track/3135556`.
+pub fn track_id(uri: &str) -> Option<u64> {
+    let url = reqwest::Url::parse(uri.trim()).ok()?;
+    let mut segments = url.path_segments()?;
+    segments.find(|x| *x == "track")?;
+    segments.next()?.parse().ok()
+}
+
+/// Deezer's public track object for `uri`, from `api.deezer.com` (moved
+/// with `MKBIRD_DEEZER_API_URL`). Unlike [`deemix_track_info`] it needs no
+/// ARL, so it still works when every ARL is bad. Short links are followed
+/// to find the track id.
+pub async fn public_track_info(uri: &str) -> std::io::Result<Value> {
+    let other = |e: reqwest::Error| std::io::Error::new(std::io::ErrorKind::Other, e);
+
+    let id = match track_id(uri) {
+        Some(id) => id,
+        None => {
+            let resolved = reqwest::get(uri.trim()).await.map_err(other)?;
+            track_id(resolved.url().as_str()).ok_or_else(|| std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} isn't a deezer track", uri.trim()),
+            ))?
+        }
+    };
+
+    let base = std::env::var("MKBIRD_DEEZER_API_URL")
+        .unwrap_or_else(|_| "https://api.deezer.com".to_string());
+    let value = reqwest::get(format!("{}/track/{}", base.trim_end_matches('/'), id))
+        .await
+        .and_then(|x| x.error_for_status())
+        .map_err(other)?
+        .json::<Value>()
+        .await
+        .map_err(other)?;
+
+    match value.get("error") {
+        Some(e) => Err(std::io::Error::new(std::io::ErrorKind::NotFound, e.to_string())),
+        None => Ok(value),
+    }
 }
 
 fn process_stderr(s: &mut std::process::ChildStderr) -> Result<Value, DeemixError> {
@@ -159,19 +386,160 @@ fn process_stderr(s: &mut std::process::ChildStderr) -> Result<Value, DeemixErro
 }
 
 
+/// Size of the `balloon proxy` buffer inserted between deemix-stream
+/// and ffmpeg, from `MKBIRD_BALLOON`. Unset or empty disables it.
+fn balloon_size() -> Option<String> {
+    std::env::var("MKBIRD_BALLOON")
+        .ok()
+        .filter(|x| !x.trim().is_empty())
+}
+
+/// Where a track's encoded stream is cached, under `MKBIRD_CACHE_DIR`.
+/// Unset or empty disables caching.
+fn cache_path(metadata: &Value) -> Option<PathBuf> {
+    let dir = std::env::var("MKBIRD_CACHE_DIR")
+        .ok()
+        .filter(|x| !x.trim().is_empty())?;
+
+    let id = match &metadata["id"] {
+        Value::Number(n) => n.to_string(),
+        Value::String(s) if s.chars().all(|c| c.is_ascii_alphanumeric()) => s.clone(),
+        _ => return None,
+    };
+
+    let ext = match metadata["selectedFormat"].as_str() {
+        Some(x) if x.starts_with("FLAC") => "flac",
+        Some(x) if x.starts_with("MP3") => "mp3",
+        _ => "bin",
+    };
+
+    Some(PathBuf::from(dir).join(format!("{}.{}", id, ext)))
+}
+
+/// Feed `source` into ffmpeg while writing a copy to `path`,
+/// without copying the stream through userspace.
+///
+/// The copy is written to `<path>.part` and only renamed once the
+/// stream ends cleanly. If the cache can't be written, the stream
+/// keeps going to ffmpeg uncached.
+fn tap_to_cache(source: ChildStdout, sink: ChildStdin, path: PathBuf) -> std::io::Result<()> {
+    let partial = path.with_extension(format!(
+        "{}.part",
+        path.extension().and_then(|x| x.to_str()).unwrap_or_default()
+    ));
+
+    std::thread::Builder::new()
+        .name(format!("cache {}", path.display()))
+        .spawn(move || {
+            let file = match std::fs::File::create(&partial) {
+                Ok(file) => file,
+                Err(e) => {
+                    tracing::warn!("couldn't create cache file {}: {}", partial.display(), e);
+                    if let Err(e) = cutils::splice::pump(&source, &sink) {
+                        tracing::debug!("stream to ffmpeg ended: {}", e);
+                    }
+                    return;
+                }
+            };
+
+            match cutils::splice::tee_to_file(&source, &sink, &file) {
+                Ok(n) => match std::fs::rename(&partial, &path) {
+                    Ok(_) => tracing::info!("cached {} bytes to {}", n, path.display()),
+                    Err(e) => tracing::warn!("couldn't move {} into cache: {}", partial.display(), e),
+                },
+                Err(e) => {
+                    tracing::warn!("caching {} stopped: {}", path.display(), e);
+                    let _ = std::fs::remove_file(&partial);
+                    // ffmpeg went away, or the cache failed; in the latter
+                    // case keep playback going, without repeating what
+                    // ffmpeg already has
+                    if let Err(e) = cutils::splice::skip(&source, e.pending) {
+                        tracing::debug!("stream to ffmpeg ended: {}", e);
+                        return;
+                    }
+                    if let Err(e) = cutils::splice::pump(&source, &sink) {
+                        tracing::debug!("stream to ffmpeg ended: {}", e);
+                    }
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+/// Best effort, a small pipe only costs throughput.
+fn grow_pipe<F: AsFd>(fd: &F, size: Option<i32>) {
+    if let Some(size) = size {
+        if let Err(e) = Pipe::new(fd).set_capacity(size as usize) {
+            tracing::warn!("failed to grow pipe to {} bytes: {}", size, e);
+        }
+    }
+}
+
+/// Stream `uri` for `guild_id` with the next healthy ARL, at the best tier
+/// it allows, stepping down a tier each time one fails. If the ARL itself is
+/// at fault, or every tier failed, the stream is retried with the next ARL.
 pub async fn deemix(
     uri: &str,
-) -> Result<Input, DeemixError> {
-    _deemix(uri, &[])
-        .await
+    guild_id: u64,
+) -> Result<(Input, StreamQuality), DeemixError> {
+    let pool = ArlPool::global();
+    let mut last_err = DeemixError::NoArl;
+
+    for _ in 0..pool.len().max(1) {
+        let arl = match pool.next() {
+            Some(arl) => arl,
+            None => break,
+        };
+
+        let mut quality = preferred_quality(guild_id, Some(&arl));
+        let err = loop {
+            let err = match _deemix(uri, &[], quality, &arl.arl).await {
+                Ok(x) => {
+                    pool.report_success(&arl.arl);
+                    return Ok(x)
+                }
+                // not the tier's or the ARL's fault
+                Err(e @ DeemixError::Budget(_)) => return Err(e),
+                Err(e) if e.is_arl_failure() => break e,
+                Err(e) => e,
+            };
+
+            match quality.step_down() {
+                Some(next) => {
+                    tracing::warn!("{} failed at {}, retrying at {}: {}", uri.trim(), quality, next, err);
+                    quality = next;
+                }
+                None => break err,
+            }
+        };
+
+        tracing::warn!("{} failed with ARL {}: {}", uri.trim(), arl.masked(), err);
+        pool.report_failure(&arl.arl, &err.to_string());
+        last_err = err;
+    }
+
+    Err(last_err)
 }
 
 pub async fn _deemix(
     uri: &str,
     pre_args: &[&str],
-) -> Result<Input, DeemixError>
+    quality: StreamQuality,
+    arl: &str,
+) -> Result<(Input, StreamQuality), DeemixError>
 {
-    let pipesize = max_pipe_size().await.unwrap();
+    let balloon = balloon_size();
+
+    // deemix-stream + [balloon] + ffmpeg
+    let permit = ProcessBudget::global()
+        .acquire(Source::Deemix, if balloon.is_some() { 3 } else { 2 })
+        .await?;
+
+    let pipesize = max_pipe_size()
+        .await
+        .map_err(|e| tracing::warn!("couldn't read pipe-max-size: {}", e))
+        .ok();
     let ffmpeg_args = [
         "-f",
         "s16le",
@@ -185,19 +553,25 @@ pub async fn _deemix(
     ];
     
     tracing::info!("Running: deemix-stream {} {}", pre_args.join(" "), uri);
-    let mut deemix = std::process::Command::new("deemix-stream")
-        .arg("-hq")
-        .arg("1")
-        .arg(uri.trim())
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()?;
-    
-    let deemix_out = deemix.stdout.as_ref().unwrap().as_raw_fd();
-    unsafe { bigpipe(deemix_out, pipesize); }
-
+    let mut pipeline = Pipeline::new(format!("deemix {}", uri.trim()))
+        .with_permit(permit);
+
+    let deemix = pipeline.spawn(
+        "deemix-stream",
+        std::process::Command::new("deemix-stream")
+            .arg("--quality")
+            .arg(quality.arg())
+            .arg(uri.trim())
+            .env("DEEMIX_ARL", arl)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+    )?;
+
+    let deemix_stdout = deemix.stdout.take().ok_or(SongbirdError::Stdout)?;
     let stderr = deemix.stderr.take();
+    grow_pipe(&deemix_stdout, pipesize);
+
     // Read first line of stderr
     // for metadata, but read entire buffer if error.
     let threadout = tokio::task::spawn_blocking(move || {
@@ -209,7 +583,7 @@ pub async fn _deemix(
 
     let (returned_stderr, value) = threadout;
 
-    deemix.stderr = Some(returned_stderr);
+    pipeline.capture_stderr(returned_stderr);
     
     let metadata_raw = value?;
     if let Some(_) = metadata_raw.get("error") {
@@ -217,64 +591,102 @@ pub async fn _deemix(
     }
 
     let _filesize = metadata_raw["filesize"].as_u64();
+    let selected = metadata_raw["selectedFormat"]
+        .as_str()
+        .and_then(StreamQuality::from_format)
+        .unwrap_or(quality);
     let metadata = Some(metadata_from_deemix_output(&metadata_raw));
 
+    // Absorb network jitter before decoding
+    let source: ChildStdout = match balloon {
+        Some(size) => {
+            let proxy = pipeline.spawn(
+                "balloon",
+                std::process::Command::new("balloon")
+                    .args(&["proxy", "--size", &size, "--progress", "0"])
+                    .stdin(deemix_stdout)
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+            )?;
+
+            let proxy_stdout = proxy.stdout.take().ok_or(SongbirdError::Stdout)?;
+            if let Some(stderr) = proxy.stderr.take() {
+                pipeline.capture_stderr(stderr);
+            }
+            grow_pipe(&proxy_stdout, pipesize);
+            proxy_stdout
+        }
+        None => deemix_stdout,
+    };
+
+    // seeking restarts mid-stream, only cache whole tracks
+    let cache = match pre_args.is_empty() {
+        true => cache_path(&metadata_raw),
+        false => None,
+    };
+
+    let (ffmpeg_stdin, tap_source): (Stdio, _) = match cache {
+        Some(_) => (Stdio::piped(), Some(source)),
+        None => (source.into(), None),
+    };
+
     tracing::info!("running ffmpeg");
-    let ffmpeg = std::process::Command::new("ffmpeg")
-        .args(pre_args)
-        .arg("-i")
-        .arg("-")
-        .args(&ffmpeg_args)
-        .stdin(deemix.stdout.take().ok_or(SongbirdError::Stdout)?)
-        .stderr(Stdio::null())
-        .stdout(Stdio::piped())
-        .spawn()
-        .expect("Failed to start child process");
+    let ffmpeg = pipeline.spawn(
+        "ffmpeg",
+        std::process::Command::new("ffmpeg")
+            .args(pre_args)
+            .arg("-i")
+            .arg("-")
+            .args(&ffmpeg_args)
+            .stdin(ffmpeg_stdin)
+            .stderr(Stdio::piped())
+            .stdout(Stdio::piped())
+    )?;
+
+    let ffmpeg_stdout = ffmpeg.stdout.take().ok_or(SongbirdError::Stdout)?;
+    let ffmpeg_stderr = ffmpeg.stderr.take();
+
+    if let (Some(path), Some(source)) = (cache, tap_source) {
+        let ffmpeg_stdin = ffmpeg.stdin.take().ok_or(SongbirdError::Stdout)?;
+        grow_pipe(&ffmpeg_stdin, pipesize);
+        tap_to_cache(source, ffmpeg_stdin, path)?;
+    }
+
+    if let Some(ffmpeg_stderr) = ffmpeg_stderr {
+        pipeline.capture_stderr(ffmpeg_stderr);
+    }
     
     tracing::info!("deezer metadata {:?}", metadata);
-    let ffmpeg_ptr = ffmpeg.stdout.as_ref().ok_or(SongbirdError::Stdout)?.as_raw_fd();
-    unsafe { bigpipe(ffmpeg_ptr, pipesize); }
+    grow_pipe(&ffmpeg_stdout, pipesize);
     
-    let now = std::time::Instant::now();
-    let pipesize = max_pipe_size().await.unwrap();
-    let pipe_threshold = std::env::var("MKBIRD_PIPE_THRESHOLD")
-        .unwrap_or_else(|_| "0.8".to_string())
-        .parse::<f32>()
-        .unwrap_or(0.8);
-
-    loop {
-        let avail = unsafe { availbytes(ffmpeg_ptr) };            
-        let mut percentage = 0.0;
-        if 0 > avail {
-            break
-        }
-        if avail > 0 {
-            percentage = pipesize as f32 / avail as f32;
-        }
+    let reader = Prebuffered::new(
+        format!("deemix {}", uri.trim()),
+        pipeline.watch(ffmpeg_stdout),
+        pipeline,
+        PrebufferConfig::from_env(),
+    )?;
+
+    let report = reader.wait().await;
+    if report.eof && report.buffered.is_zero() {
+        return Err(DeemixError::IO(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "ffmpeg produced no audio"
+        )));
+    }
 
-        if pipe_threshold > percentage {
-            tokio::time::sleep(std::time::Duration::from_micros(200)).await;
-            tracing::debug!("availbytes: {}", avail);
-            tracing::debug!("pipesize: {}", pipesize);
-        }
-        else {
-            tracing::info!("load time: {}", now.elapsed().as_secs_f64());
-            tracing::debug!("availbytes: {}", avail);
-            tracing::debug!("pipesize: {}", pipesize);
-            break
-        }
-    }  
- 
-    Ok(Input::new(
-        true,
-        children_to_reader::<f32>(vec![deemix, ffmpeg]),
-        Codec::FloatPcm,
-        Container::Raw,
-        metadata,
+    Ok((
+        Input::new(
+            true,
+            Reader::Extension(Box::new(reader)),
+            Codec::FloatPcm,
+            Container::Raw,
+            metadata,
+        ),
+        selected,
     ))
 }
 
-fn metadata_from_deemix_output(val: &serde_json::Value) -> Metadata
+pub(crate) fn metadata_from_deemix_output(val: &serde_json::Value) -> Metadata
 {
     let obj = val.as_object();
 
@@ -309,3 +721,57 @@ fn metadata_from_deemix_output(val: &serde_json::Value) -> Metadata
         ..Default::default()
     }
 }
+
+#[cfg(feature = "controller")]
+#[group]
+#[commands(quality)]
+struct Quality;
+
+#[cfg(feature = "controller")]
+#[command]
+#[only_in(guilds)]
+#[required_permissions("MANAGE_GUILD")]
+async fn quality(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let guild_id = msg.guild_id.unwrap().0;
+
+    let saved = match args.rest().trim() {
+        "" => Ok(()),
+        "reset" => set_guild_quality(guild_id, None),
+        choice => match choice.parse::<StreamQuality>() {
+            Ok(q) => set_guild_quality(guild_id, Some(q)),
+            Err(()) => {
+                msg.channel_id
+                   .say(&ctx.http, "Expected one of: flac, 320, 128, reset")
+                   .await?;
+                return Ok(());
+            }
+        }
+    };
+
+    if let Err(e) = saved {
+        tracing::error!("couldn't save the quality for {}: {}", guild_id, e);
+        msg.channel_id.say(&ctx.http, format!("Couldn't save the quality: {}", e)).await?;
+        return Ok(());
+    }
+
+    let current = ArlPool::global().current();
+    let arl = current.as_ref()
+        .and_then(|e| e.health.as_ref())
+        .map(|h| h.quality.to_string())
+        .unwrap_or("unknown".to_string());
+
+    msg.channel_id
+       .say(
+            &ctx.http,
+            format!(
+                "```\nguild: {}\noperator: {}\naccount: {}\nstreaming: {}\n```",
+                guild_quality(guild_id).map(|q| q.to_string()).unwrap_or("default".to_string()),
+                StreamQuality::operator_max(),
+                arl,
+                preferred_quality(guild_id, current.as_ref()),
+            )
+       )
+       .await?;
+
+    Ok(())
+}
@@ -0,0 +1,217 @@
+//! Finding a track on another source when its own source fails.
+//!
+//! The failed track's ISRC, title, artist and duration are turned into a
+//! [`TrackQuery`]. yt-dlp is searched for the ISRC and for
+//! `artist - title`, and every result is scored on how much of the title and
+//! artist it contains. Results whose duration is further than
+//! `MKBIRD_FALLBACK_TOLERANCE` seconds (default 5) from the original's are
+//! never picked.
+
+use std::{process::Stdio, time::Duration};
+
+use serde_json::Value;
+
+use crate::budget::{BudgetError, ProcessBudget, Source};
+
+/// Results fetched per search.
+const SEARCH_RESULTS: usize = 5;
+/// Lowest score a result may have and still be played.
+const MIN_SCORE: f64 = 0.5;
+
+/// Words that say nothing about which song a result is.
+const NOISE: [&str; 12] = [
+    "official", "video", "audio", "lyrics", "lyric", "hd", "hq",
+    "topic", "ft", "feat", "music", "the",
+];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackQuery {
+    pub isrc: Option<String>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+impl TrackQuery {
+    /// From a Deezer track object, from the public API or `deemix-metadata`.
+    pub fn from_deemix(value: &Value) -> Self {
+        let text = |x: Option<&Value>| x
+            .and_then(Value::as_str)
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty());
+
+        Self {
+            isrc: text(value.get("isrc")),
+            title: text(value.get("title")),
+            artist: text(value.get("artist").and_then(|x| x.get("name"))),
+            duration: value.get("duration")
+                .and_then(Value::as_f64)
+                .filter(|x| *x > 0.0)
+                .map(Duration::from_secs_f64),
+        }
+    }
+
+    /// Search terms, most specific first.
+    pub fn search_terms(&self) -> Vec<String> {
+        let mut terms = Vec::new();
+        if let Some(isrc) = &self.isrc {
+            terms.push(format!("\"{}\"", isrc));
+        }
+
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => terms.push(format!("{} - {}", artist, title)),
+            (None, Some(title)) => terms.push(title.clone()),
+            _ => {}
+        }
+        terms
+    }
+}
+
+impl std::fmt::Display for TrackQuery {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => write!(f, "{} - {}", artist, title),
+            (None, Some(title)) => write!(f, "{}", title),
+            _ => write!(f, "{}", self.isrc.as_deref().unwrap_or("unknown track")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub url: String,
+    pub title: String,
+    pub uploader: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+impl Candidate {
+    /// From a `yt-dlp -j --flat-playlist` line.
+    pub fn from_ytdl(value: &Value) -> Option<Self> {
+        let url = value.get("webpage_url")
+            .or(value.get("url"))
+            .and_then(Value::as_str)?
+            .to_string();
+
+        Some(Self {
+            url,
+            title: value.get("title").and_then(Value::as_str)?.to_string(),
+            uploader: value.get("channel")
+                .or(value.get("uploader"))
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            duration: value.get("duration")
+                .and_then(Value::as_f64)
+                .map(Duration::from_secs_f64),
+        })
+    }
+}
+
+fn tolerance() -> Duration {
+    std::env::var("MKBIRD_FALLBACK_TOLERANCE")
+        .ok()
+        .and_then(|x| x.parse::<f64>().ok())
+        .filter(|x| *x >= 0.0)
+        .map(Duration::from_secs_f64)
+        .unwrap_or(Duration::from_secs(5))
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|x| !x.is_empty() && !NOISE.contains(x))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Share of `needle`'s words found in `haystack`.
+fn overlap(needle: &str, haystack: &[String]) -> f64 {
+    let needle = words(needle);
+    if needle.is_empty() {
+        return 0.0;
+    }
+    needle.iter().filter(|x| haystack.contains(x)).count() as f64 / needle.len() as f64
+}
+
+/// How well `candidate` matches `query`, from 0 to 1.
+/// `None` if its duration isn't close enough.
+pub fn score(query: &TrackQuery, candidate: &Candidate, tolerance: Duration) -> Option<f64> {
+    let closeness = match (query.duration, candidate.duration) {
+        (Some(want), Some(got)) => {
+            let off = want.abs_diff(got);
+            if off > tolerance {
+                return None;
+            }
+            1.0 - off.as_secs_f64() / tolerance.as_secs_f64().max(1.0)
+        }
+        (Some(_), None) => return None,
+        (None, _) => 0.0,
+    };
+
+    let haystack = words(&format!(
+        "{} {}",
+        candidate.title,
+        candidate.uploader.as_deref().unwrap_or_default()
+    ));
+
+    let title = query.title.as_deref().map_or(0.0, |x| overlap(x, &haystack));
+    let artist = query.artist.as_deref().map_or(0.0, |x| overlap(x, &haystack));
+
+    Some(title * 0.6 + artist * 0.25 + closeness * 0.15)
+}
+
+/// The best scoring candidate, if any is good enough.
+pub fn best_match(query: &TrackQuery, candidates: Vec<Candidate>) -> Option<Candidate> {
+    let tolerance = tolerance();
+    candidates.into_iter()
+        .filter_map(|x| score(query, &x, tolerance).map(|s| (s, x)))
+        .filter(|(s, _)| *s >= MIN_SCORE)
+        .max_by(|(a, _), (b, _)| a.total_cmp(b))
+        .map(|(_, x)| x)
+}
+
+/// First [`SEARCH_RESULTS`] YouTube results for `term`.
+pub async fn ytdl_search(term: &str) -> Result<Vec<Candidate>, BudgetError> {
+    let _permit = ProcessBudget::global()
+        .acquire(Source::Metadata, 1)
+        .await?;
+
+    let output = tokio::process::Command::new("yt-dlp")
+        .args(["-j", "--flat-playlist", "--ignore-config", "--no-warnings"])
+        .arg(format!("ytsearch{}:{}", SEARCH_RESULTS, term))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await;
+
+    let output = match output {
+        Ok(x) => x,
+        Err(e) => {
+            tracing::error!("yt-dlp search failed: {}", e);
+            return Ok(Vec::new());
+        }
+    };
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|x| serde_json::from_str::<Value>(x).ok())
+        .filter_map(|x| Candidate::from_ytdl(&x))
+        .collect())
+}
+
+/// Search yt-dlp for `query` and pick the best result.
+pub async fn find_ytdl(query: &TrackQuery) -> Result<Option<Candidate>, BudgetError> {
+    let mut candidates: Vec<Candidate> = Vec::new();
+    for term in query.search_terms() {
+        for candidate in ytdl_search(&term).await? {
+            if !candidates.iter().any(|x| x.url == candidate.url) {
+                candidates.push(candidate);
+            }
+        }
+    }
+
+    tracing::info!("{} fallback candidate(s) for {}", candidates.len(), query);
+    Ok(best_match(query, candidates))
+}
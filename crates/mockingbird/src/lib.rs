@@ -2,8 +2,30 @@
 #[path = "player.rs"]
 pub mod player;
 
+#[cfg(feature = "controller")]
+pub mod controls;
+
+#[cfg(feature = "controller")]
+pub mod session;
+
+#[cfg(feature = "deemix")]
+pub mod deemix;
+
 #[cfg(feature = "deemix")]
-mod deemix;
+pub mod arl;
+
+#[cfg(feature = "deemix")]
+pub mod secrets;
+
+#[cfg(feature = "deemix")]
+pub mod monitor;
+
+#[cfg(feature = "ytdl")]
+pub mod fallback;
+
+pub mod budget;
+pub mod supervisor;
+pub mod prebuffer;
 
 
 // #[cfg(feature = "http-get")]
@@ -12,6 +34,15 @@ mod deemix;
 #[cfg(feature = "check")]
 pub mod check;
 
+#[cfg(feature = "check")]
+pub mod render;
+
+#[cfg(feature = "check")]
+pub mod bulk;
+
+#[cfg(any(feature = "controller", feature = "arl-cmd"))]
+pub mod slash;
+
 #[cfg(test)]
 mod testsuite;
 
@@ -29,3 +60,20 @@ pub async fn init(mut cfg: ClientBuilder) -> ClientBuilder {
 
     cfg.register_songbird()
 }
+
+/// Called once the bot is connected.
+#[allow(unused_variables)]
+pub async fn ready(ctx: &serenity::client::Context) {
+    #[cfg(feature = "deemix")]
+    monitor::spawn(ctx.http.clone());
+
+    #[cfg(feature = "controller")]
+    session::restore(ctx).await;
+}
+
+/// Called before the bot exits: saves the queues and leaves voice.
+#[allow(unused_variables)]
+pub async fn shutdown(data: &std::sync::Arc<serenity::prelude::RwLock<serenity::prelude::TypeMap>>, reason: &str) {
+    #[cfg(feature = "controller")]
+    session::shutdown(data, reason).await;
+}
@@ -0,0 +1,107 @@
+//! Background ARL monitoring.
+//!
+//! Started once the bot is connected. Checks the [`ArlPool`] now and every
+//! `MKBIRD_ARL_CHECK_INTERVAL` (sooner if deezer couldn't be reached), and
+//! delivers any [`ArlAlert`]s to the channel in `MKBIRD_OPS_CHANNEL`, or by
+//! DM to the bot's owners when it isn't set.
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use serenity::{
+    http::Http,
+    model::id::{ChannelId, UserId},
+};
+
+use crate::arl::{ArlAlert, ArlPool};
+
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Start monitoring. Calling it again (e.g. on reconnect) does nothing.
+pub fn spawn(http: Arc<Http>) {
+    if STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let pool = ArlPool::global();
+    tokio::spawn(async move {
+        loop {
+            let conclusive = pool.check_all().await;
+
+            let alerts = pool.take_alerts();
+            if !alerts.is_empty() {
+                notify(&http, &alerts).await;
+            }
+
+            tokio::time::sleep(match conclusive {
+                true => pool.interval(),
+                false => pool.retry_interval(),
+            }).await;
+        }
+    });
+}
+
+fn ops_channel() -> Option<ChannelId> {
+    std::env::var("MKBIRD_OPS_CHANNEL")
+        .ok()
+        .and_then(|x| x.trim().parse::<u64>().ok())
+        .map(ChannelId)
+}
+
+/// The bot's owners, or the application's if they weren't set.
+async fn owners(http: &Http) -> Vec<UserId> {
+    let owners = settings::privileges::owners().map(UserId).collect::<Vec<_>>();
+    if !owners.is_empty() {
+        return owners;
+    }
+
+    let info = match http.get_current_application_info().await {
+        Ok(info) => info,
+        Err(e) => {
+            tracing::error!("couldn't look up application owners: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut owners = vec![info.owner.id];
+    if let Some(team) = info.team {
+        owners.extend(team.members.iter().map(|m| m.user.id));
+    }
+    owners.sort();
+    owners.dedup();
+    owners
+}
+
+async fn notify(http: &Http, alerts: &[(String, ArlAlert)]) {
+    for (arl, alert) in alerts {
+        tracing::warn!("ARL {} {}", arl, alert);
+    }
+
+    let text = format!(
+        "**ARL alert**\n{}",
+        alerts.iter()
+            .map(|(arl, alert)| format!("`{}` {}", arl, alert))
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+
+    if let Some(channel) = ops_channel() {
+        if let Err(e) = channel.say(http, &text).await {
+            tracing::error!("couldn't post ARL alert to {}: {}", channel, e);
+        }
+        return;
+    }
+
+    for owner in owners(http).await {
+        let sent = match owner.create_dm_channel(http).await {
+            Ok(dm) => dm.say(http, &text).await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = sent {
+            tracing::error!("couldn't DM ARL alert to {}: {}", owner, e);
+        }
+    }
+}
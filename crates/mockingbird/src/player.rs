@@ -2,8 +2,8 @@ use serenity::{
     async_trait,
     model::channel::Message,
     framework::standard::{
-        macros::{command, group},
-        CommandResult, Args,
+        macros::{check, command, group},
+        CommandOptions, CommandResult, Args, Reason,
     }, 
     client::Cache,
     prelude::*,
@@ -17,15 +17,15 @@ use songbird::{
     Songbird,
     Call, 
     create_player,
-    input::{ffmpeg, Input, error::Error as SongbirdError},
+    input::{ffmpeg, Input, Metadata, Reader, error::Error as SongbirdError},
     tracks::{TrackHandle, Track},
     TrackEvent
 };
 
 use std::{
     process::Stdio,
-    time::Duration, collections::VecDeque,
-    sync::Arc,
+    time::{Duration, SystemTime}, collections::VecDeque,
+    sync::{Arc, OnceLock, atomic::{AtomicU8, Ordering}},
     collections::HashMap,
     path::PathBuf,
 };
@@ -37,17 +37,76 @@ use tokio::{
 
 use tokio::io::AsyncWriteExt;
 use serenity::futures::StreamExt;
-use cutils::{availbytes, bigpipe, max_pipe_size};
+use crate::budget::{Budgeted, BudgetError, BudgetPermit, ProcessBudget, Source};
+use crate::supervisor::Pipeline;
+use crate::prebuffer::{Prebuffered, PrebufferConfig};
+use crate::controls::{ControlsMessage, ControlsRefresher};
 
 const TS_PRELOAD_OFFSET: Duration = Duration::from_secs(20);
 const TS_ABANDONED_HB: Duration = Duration::from_secs(720);
+
+fn env_secs(var: &str, default: Duration) -> Duration {
+    std::env::var(var)
+        .ok()
+        .and_then(|x| x.parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(default)
+}
+
+/// How long before a track ends the next one is loaded,
+/// from `MKBIRD_PRELOAD_OFFSET`.
+fn preload_offset() -> Duration {
+    env_secs("MKBIRD_PRELOAD_OFFSET", TS_PRELOAD_OFFSET)
+}
+
+/// How often the bot checks if it was left alone in voice,
+/// from `MKBIRD_ABANDONED_TIMEOUT`.
+fn abandoned_timeout() -> Duration {
+    env_secs("MKBIRD_ABANDONED_TIMEOUT", TS_ABANDONED_HB)
+        .max(Duration::from_secs(1))
+}
 // const MAX_TRACK_LENGTH: Duration = Duration::from_secs(360*6); // 30 minutes
 // const MAX_ENQUEUED: u16 = 300;
 
 #[group]
-#[commands(join, leave, queue, now_playing, skip, shuffle)]
+#[commands(join, leave, queue, now_playing, skip, shuffle, player)]
+#[checks(Player)]
 struct BetterPlayer;
 
+/// Who may use the player, by command or button: everyone, or if the
+/// guild set a DJ role, its members and whoever can manage the server.
+/// Returns the guild it is used in.
+pub(crate) async fn can_control(ctx: &Context, guild_id: Option<GuildId>, user_id: UserId) -> Result<GuildId, String> {
+    let guild_id = guild_id.ok_or_else(|| "Only available in servers".to_string())?;
+    let dj_role = match settings::Settings::global().guild(guild_id.0).dj_role {
+        Some(role) => RoleId(role),
+        None => return Ok(guild_id),
+    };
+
+    let member = guild_id.member(ctx, user_id)
+        .await
+        .map_err(|e| format!("Couldn't look you up: {}", e))?;
+
+    let manager = ctx.cache
+        .guild(guild_id)
+        .map_or(false, |guild| guild.member_permissions(&member).manage_guild());
+
+    if member.roles.contains(&dj_role) || manager {
+        return Ok(guild_id);
+    }
+    settings::privileges::denied(user_id.0, &member.user.tag(), "the player", &guild_id.to_string(), "not a DJ");
+    Err(format!("Only {} can use the player", dj_role.mention()))
+}
+
+#[check]
+#[name = "Player"]
+async fn player_check(ctx: &Context, msg: &Message, _: &mut Args, _: &CommandOptions) -> Result<(), Reason> {
+    can_control(ctx, msg.guild_id, msg.author.id)
+        .await
+        .map(|_| ())
+        .map_err(Reason::User)
+}
+
 async fn next_track(call: &mut Call, uri: &str, guild_id: u64) -> Result<TrackHandle, HandlerError> {
     tracing::info!("Now playing: {}", uri);
     let player = Players::from_str(&uri)
@@ -72,6 +131,9 @@ pub enum HandlerError {
     #[cfg(feature = "deemix")]
     DeemixError(crate::deemix::DeemixError),
 
+    Budget(BudgetError),
+    /// No other source has the track.
+    NoMatch,
     NotImplemented,
     NoCall
 }
@@ -94,6 +156,12 @@ impl From<std::io::Error> for HandlerError {
     }
 }
 
+impl From<BudgetError> for HandlerError {
+    fn from(err: BudgetError) -> Self {
+        HandlerError::Budget(err)
+    }
+}
+
 #[cfg(feature = "http-get")]
 impl From<reqwest::Error> for HandlerError {
     fn from(err: reqwest::Error) -> Self {
@@ -124,6 +192,12 @@ impl std::fmt::Display for HandlerError {
             Self::NoCall
                 => write!(f, "Not in a voice channel to play in"),
 
+            Self::Budget(err)
+                => write!(f, "Process budget error: {}", err),
+
+            Self::NoMatch
+                => write!(f, "No equivalent track found on another source"),
+
             #[cfg(feature = "http-get")]
             Self::UnsupportedMediaType(content_type)
                 => write!(f, "Content type is not supported [{}]", content_type),
@@ -142,7 +216,16 @@ impl std::fmt::Display for HandlerError {
 }
 impl std::error::Error for HandlerError {}
 
-fn process_fan_output(buf: &mut VecDeque<String>, json_buf: Vec<serde_json::Value>, err_cnt: &mut usize, key: &str){
+/// A fanned out track: its URI, and its metadata when the fan had it.
+type Fanned = (String, Option<Metadata>);
+
+fn process_fan_output(
+    buf: &mut VecDeque<Fanned>,
+    json_buf: Vec<serde_json::Value>,
+    err_cnt: &mut usize,
+    key: &str,
+    metadata: fn(&serde_json::Value) -> Metadata,
+){
     for x in json_buf {
         if let Some(jmap) = x.as_object() {
             if !jmap.contains_key(key) {
@@ -151,7 +234,8 @@ fn process_fan_output(buf: &mut VecDeque<String>, json_buf: Vec<serde_json::Valu
                 continue
             }
         
-            buf.push_back(jmap[key].as_str().unwrap().to_owned());
+            let known = jmap.contains_key("title").then(|| metadata(&x));
+            buf.push_back((jmap[key].as_str().unwrap().to_owned(), known));
         }
         else {
 
@@ -168,32 +252,36 @@ fn process_fan_output(buf: &mut VecDeque<String>, json_buf: Vec<serde_json::Valu
  * feature generated code.
 */
 #[cfg(feature="deemix")]
-async fn fan_deezer(uri: &str, buf: &mut VecDeque<String>) -> Result<usize, HandlerError> {
+async fn fan_deezer(uri: &str, buf: &mut VecDeque<Fanned>) -> Result<usize, HandlerError> {
     let mut json_buf = Vec::new();
     let mut err_cnt = 0;
-    _urls("deemix-metadata", &[uri], &mut json_buf).await?;
+    let mut cmd = Command::new("deemix-metadata");
+    if let Some(entry) = crate::arl::ArlPool::global().current() {
+        cmd.env("DEEMIX_ARL", entry.arl);
+    }
+    _urls(cmd.arg(uri), &mut json_buf).await?;
 
-    process_fan_output(buf, json_buf, &mut err_cnt, "link");
+    process_fan_output(buf, json_buf, &mut err_cnt, "link", crate::deemix::metadata_from_deemix_output);
     Ok(err_cnt)
 }
 
 #[cfg(feature="ytdl")]
-async fn fan_ytdl(uri: &str, buf: &mut VecDeque<String>) -> Result<usize, HandlerError> {
+async fn fan_ytdl(uri: &str, buf: &mut VecDeque<Fanned>) -> Result<usize, HandlerError> {
     let mut json_buf = Vec::new();
     let mut err_cnt = 0;
-    _urls("yt-dlp", &["--flat-playlist", "-j", uri], &mut json_buf).await?;
+    _urls(Command::new("yt-dlp").args(&["--flat-playlist", "-j", uri]), &mut json_buf).await?;
     
-    process_fan_output(buf, json_buf, &mut err_cnt, "url");
+    process_fan_output(buf, json_buf, &mut err_cnt, "url", |x| Metadata::from_ytdl_output(x.clone()));
     Ok(err_cnt)
 }
 
 #[cfg(not(feature="deemix"))]
-async fn fan_deezer(uri: &str, buf: &mut VecDeque<String>) -> Result<(), HandlerError>  {
+async fn fan_deezer(uri: &str, buf: &mut VecDeque<Fanned>) -> Result<usize, HandlerError>  {
     return Err(HandlerError::NotImplemented)
 }
 
 #[cfg(not(feature="ytdl"))]
-async fn fan_ytdl(uri: &str, buf: &mut VecDeque<String>) -> Result<usize, HandlerError> {
+async fn fan_ytdl(uri: &str, buf: &mut VecDeque<Fanned>) -> Result<usize, HandlerError> {
     return Err(HandlerError::NotImplemented)
 }
 
@@ -227,19 +315,146 @@ async fn ph_httpget_player(
 }
 
 #[cfg(feature = "deemix")]
-async fn ph_deemix_player(uri: &str) -> Result<Input, HandlerError> {
+async fn ph_deemix_player(uri: &str, guild_id: u64) -> Result<(Input, Option<String>), HandlerError> {
     tracing::info!("[Deemix] Streaming: {}", uri);
-    crate::deemix::deemix(uri).await.map_err(HandlerError::from)
+    let (input, quality) = crate::deemix::deemix(uri, guild_id).await?;
+    Ok((input, Some(quality.to_string())))
+}
+
+#[cfg(feature = "ytdl")]
+async fn ytdl_metadata(uri: &str) -> Result<songbird::input::Metadata, HandlerError> {
+    let _permit = ProcessBudget::global()
+        .acquire(Source::Metadata, 1)
+        .await?;
+
+    let output = Command::new("yt-dlp")
+        .args(&["-j", "--no-playlist", "--skip-download", "--ignore-config", "--no-warnings"])
+        .arg(uri)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await?;
+
+    let value = serde_json::from_slice(&output.stdout)
+        .map_err(|_| HandlerError::Songbird(SongbirdError::Metadata))?;
+
+    Ok(songbird::input::Metadata::from_ytdl_output(value))
 }
 
 #[cfg(feature = "ytdl")]
 async fn ph_ytdl_player(uri: &str) -> Result<Input, HandlerError> {
+    use songbird::input::{Codec, Container};
+
     tracing::info!("[YTDLP] Streaming: {}", uri);
-    return songbird::ytdl(uri).await.map_err(HandlerError::from)
+    let metadata = ytdl_metadata(uri).await?;
+
+    // yt-dlp + ffmpeg
+    let permit = ProcessBudget::global()
+        .acquire(Source::Ytdl, 2)
+        .await?;
+
+    let mut pipeline = Pipeline::new(format!("yt-dlp {}", uri))
+        .with_permit(permit);
+
+    let ytdl = pipeline.spawn(
+        "yt-dlp",
+        std::process::Command::new("yt-dlp")
+            .args(&[
+                "-f", "webm[abr>0]/bestaudio/best",
+                "-R", "infinite",
+                "--no-playlist",
+                "--ignore-config",
+                "--no-warnings",
+                "-q",
+                "-o", "-",
+            ])
+            .arg(uri)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+    )?;
+
+    let ytdl_stdout = ytdl.stdout.take().ok_or(SongbirdError::Stdout)?;
+    if let Some(stderr) = ytdl.stderr.take() {
+        pipeline.capture_stderr(stderr);
+    }
+
+    let ffmpeg = pipeline.spawn(
+        "ffmpeg",
+        std::process::Command::new("ffmpeg")
+            .args(&["-i", "-", "-f", "s16le", "-ac", "2", "-ar", "48000", "-acodec", "pcm_f32le", "-"])
+            .stdin(ytdl_stdout)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+    )?;
+
+    let ffmpeg_stdout = ffmpeg.stdout.take().ok_or(SongbirdError::Stdout)?;
+    if let Some(stderr) = ffmpeg.stderr.take() {
+        pipeline.capture_stderr(stderr);
+    }
+
+    let reader = Prebuffered::new(
+        format!("yt-dlp {}", uri),
+        pipeline.watch(ffmpeg_stdout),
+        pipeline,
+        PrebufferConfig::from_env(),
+    )?;
+
+    let report = reader.wait().await;
+    if report.eof && report.buffered.is_zero() {
+        return Err(HandlerError::IOError(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "ffmpeg produced no audio"
+        )));
+    }
+
+    Ok(Input::new(
+        true,
+        Reader::Extension(Box::new(reader)),
+        Codec::FloatPcm,
+        Container::Raw,
+        Some(metadata),
+    ))
+}
+
+/// Play the deezer track `uri` from yt-dlp instead, matched by its
+/// ISRC, title, artist and duration.
+#[cfg(all(feature = "deemix", feature = "ytdl"))]
+async fn ph_fallback_player(uri: &str) -> Result<(Input, Fallback), HandlerError> {
+    use crate::fallback::{self, TrackQuery};
+
+    // the ARL may be why the track failed, so ask the public API first
+    let info = match crate::deemix::public_track_info(uri).await {
+        Ok(info) => info,
+        Err(e) => {
+            tracing::warn!("[Fallback] public track info for {} failed: {}", uri.trim(), e);
+            crate::deemix::deemix_track_info(uri).await?
+        }
+    };
+    let query = TrackQuery::from_deemix(&info);
+    let candidate = fallback::find_ytdl(&query)
+        .await?
+        .ok_or(HandlerError::NoMatch)?;
+
+    tracing::info!("[Fallback] {} -> {}", uri.trim(), candidate.url);
+    let input = ph_ytdl_player(&candidate.url).await?;
+
+    Ok((input, Fallback {
+        original: query.to_string(),
+        title: candidate.title,
+        url: candidate.url,
+    }))
+}
+
+#[cfg(not(all(feature = "deemix", feature = "ytdl")))]
+async fn ph_fallback_player(uri: &str) -> Result<(Input, Fallback), HandlerError> {
+    return Err(HandlerError::NotImplemented)
 }
 
 #[cfg(not(feature = "deemix"))]
-async fn ph_deemix_player(uri: &str) -> Result<Input, HandlerError> {
+async fn ph_deemix_player(uri: &str, guild_id: u64) -> Result<(Input, Option<String>), HandlerError> {
     return Err(HandlerError::NotImplemented)
 }
 
@@ -253,9 +468,24 @@ async fn ph_httpget_player(uri: &str) -> (FilePath, Result<Input, HandlerError>)
     return (FilePath::new(), Err(HandlerError::NotImplemented))
 }
 
-async fn _urls(cmd: &str, args: &[&str], buf: &mut Vec<serde_json::Value>) -> std::io::Result<()> {
-    let child = Command::new(cmd)
-        .args(args)
+/// Tie `permit` to the lifetime of the input's reader.
+#[cfg(feature = "http-get")]
+fn budgeted_input(input: Input, permit: BudgetPermit) -> Input {
+    Input::new(
+        input.stereo,
+        Reader::Extension(Box::new(Budgeted::new(input.reader, permit))),
+        input.kind,
+        input.container,
+        Some(*input.metadata),
+    )
+}
+
+async fn _urls(cmd: &mut Command, buf: &mut Vec<serde_json::Value>) -> Result<(), HandlerError> {
+    let _permit = ProcessBudget::global()
+        .acquire(Source::Metadata, 1)
+        .await?;
+
+    let child = cmd
         .stdout(Stdio::piped())
         .spawn()
         .unwrap();
@@ -290,7 +520,7 @@ async fn _urls(cmd: &str, args: &[&str], buf: &mut Vec<serde_json::Value>) -> st
 
 
 
-#[derive(PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Players {
     Ytdl,
     Deemix,
@@ -298,7 +528,7 @@ pub enum Players {
 }
 
 impl Players {
-    fn from_str(data : &str) -> Option<Self>
+    pub(crate) fn from_str(data : &str) -> Option<Self>
     {
         const DEEMIX: [&'static str; 4] = ["deezer.page.link", "deezer.com", "open.spotify", "spotify.link"];
         const YTDL: [&'static str; 4] = ["youtube.com", "youtu.be", "music.youtube.com", "soundcloud.com"];
@@ -308,18 +538,51 @@ impl Players {
             "vxsesh.cypress.local"
         ];
 
-        if DEEMIX.iter().any(|x|data.contains(x)) { return Some(Self::Deemix) }
-        else if YTDL.iter().any(|x|data.contains(x)) {return Some(Self::Ytdl) }
-        else if HTTPGET.iter().any(|x|data.contains(x)) {return Some(Self::HttpGet) }
+        /// Hosts routed to a player, from `var` (comma separated)
+        /// or `default`. Read once.
+        fn hosts(cell: &'static OnceLock<Vec<String>>, var: &str, default: &[&str]) -> &'static [String] {
+            cell.get_or_init(|| match std::env::var(var) {
+                Ok(x) => x.split(',')
+                    .map(|x| x.trim().to_string())
+                    .filter(|x| !x.is_empty())
+                    .collect(),
+                Err(_) => default.iter().map(|x| x.to_string()).collect(),
+            })
+        }
+
+        static DEEMIX_HOSTS: OnceLock<Vec<String>> = OnceLock::new();
+        static YTDL_HOSTS: OnceLock<Vec<String>> = OnceLock::new();
+        static HTTP_HOSTS: OnceLock<Vec<String>> = OnceLock::new();
+
+        if hosts(&DEEMIX_HOSTS, "MKBIRD_DEEMIX_HOSTS", &DEEMIX).iter().any(|x| data.contains(x.as_str())) { return Some(Self::Deemix) }
+        else if hosts(&YTDL_HOSTS, "MKBIRD_YTDL_HOSTS", &YTDL).iter().any(|x| data.contains(x.as_str())) { return Some(Self::Ytdl) }
+        else if hosts(&HTTP_HOSTS, "MKBIRD_HTTP_HOSTS", &HTTPGET).iter().any(|x| data.contains(x.as_str())) { return Some(Self::HttpGet) }
         else { return None }
     }
 
     async fn play(&self, handler: &mut Call, uri: &str, guild_id: u64) -> Result<TrackHandle, HandlerError>
     {
         let mut is_tempfile = false;
+        let mut format = None;
+        let mut fallback = None;
 
         let input = match self {
-            Self::Deemix => ph_deemix_player(uri).await,
+            Self::Deemix => match ph_deemix_player(uri, guild_id).await {
+                Ok((input, quality)) => { format = quality; Ok(input) }
+
+                // waiting on the budget isn't the source's fault
+                Err(e @ HandlerError::Budget(_)) => Err(e),
+                #[cfg(feature = "deemix")]
+                Err(e @ HandlerError::DeemixError(crate::deemix::DeemixError::Budget(_))) => Err(e),
+
+                Err(e) => match ph_fallback_player(uri).await {
+                    Ok((input, used)) => { fallback = Some(used); Ok(input) }
+                    Err(fallback_err) => {
+                        tracing::warn!("no fallback for {}: {}", uri.trim(), fallback_err);
+                        Err(e)
+                    }
+                }
+            },
             Self::Ytdl => ph_ytdl_player(uri).await,
             Self::HttpGet => {
                 let (fp, result) = ph_httpget_player(
@@ -347,21 +610,113 @@ impl Players {
         }?;
 
         let (track, track_handle) = create_player(input);
+        if let Some(format) = format {
+            track_handle.typemap().write().await.insert::<TrackFormatKey>(format);
+        }
+        if let Some(fallback) = fallback {
+            track_handle.typemap().write().await.insert::<FallbackKey>(fallback);
+        }
         handler.enqueue(track);
 
         Ok(track_handle)
     }
 
-    async fn fan_collection(&self, uri: &str) -> Result<VecDeque<String>, HandlerError> {
+    async fn fan_collection(&self, uri: &str) -> Result<VecDeque<Fanned>, HandlerError> {
         let mut buf = VecDeque::new();
         match self {
-            Self::HttpGet => {buf.push_back(uri.to_owned()); Ok(1)},
+            Self::HttpGet => {buf.push_back((uri.to_owned(), None)); Ok(1)},
             Self::Deemix => fan_deezer(uri, &mut buf).await,
             Self::Ytdl => fan_ytdl(uri, &mut buf).await 
         }?;
 
         return Ok(buf)
     }
+
+    /// Look up `uri`'s metadata without playing it.
+    async fn metadata(&self, uri: &str) -> Option<Metadata> {
+        let result = match self {
+            #[cfg(feature = "deemix")]
+            Self::Deemix => crate::deemix::deemix_metadata(uri).await.map_err(HandlerError::from),
+            #[cfg(feature = "ytdl")]
+            Self::Ytdl => ytdl_metadata(uri).await,
+            _ => return None,
+        };
+
+        match result {
+            Ok(metadata) => Some(metadata),
+            Err(e) => {
+                tracing::warn!("couldn't look up metadata for {}: {}", uri, e);
+                None
+            }
+        }
+    }
+}
+
+/// An entry in a guild's queue.
+#[derive(Debug, Clone)]
+pub struct QueueItem {
+    pub uri: String,
+    pub source: Players,
+    /// Filled in the background after the item is queued.
+    pub metadata: Arc<OnceLock<Metadata>>,
+    pub requester: UserId,
+    /// Where it was requested from.
+    pub channel: ChannelId,
+    pub message: Option<MessageId>,
+    pub queued_at: SystemTime,
+}
+
+impl QueueItem {
+    pub fn new(uri: String, source: Players, requester: UserId, channel: ChannelId, message: Option<MessageId>) -> Self {
+        Self {
+            uri,
+            source,
+            metadata: Arc::new(OnceLock::new()),
+            requester,
+            channel,
+            message,
+            queued_at: SystemTime::now(),
+        }
+    }
+
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata.get()
+    }
+
+    /// `Artist - Title` once the metadata is known, the URI until then.
+    pub fn title(&self) -> String {
+        self.metadata()
+            .and_then(metadata_title)
+            .unwrap_or_else(|| self.uri.clone())
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.metadata().and_then(|m| m.duration)
+    }
+}
+
+/// `Artist - Title`, or just the title without an artist.
+pub fn metadata_title(metadata: &Metadata) -> Option<String> {
+    match (&metadata.artist, &metadata.track) {
+        (Some(artist), Some(track)) => Some(format!("{} - {}", artist, track)),
+        (None, Some(track)) => Some(track.clone()),
+        _ => None,
+    }
+}
+
+/// Look up the metadata of `items` that don't have it yet, one at a time.
+/// Items no longer queued are passed over.
+pub(crate) async fn fill_metadata(items: Vec<QueueItem>) {
+    for item in items {
+        // the queue dropped its copy (skipped, cleared, played)
+        if item.metadata.get().is_some() || Arc::strong_count(&item.metadata) == 1 {
+            continue
+        }
+
+        if let Some(metadata) = item.source.metadata(&item.uri).await {
+            let _ = item.metadata.set(metadata);
+        }
+    }
 }
 
 #[cfg(feature = "http-get")]
@@ -417,8 +772,12 @@ pub async fn get_file(
 
             tracing::info!("wrote: {} [{}]", fp.display(), human_filesize(fd.metadata().await?.len()));
 
-            let input = songbird::input::ffmpeg(&fp).await.map_err(HandlerError::from);
-            input
+            let permit = ProcessBudget::global()
+                .acquire(Source::HttpGet, 1)
+                .await?;
+
+            let input = songbird::input::ffmpeg(&fp).await?;
+            Ok(budgeted_input(input, permit))
         }
 
         content_type => {
@@ -428,6 +787,34 @@ pub async fn get_file(
     }
 }
 
+/// Stream format a track is actually played in, e.g. `FLAC`.
+pub struct TrackFormatKey;
+impl TypeMapKey for TrackFormatKey {
+    type Value = String;
+}
+
+/// Set on tracks played from another source
+/// because their own couldn't play them.
+#[derive(Debug, Clone)]
+pub struct Fallback {
+    /// What was asked for, e.g. `Artist - Title`.
+    pub original: String,
+    /// What is played instead.
+    pub title: String,
+    pub url: String,
+}
+
+pub struct FallbackKey;
+impl TypeMapKey for FallbackKey {
+    type Value = Fallback;
+}
+
+/// The queue entry a track was played from.
+pub struct QueueItemKey;
+impl TypeMapKey for QueueItemKey {
+    type Value = QueueItem;
+}
+
 type LazyQueue = HashMap<GuildId, Arc<QueueContext>>;
 pub struct LazyQueueKey;
 impl TypeMapKey for LazyQueueKey {
@@ -435,14 +822,17 @@ impl TypeMapKey for LazyQueueKey {
 }
 
 pub struct QueueContext {
-    guild_id: GuildId,
-    invited_from: ChannelId,
-    voice_chan_id: GuildChannel,
+    pub(crate) guild_id: GuildId,
+    pub(crate) invited_from: ChannelId,
+    pub(crate) voice_chan_id: GuildChannel,
     cache: Arc<Cache>,
     data: Arc<RwLock<TypeMap>>,
-    http: Arc<Http>,
-    manager: Arc<Songbird>,
-    cold_queue: Arc<RwLock<VecDeque<String>>>,
+    pub(crate) http: Arc<Http>,
+    pub(crate) manager: Arc<Songbird>,
+    pub(crate) cold_queue: Arc<RwLock<VecDeque<QueueItem>>>,
+    pub(crate) controls: Mutex<ControlsMessage>,
+    /// Volume of every track, in percent.
+    pub(crate) volume: AtomicU8,
 }
 
 struct RemoveTempFile(PathBuf);
@@ -473,27 +863,41 @@ impl VoiceEventHandler for AbandonedChannel {
     }
 }
 
-async fn play_routine(qctx: Arc<QueueContext>) -> Result<(), HandlerError> {
+pub(crate) async fn play_routine(qctx: Arc<QueueContext>) -> Result<(), HandlerError> {
     let mut tries = 4;
     let handler = qctx.manager.get(qctx.guild_id)
         .ok_or_else(|| HandlerError::NoCall)?;
     
     let mut call = handler.lock().await;
 
-    while let Some(uri) = qctx.cold_queue.write().await.pop_front() {
-        let uri = dbg!(uri);
+    while let Some(item) = qctx.cold_queue.write().await.pop_front() {
+        let uri = item.uri.clone();
+        tracing::debug!("[{}] next track: {}", qctx.guild_id, uri.trim());
         match next_track(&mut call, &uri, qctx.guild_id.0).await {
             Ok(track) => {
-                let track = dbg!(track);
+                tracing::debug!("[{}] playing {:?}", qctx.guild_id, track);
+                let _ = track.set_volume(qctx.volume.load(Ordering::Relaxed) as f32 / 100.0);
+                track.typemap().write().await.insert::<QueueItemKey>(item);
+                let fallback = track.typemap().read().await.get::<FallbackKey>().cloned();
+                if let Some(fallback) = fallback {
+                    let _ = qctx.invited_from
+                        .say(&qctx.http, format!(
+                            "Couldn't play {} from its source, playing **{}** instead\n<{}>",
+                            fallback.original, fallback.title, fallback.url
+                        ))
+                        .await;
+                }
+
                 if let Some(duration) = track.metadata().duration {
-                    if duration < TS_PRELOAD_OFFSET {
+                    let offset = preload_offset();
+                    if duration < offset {
                         tracing::warn!("No duration provided, preloading disabled");
                         break
                     }
 
                     tracing::info!("Preload Event Added from Duration");
                     track.add_event(
-                        Event::Delayed(duration - TS_PRELOAD_OFFSET),
+                        Event::Delayed(duration - offset),
                         PreemptLoader(qctx.clone())
                     ).unwrap();
                 }
@@ -582,7 +986,7 @@ impl VoiceEventHandler for PreemptLoader {
     }
 }
 
-async fn leave_routine (
+pub(crate) async fn leave_routine (
     data: Arc<RwLock<TypeMap>>,
     guild_id: GuildId,
     manager: Arc<Songbird>
@@ -598,50 +1002,65 @@ async fn leave_routine (
     
     manager.remove(guild_id).await?;
 
-    {
+    let qctx = {
         let mut glob = data.write().await; 
         let queue = glob.get_mut::<LazyQueueKey>()
             .expect("Expected LazyQueueKey in TypeMap");
-        queue.remove(&guild_id);
+        queue.remove(&guild_id)
+    };
+
+    if let Some(qctx) = qctx {
+        crate::controls::remove(&qctx).await;
     }
 
     Ok(())
 }
 
-async fn join_routine(ctx: &Context, msg: &Message) -> Result<Arc<QueueContext>, JoinError> {
-    let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
-
-    let channel_id = guild
-        .voice_states
-        .get(&msg.author.id)
-        .and_then(|voice_state| voice_state.channel_id);
+async fn join_routine(
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: UserId,
+    reply_to: ChannelId,
+) -> Result<Arc<QueueContext>, JoinError> {
+    let channel_id = guild_id
+        .to_guild_cached(&ctx.cache)
+        .and_then(|guild| guild.voice_states.get(&user_id).and_then(|voice_state| voice_state.channel_id));
 
     let connect_to = match channel_id {
         Some(channel) => {
             tracing::info!(
-                "[{}::{}] requested coggie in vc [{}::{:?}]",
-                msg.author.id, msg.author.name, msg.channel_id, msg.channel_id.name(&ctx).await
+                "[{}] requested coggie in vc [{}::{:?}]",
+                user_id, reply_to, reply_to.name(&ctx).await
             );
             channel
         },
         None => {
-            msg.reply(&ctx.http, "Not in a voice channel").await.unwrap();
             return Err(JoinError::NoCall);
         },
     };
 
-    let chan: Channel  = connect_to.to_channel(&ctx.http).await.unwrap();
+    join_channel(ctx, guild_id, connect_to, reply_to).await
+}
+
+/// Join `connect_to`, replying in `reply_to`.
+pub(crate) async fn join_channel(
+    ctx: &Context,
+    guild_id: GuildId,
+    connect_to: ChannelId,
+    reply_to: ChannelId,
+) -> Result<Arc<QueueContext>, JoinError> {
+    let chan: Channel = match connect_to.to_channel(&ctx.http).await {
+        Ok(chan) => chan,
+        Err(e) => {
+            tracing::error!("couldn't resolve voice channel {}: {}", connect_to, e);
+            return Err(JoinError::NoCall);
+        }
+    };
 
     let gchan = match chan {
         Channel::Guild(ref gchan) => gchan,
         _ => {
-            msg.reply(
-              &ctx.http,
-              "Not supported voice channel"
-            ).await
-             .unwrap();
-
+            let _ = reply_to.say(&ctx.http, "Not supported voice channel").await;
             return Err(JoinError::NoCall);
         }
     };
@@ -651,9 +1070,9 @@ async fn join_routine(ctx: &Context, msg: &Message) -> Result<Arc<QueueContext>,
        None => {
            tracing::info!(
                "[{}::{:?}] coggie detected low quality vc",
-               msg.channel_id, msg.channel_id.name(&ctx).await
+               reply_to, reply_to.name(&ctx).await
            );
-           let _ = msg.reply(
+           let _ = reply_to.say(
                &ctx.http,
                r#"**Couldn't detect bitrate.** For the best experience,
                   check that the voice room is using 128kbps."#
@@ -663,12 +1082,12 @@ async fn join_routine(ctx: &Context, msg: &Message) -> Result<Arc<QueueContext>,
        Some(x) => {
             tracing::info!(
                 "[{}::{:?}] coggie detected low quality vc",
-                msg.channel_id, msg.channel_id.name(&ctx).await
+                reply_to, reply_to.name(&ctx).await
             );
 
             #[cfg(feature = "deemix")]
-            let _ = msg.reply(
-                &ctx,
+            let _ = reply_to.say(
+                &ctx.http,
                 format!(
                     r#"**Low quality voice room** detected.
 
@@ -694,17 +1113,20 @@ async fn join_routine(ctx: &Context, msg: &Message) -> Result<Arc<QueueContext>,
     let call_lock = manager.get(guild_id).unwrap(); 
     let mut call = call_lock.lock().await;
 
+    let settings = settings::Settings::global().guild(guild_id.0);
     let queuectx =
         if let Channel::Guild(voice_chan_id) = chan {
             QueueContext {
                 guild_id,
                 voice_chan_id,
-                invited_from: msg.channel_id,
+                invited_from: settings.announce_channel.map(ChannelId).unwrap_or(reply_to),
                 cache: ctx.cache.clone(),
                 data: ctx.data.clone(),
                 manager: manager.clone(),
                 http: ctx.http.clone(),
                 cold_queue: Arc::new(RwLock::new(VecDeque::new())),
+                controls: Mutex::new(ControlsMessage::default()),
+                volume: AtomicU8::new(settings.volume()),
             }
         } else {
             tracing::error!("Expected voice channel (GuildChannel), got {:?}", chan);
@@ -729,275 +1151,208 @@ async fn join_routine(ctx: &Context, msg: &Message) -> Result<Arc<QueueContext>,
     );
 
     call.add_global_event(
-        Event::Periodic(TS_ABANDONED_HB, None),
+        Event::Periodic(abandoned_timeout(), None),
         AbandonedChannel(queuectx.clone())
     );
 
-    Ok(queuectx)
-}
-
-#[command]
-#[aliases("np", "playing", "now-playing", "playing-now", "nowplaying")]
-#[only_in(guilds)]
-async fn now_playing(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
-
-    tracing::info!(
-        "[{}::{}] asked what track is playing in [{}::{:?}]",
-        msg.author.id, msg.author.name,
-        msg.channel_id, msg.channel_id.name(&ctx).await
-   );
-
-
-    let qctx = {
-        let mut glob = ctx.data.write().await;
-        let queue = glob.get_mut::<LazyQueueKey>()
-            .expect("Expected LazyQueueKey in TypeMap");
-        queue.get(&guild_id).cloned()
-    };
-
-    let qctx = match qctx {
-        Some(qctx) => qctx,
-        None => {
-            msg.channel_id
-               .say(&ctx.http, "Not in a voice channel")
-               .await?;
-            return Ok(());
-        }
-    };
+    call.add_global_event(
+        Event::Track(TrackEvent::Play),
+        ControlsRefresher(queuectx.clone())
+    );
 
-    let call_lock = qctx.manager
-        .get(qctx.guild_id)
-        .unwrap();
+    call.add_global_event(
+        Event::Track(TrackEvent::End),
+        ControlsRefresher(queuectx.clone())
+    );
 
-    let call = call_lock.lock().await;
+    Ok(queuectx)
+}
 
-    match call.queue().current() {
-        Some(ref x) => {
-            msg.channel_id
-               .say(&ctx.http,
-                    format!(
-                        "{}: {}", qctx.voice_chan_id.mention(),
-                        x.metadata()
-                            .clone()
-                            .source_url
-                            .unwrap_or("Unknown".to_string())
-                    )
-               ).await?;
-        }
-        None => {
-            msg.channel_id
-               .say(&ctx.http, "Nothing is currently playing")
-               .await?;
-        }
-    }
+/*
+ * Player operations, shared by the prefix and slash commands.
+ * `Ok` is the reply, `Err` is a reply describing what went wrong.
+*/
 
-    Ok(())
+pub(crate) async fn queue_context(ctx: &Context, guild_id: GuildId) -> Option<Arc<QueueContext>> {
+    ctx.data.read().await
+        .get::<LazyQueueKey>()
+        .expect("Expected LazyQueueKey in TypeMap")
+        .get(&guild_id)
+        .cloned()
 }
 
-#[command]
-#[only_in(guilds)]
-async fn join(ctx: &Context, msg: &Message) -> CommandResult {
-    let connect_to = join_routine(&ctx, msg).await;
-    
-    if let Err(ref e) = connect_to {
-        msg.channel_id
-           .say(&ctx.http, format!("Failed to join voice channel: {:?}", e))
-           .await?;        
+fn join_error(e: &JoinError) -> String {
+    match e {
+        JoinError::NoCall => "Not in a voice channel".to_string(),
+        e => format!("Failed to join voice channel: {:?}", e),
     }
-
-    msg.channel_id
-       .say(&ctx.http, format!("Joined {}", connect_to.unwrap().voice_chan_id.mention()))
-       .await?;
-
-    Ok(())
 }
 
-#[command]
-#[only_in(guilds)]
-async fn leave(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
+pub(crate) async fn join_voice(ctx: &Context, guild_id: GuildId, user_id: UserId, channel_id: ChannelId) -> Result<String, String> {
+    join_routine(ctx, guild_id, user_id, channel_id)
+        .await
+        .map(|qctx| format!("Joined {}", qctx.voice_chan_id.mention()))
+        .map_err(|e| join_error(&e))
+}
 
+pub(crate) async fn leave_voice(ctx: &Context, guild_id: GuildId) -> Result<String, String> {
     let manager = songbird::get(ctx)
         .await
         .expect("songbird voice client placed in at initialisation.")
         .clone();
 
-    let handler = manager.get(guild_id);
-    
-    if handler.is_none() {
-        msg.reply(ctx, "Not in a voice channel").await?;
-        return Ok(())
-    }
-    
-    let handler = handler.unwrap();
+    let handler = manager.get(guild_id)
+        .ok_or_else(|| "Not in a voice channel".to_string())?;
 
-    {
-        let mut call = handler.lock().await;
-        call.remove_all_global_events();
-        call.stop();
-        let _ = call.deafen(false).await;
-    }
+    let _ = handler.lock().await.deafen(false).await;
 
-    if let Err(e) = manager.remove(guild_id).await {
-        msg.channel_id
-           .say(&ctx.http, format!("Failed: {:?}", e))
-           .await?;
+    match leave_routine(ctx.data.clone(), guild_id, manager).await {
+        Ok(()) => Ok("Left voice channel".to_string()),
+        Err(e) => Err(format!("Failed: {:?}", e)),
     }
-    
-    {
-        let mut glob = ctx.data.write().await; 
-        let queue = glob.get_mut::<LazyQueueKey>().expect("Expected LazyQueueKey in TypeMap");
-        queue.remove(&guild_id);
-    }
-
-    msg.channel_id.say(&ctx.http, "Left voice channel").await?;
-    Ok(())
 }
 
-#[command]
-#[aliases("play", "p", "q")]
-#[only_in(guilds)]
-async fn queue(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
-    tracing::info!(
-        "[{}::{}] queued track in [{}::{:?}]",
-        msg.author.id, msg.author.name,
-        msg.channel_id, msg.channel_id.name(&ctx).await
-    );
+pub(crate) async fn now_playing_text(ctx: &Context, guild_id: GuildId) -> Result<String, String> {
+    let qctx = queue_context(ctx, guild_id)
+        .await
+        .ok_or_else(|| "Not in a voice channel".to_string())?;
 
-    let url = match args.single::<String>() {
-        Ok(url) => url,
-        Err(_) => {
-            msg.channel_id
-               .say(&ctx.http, "Must provide a URL to a video or audio")
-               .await
-               .unwrap();
-            return Ok(());
-        },
-    };
+    let call_lock = qctx.manager
+        .get(qctx.guild_id)
+        .ok_or_else(|| "Not in a voice channel".to_string())?;
 
+    let call = call_lock.lock().await;
+
+    match call.queue().current() {
+        Some(ref x) => {
+            let format = x.typemap()
+                .read()
+                .await
+                .get::<TrackFormatKey>()
+                .map(|f| format!(" [{}]", f))
+                .unwrap_or_default();
+
+            Ok(format!(
+                "{}: {}{}", qctx.voice_chan_id.mention(),
+                x.metadata()
+                    .clone()
+                    .source_url
+                    .unwrap_or("Unknown".to_string()),
+                format
+            ))
+        }
+        None => Ok("Nothing is currently playing".to_string()),
+    }
+}
+
+/// Queue `url` for `user_id`, joining their voice channel if needed.
+pub(crate) async fn enqueue(
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: UserId,
+    channel_id: ChannelId,
+    message: Option<MessageId>,
+    url: &str,
+) -> Result<String, String> {
     if !url.starts_with("http") {
-        msg.channel_id
-           .say(&ctx.http, "Must provide a valid URL")
-           .await
-           .unwrap();
-        return Ok(());
+        return Err("Must provide a valid URL".to_string());
     };
 
-    let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
+    let player = Players::from_str(url)
+        .ok_or_else(|| format!("Failed to select extractor for URL: {}", url))?;
 
     let manager = songbird::get(ctx)
         .await
         .expect("Songbird Voice client placed in at initialisation.")
         .clone();
 
-    let qctx: Arc<QueueContext>;
+    let (call, qctx) = match (manager.get(guild_id), queue_context(ctx, guild_id).await) {
+        (Some(call_lock), Some(qctx)) => (call_lock, qctx),
+        _ => {
+            let qctx = join_routine(ctx, guild_id, user_id, channel_id)
+                .await
+                .map_err(|e| join_error(&e))?;
 
-    let call = match manager.get(guild_id) {
-        Some(call_lock) => {
-            qctx = ctx.data.write().await.get_mut::<LazyQueueKey>().unwrap().get_mut(&guild_id).unwrap().clone();
-            call_lock
-        },
-        None => {
-            let tmp = join_routine(ctx, msg).await;            
-
-            if let Err(ref e) = tmp {
-                msg.channel_id
-                   .say(&ctx.http, format!("Failed to join voice channel: {:?}", e))
-                   .await
-                   .unwrap();        
-                return Ok(());
-            };
-            qctx = tmp.unwrap();
-            msg.channel_id
-                   .say(&ctx.http, format!("Joined: {}", qctx.voice_chan_id.mention()))
-                   .await
-                   .unwrap();
+            let _ = channel_id
+                .say(&ctx.http, format!("Joined: {}", qctx.voice_chan_id.mention()))
+                .await;
 
-            let call = manager.get(guild_id).ok_or_else(|| JoinError::NoCall);
-            call?
+            let call = manager.get(guild_id)
+                .ok_or_else(|| join_error(&JoinError::NoCall))?;
+            (call, qctx)
         }
     };
 
-    match Players::from_str(&url)
-        .ok_or_else(|| String::from("Failed to select extractor for URL"))
-    {
-        Ok(player) => {
-            let mut uris = player.fan_collection(url.as_str()).await?;
-            let added = uris.len();
-            
-            // YTDLP singles don't work.
-            // so instead, use the original URI.
-            if uris.len() == 1 && player == Players::Ytdl {
-                uris.clear();
-                uris.push_back(url.clone());
-            }
-            
-            qctx.cold_queue.write().await.extend(uris.drain(..));    
+    let mut uris = player.fan_collection(url)
+        .await
+        .map_err(|e| e.to_string())?;
+    let added = uris.len();
+    
+    // YTDLP singles don't work.
+    // so instead, use the original URI.
+    if uris.len() == 1 && player == Players::Ytdl {
+        uris.clear();
+        uris.push_back((url.to_string(), None));
+    }
 
-            let maybe_hot = {
-                let call = call.lock().await;
-                call.queue().len() > 0            
-            };
+    let mut skipped = 0;
+    if let Some(max) = settings::Settings::global().guild(guild_id.0).max_queue {
+        let room = max.saturating_sub(qctx.cold_queue.read().await.len());
+        if room == 0 {
+            return Err(format!("The queue is full ({} tracks)", max));
+        }
+        skipped = uris.len().saturating_sub(room);
+        uris.truncate(room);
+    }
 
-            drop(call); // probably not needed, but just in case
-            if !maybe_hot {
-                play_routine(qctx.clone()).await?;
+    let items = uris.drain(..)
+        .map(|(uri, metadata)| {
+            let item = QueueItem::new(uri, player, user_id, channel_id, message);
+            if let Some(metadata) = metadata {
+                let _ = item.metadata.set(metadata);
             }
+            item
+        })
+        .collect::<Vec<_>>();
 
-            let content = format!(
-                "Added {} Song(s) [{}] queued",
-                added,
-                qctx.cold_queue.read().await.len()
-            );
-            
-            msg.channel_id            
-               .say(&ctx.http, &content)
-               .await?;            
-        },
+    qctx.cold_queue.write().await.extend(items.iter().cloned());
+    tokio::spawn(fill_metadata(items.clone()));
 
-        Err(_) => {
-            msg.channel_id
-               .say(&ctx.http, format!("Failed to select extractor for URL: {}", url))
-               .await?;
-        }
-    }
-
-    Ok(())
-}
+    let maybe_hot = {
+        let call = call.lock().await;
+        call.queue().len() > 0            
+    };
 
-#[command]
-#[only_in(guilds)]
-async fn skip(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
+    drop(call); // probably not needed, but just in case
+    if !maybe_hot {
+        play_routine(qctx.clone())
+            .await
+            .map_err(|e| e.to_string())?;
+    }
 
-    tracing::info!(
-        "[{}::{}] skipped track in [{}::{:?}]",
-        msg.author.id, msg.author.name,
-        msg.channel_id, msg.channel_id.name(&ctx).await
-    );
+    let queued = qctx.cold_queue.read().await.len();
+    let reply = match items.as_slice() {
+        [item] if item.metadata().is_some() => format!("Added **{}** [{}] queued", item.title(), queued),
+        _ => format!("Added {} Song(s) [{}] queued", added - skipped, queued),
+    };
 
-    let qctx = ctx.data.write().await
-        .get_mut::<LazyQueueKey>().unwrap()
-        .get_mut(&guild_id).unwrap().clone();
-     
-    let skipn = args.remains()
-        .unwrap_or("1")
-        .parse::<isize>()
-        .unwrap_or(1);
+    Ok(match skipped {
+        0 => reply,
+        n => format!("{}, {} left out because the queue is full", reply, n),
+    })
+}
 
+/// Skip the current track and `skipn - 1` queued ones.
+pub(crate) async fn skip_tracks(ctx: &Context, guild_id: GuildId, skipn: isize) -> Result<String, String> {
     if 1 > skipn  {
-        msg.channel_id
-           .say(&ctx.http, "Must skip at least 1 song")
-           .await?;
-        return Ok(())
+        return Err("Must skip at least 1 song".to_string());
     }
 
-    else if skipn >= qctx.cold_queue.read().await.len() as isize + 1 {
+    let qctx = queue_context(ctx, guild_id)
+        .await
+        .ok_or_else(|| "Not in a voice channel to play in".to_string())?;
+
+    if skipn >= qctx.cold_queue.read().await.len() as isize + 1 {
         qctx.cold_queue.write().await.clear();
     }
 
@@ -1008,53 +1363,22 @@ async fn skip(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
         write_lock.extend(bottom);
     }
 
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
-
-    let handler_lock = match manager.get(guild_id) {
-        Some(x) => x,
-        None => {
-            msg.channel_id
-               .say(&ctx.http, "Not in a voice channel to play in")
-               .await?;
-            return Ok(())
-        }
-    };
+    let handler_lock = qctx.manager.get(guild_id)
+        .ok_or_else(|| "Not in a voice channel to play in".to_string())?;
 
     let cold_queue_len = qctx.cold_queue.read().await.len();
 
-    msg.channel_id
-       .say(
-            &ctx.http,
-            format!("Song skipped [{}]: {} in queue.", skipn, cold_queue_len),
-       )
-       .await?;
-
     let mut call = handler_lock.lock().await;
     let queue = call.queue();
     let _ = queue.skip();
 
-    Ok(())
+    Ok(format!("Song skipped [{}]: {} in queue.", skipn, cold_queue_len))
 }
 
-
-#[command]
-#[only_in(guilds)]
-async fn shuffle(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
-    let guild = msg.guild(&ctx.cache).unwrap();
-    let guild_id = guild.id;
-
-    tracing::info!(
-        "[{}::{}] shuffled playlist in [{}::{:?}]",
-        msg.author.id, msg.author.name,
-        msg.channel_id, msg.channel_id.name(&ctx).await
-    );
-
-    let qctx = ctx.data.write().await
-        .get_mut::<LazyQueueKey>().unwrap()
-        .get_mut(&guild_id).unwrap().clone();
+pub(crate) async fn shuffle_queue(ctx: &Context, guild_id: GuildId) -> Result<String, String> {
+    let qctx = queue_context(ctx, guild_id)
+        .await
+        .ok_or_else(|| "Not in a voice channel to play in".to_string())?;
 
     {
         use rand::thread_rng;
@@ -1068,41 +1392,148 @@ async fn shuffle(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
         write_lock.extend(vec);
     }
 
-    let manager = songbird::get(ctx)
-        .await
-        .expect("Songbird Voice client placed in at initialisation.")
-        .clone();
+    let handler_lock = qctx.manager.get(guild_id)
+        .ok_or_else(|| "Not in a voice channel to play in".to_string())?;
 
-    let handler_lock = match manager.get(guild_id) {
-        Some(x) => x,
-        None => {
+    let mut call = handler_lock.lock().await;
+    let queue = call.queue();
+    let _ = queue.skip();
+
+    Ok("shuffled.".to_string())
+}
+
+/// Queued items whose title or URI contains `needle`, at most `limit`.
+pub(crate) async fn search_queue(ctx: &Context, guild_id: GuildId, needle: &str, limit: usize) -> Vec<QueueItem> {
+    let qctx = match queue_context(ctx, guild_id).await {
+        Some(qctx) => qctx,
+        None => return Vec::new(),
+    };
+
+    let needle = needle.to_lowercase();
+    let queue = qctx.cold_queue.read().await;
+    queue.iter()
+        .filter(|item| item.title().to_lowercase().contains(&needle) || item.uri.contains(&needle))
+        .take(limit)
+        .cloned()
+        .collect()
+}
+
+/// Say `reply` in `msg`'s channel, whether it's the result or the error.
+async fn say_reply(ctx: &Context, msg: &Message, reply: Result<String, String>) -> CommandResult {
+    let text = match reply {
+        Ok(x) | Err(x) => x,
+    };
+    msg.channel_id.say(&ctx.http, text).await?;
+    Ok(())
+}
+
+#[command]
+#[aliases("np", "playing", "now-playing", "playing-now", "nowplaying")]
+#[only_in(guilds)]
+async fn now_playing(ctx: &Context, msg: &Message) -> CommandResult {
+    tracing::info!(
+        "[{}::{}] asked what track is playing in [{}::{:?}]",
+        msg.author.id, msg.author.name,
+        msg.channel_id, msg.channel_id.name(&ctx).await
+    );
+
+    let guild_id = msg.guild_id.ok_or(JoinError::NoCall)?;
+    say_reply(ctx, msg, now_playing_text(ctx, guild_id).await).await
+}
+
+#[command]
+#[only_in(guilds)]
+async fn join(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or(JoinError::NoCall)?;
+    say_reply(ctx, msg, join_voice(ctx, guild_id, msg.author.id, msg.channel_id).await).await
+}
+
+#[command]
+#[only_in(guilds)]
+async fn leave(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or(JoinError::NoCall)?;
+    say_reply(ctx, msg, leave_voice(ctx, guild_id).await).await
+}
+
+#[command]
+#[aliases("play", "p", "q")]
+#[only_in(guilds)]
+async fn queue(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    tracing::info!(
+        "[{}::{}] queued track in [{}::{:?}]",
+        msg.author.id, msg.author.name,
+        msg.channel_id, msg.channel_id.name(&ctx).await
+    );
+
+    let url = match args.single::<String>() {
+        Ok(url) => url,
+        Err(_) => {
             msg.channel_id
-               .say(&ctx.http, "Not in a voice channel to play in")
+               .say(&ctx.http, "Must provide a URL to a video or audio")
                .await?;
-            return Ok(())
-        }
+            return Ok(());
+        },
     };
 
-    msg.channel_id
-       .say(
-            &ctx.http,
-            format!("shuffled."),
-       )
-       .await?;
+    let guild_id = msg.guild_id.ok_or(JoinError::NoCall)?;
+    let reply = enqueue(ctx, guild_id, msg.author.id, msg.channel_id, Some(msg.id), &url).await;
+    say_reply(ctx, msg, reply).await
+}
 
-    let mut call = handler_lock.lock().await;
-    let queue = call.queue();
-    let _ = queue.skip();
+#[command]
+#[only_in(guilds)]
+async fn skip(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    tracing::info!(
+        "[{}::{}] skipped track in [{}::{:?}]",
+        msg.author.id, msg.author.name,
+        msg.channel_id, msg.channel_id.name(&ctx).await
+    );
 
+    let skipn = args.remains()
+        .unwrap_or("1")
+        .parse::<isize>()
+        .unwrap_or(1);
+
+    let guild_id = msg.guild_id.ok_or(JoinError::NoCall)?;
+    say_reply(ctx, msg, skip_tracks(ctx, guild_id, skipn).await).await
+}
+
+#[command]
+#[only_in(guilds)]
+async fn shuffle(ctx: &Context, msg: &Message) -> CommandResult {
+    tracing::info!(
+        "[{}::{}] shuffled playlist in [{}::{:?}]",
+        msg.author.id, msg.author.name,
+        msg.channel_id, msg.channel_id.name(&ctx).await
+    );
+
+    let guild_id = msg.guild_id.ok_or(JoinError::NoCall)?;
+    say_reply(ctx, msg, shuffle_queue(ctx, guild_id).await).await
+}
+
+/// Post the player message here, removing the old one.
+#[command]
+#[only_in(guilds)]
+async fn player(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg.guild_id.ok_or(JoinError::NoCall)?;
+    match queue_context(ctx, guild_id).await {
+        Some(qctx) => crate::controls::post(&qctx, msg.channel_id).await?,
+        None => { msg.channel_id.say(&ctx.http, "Not in a voice channel").await?; }
+    }
     Ok(())
 }
 
+#[cfg(feature = "set-arl-cmd")]
 #[group]
 #[commands(setarl, getarl)]
 struct Dangerous;
 
+/// ARLs are account credentials: only owners may handle them,
+/// and only in DMs, so they never land in a guild channel.
+#[cfg(feature = "set-arl-cmd")]
 #[command]
-#[only_in(guilds)]
+#[owners_only]
+#[only_in(dms)]
 async fn setarl(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
     tracing::info!("[{}::{}] set a new arl", msg.author.id, msg.author.name);
 
@@ -1113,33 +1544,52 @@ async fn setarl(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
         return Ok(())
     }
 
-    std::env::set_var("DEEMIX_ARL", arl);
-    msg.channel_id.say(&ctx.http, "**ARL has been set**").await?;
+    let pool = crate::arl::ArlPool::global();
+    match pool.add(&arl) {
+        Ok(true) if pool.persistent() => {
+            msg.channel_id.say(&ctx.http, "**ARL has been added to the pool**").await?;
+        }
+        Ok(true) => {
+            msg.channel_id.say(&ctx.http, "**ARL has been added to the pool**, but no secret key is configured so it won't survive a restart").await?;
+        }
+        Ok(false) => {
+            msg.channel_id.say(&ctx.http, "ARL is already in the pool").await?;
+            return Ok(())
+        }
+        Err(e) => {
+            tracing::error!("failed to store ARL: {}", e);
+            msg.channel_id.say(&ctx.http, format!("ARL added to the pool, but couldn't be stored: {}", e)).await?;
+        }
+    }
 
+    pool.check_all().await;
     return Ok(())
 }
 
+#[cfg(feature = "set-arl-cmd")]
 #[command]
-#[only_in(guilds)]
+#[owners_only]
+#[only_in(dms)]
 async fn getarl(ctx: &Context, msg: &Message) -> CommandResult {
     tracing::info!("[{}::{}] requested arl", msg.author.id, msg.author.name);
 
-    let arl = std::env::var("DEEMIX_ARL");
-    tracing::info!("getarl: {:?}", arl);
-    match arl {
-        Err(e) => { msg.channel_id.say(&ctx.http, format!("Error: {}", e)).await?; }
-        Ok(arl) if arl.is_empty() => { msg.channel_id.say(&ctx.http, "ARL not set").await?; }
-        Ok(arl) => {
-            #[cfg(feature = "check")]
-            {
-                msg.channel_id.say(&ctx.http, format!("getting arl data...")).await?;
-                use serenity::framework::standard::{Args, Delimiter};
-                let mut args = Args::new(arl.as_str(), &[Delimiter::Single(' ')]);
-                crate::check::arl_check(ctx, msg, args).await?;
-            }
-            #[cfg(not(feature = "check"))]
-            msg.channel_id.say(&ctx.http, format!("ARL: {}", &arl)).await?;
+    let arl = match crate::arl::ArlPool::global().current() {
+        Some(entry) => entry.arl,
+        None => {
+            msg.channel_id.say(&ctx.http, "No healthy ARL in the pool").await?;
+            return Ok(())
+        }
+    };
+
+    msg.channel_id.say(&ctx.http, "getting arl data...").await?;
+    match crate::check::check_arl(&arl).await {
+        Ok(check) => {
+            crate::render::send(&ctx.http, msg.channel_id, &arl, &check, crate::render::Format::Embed).await?;
+        }
+        Err(e) => {
+            msg.channel_id.say(&ctx.http, format!("ARL: {}\nError: {}", &arl, e)).await?;
         }
     }
+
     return Ok(())
 }
@@ -0,0 +1,345 @@
+//! Prebuffering of decoded PCM.
+//!
+//! ffmpeg's output is drained by a background thread into a bounded ring
+//! buffer. A load waits (asynchronously) until `MKBIRD_PREBUFFER_SECS` of
+//! audio are buffered, the source reaches EOF, or `MKBIRD_PREBUFFER_TIMEOUT`
+//! expires, whichever comes first. Playback then starts from the buffer.
+//!
+//! If the buffer runs dry during playback the reader waits briefly and then
+//! hands songbird silence instead of stalling the mixer, so a slow source
+//! stutters instead of freezing every track in the call.
+
+use std::{
+    collections::VecDeque,
+    io::Read,
+    sync::{
+        Arc,
+        Condvar,
+        Mutex,
+        OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Notify;
+
+/// 48kHz, stereo, f32le
+pub const BYTES_PER_SEC: usize = 48_000 * 2 * 4;
+const FRAME: usize = 2 * 4;
+const CHUNK_SIZE: usize = 16 * 1024;
+/// How long a read waits on an empty buffer before returning silence.
+const UNDERRUN_WAIT: Duration = Duration::from_millis(20);
+
+static METRICS: OnceLock<LoadMetrics> = OnceLock::new();
+
+#[derive(Debug, Clone)]
+pub struct PrebufferConfig {
+    /// Seconds of audio buffered before playback is released.
+    pub seconds: f64,
+    /// Upper bound on how long a load may wait for `seconds`.
+    pub deadline: Duration,
+    /// Size of the ring buffer, in seconds of audio.
+    pub capacity: f64,
+}
+
+impl Default for PrebufferConfig {
+    fn default() -> Self {
+        Self {
+            seconds: 3.0,
+            deadline: Duration::from_secs(10),
+            capacity: 20.0,
+        }
+    }
+}
+
+impl PrebufferConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let var = |name: &str| std::env::var(name)
+            .ok()
+            .and_then(|x| x.parse::<f64>().ok())
+            .filter(|x| *x >= 0.0);
+
+        let seconds = var("MKBIRD_PREBUFFER_SECS").unwrap_or(default.seconds);
+        Self {
+            seconds,
+            deadline: var("MKBIRD_PREBUFFER_TIMEOUT")
+                .map(Duration::from_secs_f64)
+                .unwrap_or(default.deadline),
+            capacity: var("MKBIRD_BUFFER_SECS")
+                .unwrap_or(default.capacity)
+                .max(seconds),
+        }
+    }
+
+    fn target_bytes(&self) -> usize {
+        (self.seconds * BYTES_PER_SEC as f64) as usize
+    }
+
+    fn capacity_bytes(&self) -> usize {
+        ((self.capacity * BYTES_PER_SEC as f64) as usize).max(CHUNK_SIZE)
+    }
+}
+
+/// Outcome of a single prebuffer.
+#[derive(Debug, Clone)]
+pub struct LoadReport {
+    pub load_time: Duration,
+    pub buffered: Duration,
+    pub timed_out: bool,
+    pub eof: bool,
+}
+
+/// Aggregate load metrics since startup.
+#[derive(Debug, Default)]
+pub struct LoadMetrics {
+    loads: AtomicU64,
+    timeouts: AtomicU64,
+    underruns: AtomicU64,
+    load_time_ms: AtomicU64,
+    max_load_time_ms: AtomicU64,
+}
+
+impl LoadMetrics {
+    pub fn global() -> &'static LoadMetrics {
+        METRICS.get_or_init(LoadMetrics::default)
+    }
+
+    fn record(&self, report: &LoadReport) {
+        let ms = report.load_time.as_millis() as u64;
+        self.loads.fetch_add(1, Ordering::Relaxed);
+        self.load_time_ms.fetch_add(ms, Ordering::Relaxed);
+        self.max_load_time_ms.fetch_max(ms, Ordering::Relaxed);
+        if report.timed_out {
+            self.timeouts.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn loads(&self) -> u64 { self.loads.load(Ordering::Relaxed) }
+    pub fn timeouts(&self) -> u64 { self.timeouts.load(Ordering::Relaxed) }
+    pub fn underruns(&self) -> u64 { self.underruns.load(Ordering::Relaxed) }
+
+    pub fn mean_load_time(&self) -> Duration {
+        match self.loads() {
+            0 => Duration::ZERO,
+            n => Duration::from_millis(self.load_time_ms.load(Ordering::Relaxed) / n),
+        }
+    }
+
+    pub fn max_load_time(&self) -> Duration {
+        Duration::from_millis(self.max_load_time_ms.load(Ordering::Relaxed))
+    }
+}
+
+struct Ring {
+    buf: VecDeque<u8>,
+    capacity: usize,
+    eof: bool,
+    closed: bool,
+    error: Option<std::io::Error>,
+}
+
+struct Shared {
+    ring: Mutex<Ring>,
+    readable: Condvar,
+    writable: Condvar,
+    progress: Notify,
+}
+
+/// Audio reader backed by a ring buffer which is filled in the background.
+///
+/// `G` is kept alive alongside the reader, normally the
+/// [`Pipeline`](crate::supervisor::Pipeline) producing the audio, so
+/// dropping the reader also stops the source.
+pub struct Prebuffered<G> {
+    shared: Arc<Shared>,
+    config: PrebufferConfig,
+    label: String,
+    underrunning: bool,
+    _guard: G,
+}
+
+impl<G> Prebuffered<G> {
+    pub fn new<R>(label: impl Into<String>, source: R, guard: G, config: PrebufferConfig) -> std::io::Result<Self>
+    where
+        R: Read + Send + 'static,
+    {
+        let label = label.into();
+        let shared = Arc::new(Shared {
+            ring: Mutex::new(Ring {
+                buf: VecDeque::with_capacity(config.capacity_bytes()),
+                capacity: config.capacity_bytes(),
+                eof: false,
+                closed: false,
+                error: None,
+            }),
+            readable: Condvar::new(),
+            writable: Condvar::new(),
+            progress: Notify::new(),
+        });
+
+        let filler = shared.clone();
+        std::thread::Builder::new()
+            .name(format!("{} prebuffer", label))
+            .spawn(move || fill(source, filler))?;
+
+        Ok(Self {
+            shared,
+            config,
+            label,
+            underrunning: false,
+            _guard: guard,
+        })
+    }
+
+    /// Wait until enough audio is buffered to start playback.
+    pub async fn wait(&self) -> LoadReport {
+        let now = Instant::now();
+        let deadline = tokio::time::Instant::now() + self.config.deadline;
+        let target = self.config.target_bytes();
+
+        let (buffered, timed_out, eof) = loop {
+            // register interest before checking, so progress isn't missed
+            let notified = self.shared.progress.notified();
+
+            let (buffered, eof) = {
+                let ring = self.shared.ring.lock().unwrap();
+                (ring.buf.len(), ring.eof)
+            };
+
+            if buffered >= target || eof {
+                break (buffered, false, eof);
+            }
+
+            if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                break (buffered, true, false);
+            }
+        };
+
+        let report = LoadReport {
+            load_time: now.elapsed(),
+            buffered: Duration::from_secs_f64(buffered as f64 / BYTES_PER_SEC as f64),
+            timed_out,
+            eof,
+        };
+
+        if timed_out {
+            tracing::warn!(
+                "[{}] prebuffer deadline hit after {:.3}s with {:.2}s buffered, starting anyway",
+                self.label, report.load_time.as_secs_f64(), report.buffered.as_secs_f64()
+            );
+        } else {
+            tracing::info!(
+                "[{}] load time: {:.3}s ({:.2}s buffered)",
+                self.label, report.load_time.as_secs_f64(), report.buffered.as_secs_f64()
+            );
+        }
+
+        LoadMetrics::global().record(&report);
+        report
+    }
+}
+
+fn fill<R: Read>(mut source: R, shared: Arc<Shared>) {
+    let mut chunk = [0u8; CHUNK_SIZE];
+
+    loop {
+        let result = source.read(&mut chunk);
+        let mut ring = shared.ring.lock().unwrap();
+
+        match result {
+            Ok(0) => { ring.eof = true; }
+            Ok(n) => {
+                while !ring.closed && ring.capacity - ring.buf.len() < n {
+                    ring = shared.writable.wait(ring).unwrap();
+                }
+                ring.buf.extend(&chunk[..n]);
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                ring.error = Some(e);
+                ring.eof = true;
+            }
+        }
+
+        let done = ring.eof || ring.closed;
+        drop(ring);
+        shared.readable.notify_all();
+        shared.progress.notify_one();
+
+        if done { break }
+    }
+}
+
+impl<G> Read for Prebuffered<G> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut ring = self.shared.ring.lock().unwrap();
+
+        if ring.buf.is_empty() && !ring.eof {
+            ring = self.shared.readable
+                .wait_timeout_while(ring, UNDERRUN_WAIT, |r| r.buf.is_empty() && !r.eof)
+                .unwrap()
+                .0;
+        }
+
+        if ring.buf.is_empty() {
+            if ring.eof {
+                return match ring.error.take() {
+                    Some(e) => Err(e),
+                    None => Ok(0),
+                };
+            }
+
+            // Underrun: keep the mixer fed with whole frames of silence.
+            let n = buf.len().min(CHUNK_SIZE) / FRAME * FRAME;
+            if n > 0 {
+                if !self.underrunning {
+                    tracing::warn!("[{}] buffer underrun, source is too slow", self.label);
+                    LoadMetrics::global().underruns.fetch_add(1, Ordering::Relaxed);
+                    self.underrunning = true;
+                }
+                buf[..n].fill(0);
+                return Ok(n);
+            }
+
+            ring = self.shared.readable
+                .wait_while(ring, |r| r.buf.is_empty() && !r.eof)
+                .unwrap();
+
+            if ring.buf.is_empty() {
+                return Ok(0);
+            }
+        }
+
+        self.underrunning = false;
+        let mut n = buf.len().min(ring.buf.len());
+        // hand out whole frames, so silence never lands mid-sample
+        if n >= FRAME {
+            n = n / FRAME * FRAME;
+        }
+        let (head, tail) = ring.buf.as_slices();
+        if n <= head.len() {
+            buf[..n].copy_from_slice(&head[..n]);
+        } else {
+            buf[..head.len()].copy_from_slice(head);
+            buf[head.len()..n].copy_from_slice(&tail[..n - head.len()]);
+        }
+        ring.buf.drain(..n);
+
+        drop(ring);
+        self.shared.writable.notify_one();
+        Ok(n)
+    }
+}
+
+impl<G> Drop for Prebuffered<G> {
+    fn drop(&mut self) {
+        self.shared.ring.lock().unwrap().closed = true;
+        self.shared.writable.notify_all();
+    }
+}
@@ -0,0 +1,317 @@
+//! Output formats for ARL checks.
+//!
+//! A check can be sent as a Discord embed, a Markdown table, JSON or CSV.
+//! Each format implements [`Render`]; [`send`] picks one and posts it,
+//! with JSON and CSV attached as files.
+
+use chrono::prelude::*;
+use serde_json::{json, Value};
+use serenity::{
+    builder::CreateEmbed,
+    http::Http,
+    model::{channel::Message, id::ChannelId},
+};
+
+use crate::check::{ExtractChecks, SoundQuality};
+
+const RED: u32    = 0x00FF0000;
+const GREEN: u32  = 0x0000FF00;
+const YELLOW: u32 = 0x00FFFF00;
+const BLANKSPACE: &str = "\x20"; // 0x20
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Embed,
+    Markdown,
+    Json,
+    Csv,
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Embed => "txt",
+            Self::Markdown => "md",
+            Self::Json => "json",
+            Self::Csv => "csv",
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "embed" => Ok(Self::Embed),
+            "md" | "markdown" => Ok(Self::Markdown),
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => Err(format!("unknown format {:?}, expected embed, md, json or csv", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Embed => write!(f, "embed"),
+            Self::Markdown => write!(f, "md"),
+            Self::Json => write!(f, "json"),
+            Self::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+pub trait Render {
+    type Output;
+
+    fn render(&self, arl: &str, check: &ExtractChecks) -> Self::Output;
+}
+
+pub struct EmbedRenderer;
+pub struct MarkdownRenderer;
+pub struct JsonRenderer;
+pub struct CsvRenderer;
+
+/// Left-align `rows` into columns separated by two spaces, like `column -t`.
+/// Widths count characters, so `✓` lines up with ASCII.
+pub fn table(rows: &[Vec<String>]) -> String {
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let widths = (0..columns)
+        .map(|i| rows.iter()
+            .filter_map(|r| r.get(i))
+            .map(|x| x.chars().count())
+            .max()
+            .unwrap_or(0))
+        .collect::<Vec<_>>();
+
+    rows.iter()
+        .map(|row| {
+            let line = row.iter()
+                .enumerate()
+                .map(|(i, x)| format!("{}{}", x, " ".repeat(widths[i] - x.chars().count())))
+                .collect::<Vec<_>>()
+                .join("  ");
+            line.trim_end().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Quality × device matrix, header first.
+pub fn sound_quality_rows(check: &ExtractChecks) -> Vec<Vec<String>> {
+    fn f(x: bool) -> String {
+        if x { "✓" } else { "✗" }.to_string()
+    }
+
+    fn flags(x: &SoundQuality) -> [bool; 5] {
+        [x.reality, x.lossless, x.high, x.standard, x.low]
+    }
+
+    let (mobile, tablet, web) = (flags(&check.mobile_sq), flags(&check.tablet_sq), flags(&check.web_sq));
+
+    let mut rows = vec![
+        ["Quality", "Mobile", "Tablet", "Web"].map(String::from).to_vec()
+    ];
+    for (i, name) in ["Reality", "Lossless", "High", "Standard", "Low"].iter().enumerate() {
+        rows.push(vec![name.to_string(), f(mobile[i]), f(tablet[i]), f(web[i])]);
+    }
+    rows
+}
+
+pub fn sound_quality_table(check: &ExtractChecks) -> String {
+    table(&sound_quality_rows(check))
+}
+
+fn expiration(check: &ExtractChecks) -> Option<DateTime<Utc>> {
+    check.expiration
+        .and_then(|x| NaiveDateTime::from_timestamp_opt(x, 0))
+        .map(|naive| DateTime::<Utc>::from_utc(naive, Utc))
+}
+
+fn unknown(x: &Option<String>) -> String {
+    x.clone().unwrap_or_else(|| "unknown".to_string())
+}
+
+impl Render for EmbedRenderer {
+    type Output = CreateEmbed;
+
+    fn render(&self, arl: &str, check: &ExtractChecks) -> CreateEmbed {
+        fn checkmark(check: bool) -> &'static str {
+            if check { ":white_check_mark:" }
+            else { ":x:" }
+        }
+
+        let color = if check.dank()
+          { GREEN }
+        else if check.lossless() && check.explicit()
+          { YELLOW }
+        else
+          { RED };
+
+        let explicit = checkmark(check.explicit());
+        let lossless = checkmark(check.lossless());
+        let country_checkmark = checkmark(check.country.eq_ignore_ascii_case("us"));
+
+        let (expiredate, expire_checkmark) = match expiration(check) {
+            Some(datetime) => (
+                datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
+                checkmark(datetime > Utc::now())
+            ),
+            None => ("unknown".to_string(), checkmark(false)),
+        };
+
+        let mut e = CreateEmbed::default();
+        e.title("ARL Check")
+            .color(color)
+            .description(arl)
+            .fields(vec![
+                (format!("Allows Explicit: {explicit}"), BLANKSPACE.to_string(), false),
+                (format!("Allows Lossless: {lossless}"), BLANKSPACE.to_string(), false),
+                (format!("Country: {} {}", check.country, country_checkmark), BLANKSPACE.to_string(), false),
+                (format!("Inscription date: {}", unknown(&check.inscription)), BLANKSPACE.to_string(), false),
+                (format!("Expiration: {expiredate} {expire_checkmark}"), BLANKSPACE.to_string(), false),
+                (format!("Email: {}", unknown(&check.email)), BLANKSPACE.to_string(), false),
+                (format!("Offer {} ({})", unknown(&check.offer_name), check.offer_id), BLANKSPACE.to_string(), false),
+                (BLANKSPACE.to_string(), format!("```\n{}\n```", sound_quality_table(check)), false),
+            ])
+            .footer(|f| f.text("**Deezer uses Mobile API.**"));
+        e
+    }
+}
+
+impl Render for MarkdownRenderer {
+    type Output = String;
+
+    fn render(&self, arl: &str, check: &ExtractChecks) -> String {
+        fn row(cells: &[String]) -> String {
+            format!("| {} |", cells.join(" | "))
+        }
+
+        let expiredate = expiration(check)
+            .map(|x| x.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or("unknown".to_string());
+
+        let properties = [
+            ("Name", check.name.clone()),
+            ("Email", unknown(&check.email)),
+            ("Country", check.country.clone()),
+            ("Offer", format!("{} ({})", unknown(&check.offer_name), check.offer_id)),
+            ("Premium", check.premium().to_string()),
+            ("Explicit", check.explicit().to_string()),
+            ("Lossless", check.lossless().to_string()),
+            ("Inscription date", unknown(&check.inscription)),
+            ("Expiration", expiredate),
+        ];
+
+        let mut lines = vec![
+            format!("**ARL Check** `{}`", arl),
+            String::new(),
+            "| Property | Value |".to_string(),
+            "| --- | --- |".to_string(),
+        ];
+        lines.extend(properties.iter().map(|(k, v)| row(&[k.to_string(), v.clone()])));
+        lines.push(String::new());
+
+        let quality = sound_quality_rows(check);
+        lines.push(row(&quality[0]));
+        lines.push(row(&vec!["---".to_string(); quality[0].len()]));
+        lines.extend(quality[1..].iter().map(|r| row(r)));
+
+        lines.join("\n")
+    }
+}
+
+impl Render for JsonRenderer {
+    type Output = Value;
+
+    fn render(&self, arl: &str, check: &ExtractChecks) -> Value {
+        json!({
+            "arl": arl,
+            "user_id": check.user_id,
+            "name": check.name,
+            "email": check.email,
+            "country": check.country,
+            "offer_id": check.offer_id,
+            "offer_name": check.offer_name,
+            "premium": check.premium(),
+            "explicit": check.explicit(),
+            "lossless": check.lossless(),
+            "expiration": check.expiration,
+            "inscription": check.inscription,
+            "default_sound_quality": check.default_sound_quality,
+            "sound_quality": {
+                "mobile": check.mobile_sq,
+                "tablet": check.tablet_sq,
+                "web": check.web_sq,
+            },
+        })
+    }
+}
+
+impl CsvRenderer {
+    pub const HEADER: &'static str =
+        "arl,user_id,name,email,country,offer_id,offer_name,premium,explicit,lossless,expiration,inscription";
+
+    pub(crate) fn escape(field: &str) -> String {
+        if field.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    }
+
+    /// One record, without the header or a trailing newline.
+    pub fn row(&self, arl: &str, check: &ExtractChecks) -> String {
+        let opt = |x: &Option<String>| x.clone().unwrap_or_default();
+        [
+            arl.to_string(),
+            check.user_id.to_string(),
+            check.name.clone(),
+            opt(&check.email),
+            check.country.clone(),
+            check.offer_id.to_string(),
+            opt(&check.offer_name),
+            check.premium().to_string(),
+            check.explicit().to_string(),
+            check.lossless().to_string(),
+            check.expiration.map(|x| x.to_string()).unwrap_or_default(),
+            opt(&check.inscription),
+        ]
+        .iter()
+        .map(|x| Self::escape(x))
+        .collect::<Vec<_>>()
+        .join(",")
+    }
+}
+
+impl Render for CsvRenderer {
+    type Output = String;
+
+    fn render(&self, arl: &str, check: &ExtractChecks) -> String {
+        format!("{}\n{}\n", Self::HEADER, self.row(arl, check))
+    }
+}
+
+/// Post `check` to `channel` as `format`.
+pub async fn send(
+    http: &Http,
+    channel: ChannelId,
+    arl: &str,
+    check: &ExtractChecks,
+    format: Format,
+) -> serenity::Result<Message> {
+    let attachment = match format {
+        Format::Embed => {
+            let embed = EmbedRenderer.render(arl, check);
+            return channel.send_message(http, |m| m.set_embed(embed)).await;
+        }
+        Format::Markdown => return channel.say(http, MarkdownRenderer.render(arl, check)).await,
+        Format::Json => serde_json::to_string_pretty(&JsonRenderer.render(arl, check))?,
+        Format::Csv => CsvRenderer.render(arl, check),
+    };
+
+    let name = format!("{}.{}", arl, format.extension());
+    channel.send_files(http, vec![(attachment.as_bytes(), name.as_str())], |m| m).await
+}
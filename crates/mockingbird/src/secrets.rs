@@ -0,0 +1,169 @@
+//! Encrypted-at-rest storage for ARLs.
+//!
+//! ARLs added at runtime are sealed with XChaCha20-Poly1305 and written to
+//! `MKBIRD_SECRET_STORE` (default `mockingbird.secrets`) with mode `0600`.
+//! The 32 byte key is read, as 64 hex characters, from `MKBIRD_SECRET_KEY`
+//! or from the file named by `MKBIRD_SECRET_KEY_FILE` (raw or hex). Without
+//! a key nothing is written and runtime ARLs are lost on restart.
+//!
+//! File layout: `nonce (24 bytes) || ciphertext`, where the plaintext is a
+//! JSON list of ARLs.
+
+use std::{
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use chacha20poly1305::{
+    aead::{Aead, NewAead},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use rand::RngCore;
+
+const NONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
+const DEFAULT_STORE: &str = "mockingbird.secrets";
+
+#[derive(Debug)]
+pub enum SecretError {
+    /// The key isn't 32 bytes (or 64 hex characters).
+    BadKey,
+    /// The store was tampered with, or sealed with another key.
+    Decrypt,
+    Encrypt,
+    IO(std::io::Error),
+    Json(serde_json::Error),
+}
+
+impl std::fmt::Display for SecretError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SecretError::BadKey => write!(f, "secret key must be 32 bytes or 64 hex characters"),
+            SecretError::Decrypt => write!(f, "couldn't decrypt secret store (wrong key or corrupt)"),
+            SecretError::Encrypt => write!(f, "couldn't encrypt secret store"),
+            SecretError::IO(e) => write!(f, "secret store IO error: {}", e),
+            SecretError::Json(e) => write!(f, "secret store is malformed: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for SecretError {
+    fn from(e: std::io::Error) -> Self {
+        SecretError::IO(e)
+    }
+}
+
+impl From<serde_json::Error> for SecretError {
+    fn from(e: serde_json::Error) -> Self {
+        SecretError::Json(e)
+    }
+}
+
+impl std::error::Error for SecretError {}
+
+fn parse_key(raw: &[u8]) -> Result<[u8; KEY_LEN], SecretError> {
+    if raw.len() == KEY_LEN {
+        let mut key = [0u8; KEY_LEN];
+        key.copy_from_slice(raw);
+        return Ok(key);
+    }
+
+    let text = std::str::from_utf8(raw)
+        .map_err(|_| SecretError::BadKey)?
+        .trim();
+
+    if text.len() != KEY_LEN * 2 || !text.is_ascii() {
+        return Err(SecretError::BadKey);
+    }
+
+    let mut key = [0u8; KEY_LEN];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16)
+            .map_err(|_| SecretError::BadKey)?;
+    }
+    Ok(key)
+}
+
+pub struct SecretStore {
+    path: PathBuf,
+    cipher: XChaCha20Poly1305,
+}
+
+impl SecretStore {
+    pub fn new(path: impl Into<PathBuf>, key: [u8; KEY_LEN]) -> Self {
+        Self {
+            path: path.into(),
+            cipher: XChaCha20Poly1305::new(Key::from_slice(&key)),
+        }
+    }
+
+    /// Open the store configured in the environment.
+    /// `Ok(None)` if no key is configured.
+    pub fn from_env() -> Result<Option<Self>, SecretError> {
+        let key = match (std::env::var("MKBIRD_SECRET_KEY"), std::env::var("MKBIRD_SECRET_KEY_FILE")) {
+            (Ok(key), _) => parse_key(key.as_bytes())?,
+            (_, Ok(file)) => parse_key(&std::fs::read(file)?)?,
+            _ => return Ok(None),
+        };
+
+        // children have no use for it
+        std::env::remove_var("MKBIRD_SECRET_KEY");
+
+        let path = std::env::var("MKBIRD_SECRET_STORE")
+            .unwrap_or(DEFAULT_STORE.to_string());
+
+        Ok(Some(Self::new(path, key)))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Every stored ARL. A missing store is empty.
+    pub fn load(&self) -> Result<Vec<String>, SecretError> {
+        let sealed = match std::fs::read(&self.path) {
+            Ok(x) => x,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        if sealed.len() < NONCE_LEN {
+            return Err(SecretError::Decrypt);
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let plaintext = self.cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| SecretError::Decrypt)?;
+
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+
+    /// Replace the store's contents with `arls`.
+    pub fn save(&self, arls: &[String]) -> Result<(), SecretError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let plaintext = serde_json::to_vec(arls)?;
+        let ciphertext = self.cipher
+            .encrypt(XNonce::from_slice(&nonce), plaintext.as_slice())
+            .map_err(|_| SecretError::Encrypt)?;
+
+        // write next to the store and rename, so a crash never leaves it half written
+        let tmp = self.path.with_extension("tmp");
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)?;
+
+        file.write_all(&nonce)?;
+        file.write_all(&ciphertext)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, &self.path)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+//! Keeping voice sessions across a restart.
+//!
+//! [`shutdown`] tells every music channel the bot is restarting, writes
+//! each guild's voice channel, volume and queue (the playing track first,
+//! then any songbird has already loaded) to `MKBIRD_QUEUE_FILE`, and
+//! leaves through `leave_routine`. Once the bot is back, [`restore`]
+//! rejoins those channels and plays the queues. The file is removed as it
+//! is read, so a queue is only restored once.
+
+use std::{
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc},
+};
+
+use serde_json::{json, Value};
+use serenity::{
+    model::id::{ChannelId, GuildId, UserId},
+    prelude::*,
+};
+
+use crate::player::{self, LazyQueueKey, Players, QueueContext, QueueItem, QueueItemKey};
+
+pub const DEFAULT_PATH: &str = "mockingbird-queues.json";
+
+fn path() -> PathBuf {
+    std::env::var("MKBIRD_QUEUE_FILE")
+        .unwrap_or_else(|_| DEFAULT_PATH.to_string())
+        .into()
+}
+
+/// A guild's voice session, as saved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub guild_id: GuildId,
+    pub voice_channel: ChannelId,
+    pub invited_from: ChannelId,
+    pub volume: u8,
+    /// `(uri, requester, requested from)`, the playing track first.
+    pub tracks: Vec<(String, UserId, ChannelId)>,
+}
+
+impl Session {
+    async fn read(qctx: &QueueContext) -> Self {
+        // the playing track, then any already loaded ahead of the cold queue
+        let mut queued = Vec::new();
+        if let Some(call) = qctx.manager.get(qctx.guild_id) {
+            let handles = call.lock().await.queue().current_queue();
+            for track in handles {
+                if let Some(item) = track.typemap().read().await.get::<QueueItemKey>() {
+                    queued.push(item.clone());
+                }
+            }
+        }
+
+        let tracks = saved_tracks(&queued, qctx.cold_queue.read().await.iter());
+
+        Self {
+            guild_id: qctx.guild_id,
+            voice_channel: qctx.voice_chan_id.id,
+            invited_from: qctx.invited_from,
+            volume: qctx.volume.load(Ordering::Relaxed),
+            tracks,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "guild_id": self.guild_id.0,
+            "voice_channel": self.voice_channel.0,
+            "invited_from": self.invited_from.0,
+            "volume": self.volume,
+            "tracks": self.tracks.iter()
+                .map(|(uri, requester, channel)| json!({
+                    "uri": uri,
+                    "requester": requester.0,
+                    "channel": channel.0,
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    /// `None` if `value` isn't a saved session.
+    pub fn from_json(value: &Value) -> Option<Self> {
+        let id = |v: &Value, key: &str| v.get(key).and_then(Value::as_u64);
+
+        Some(Self {
+            guild_id: GuildId(id(value, "guild_id")?),
+            voice_channel: ChannelId(id(value, "voice_channel")?),
+            invited_from: ChannelId(id(value, "invited_from")?),
+            volume: id(value, "volume").and_then(|x| u8::try_from(x).ok()).unwrap_or(100),
+            tracks: value.get("tracks")?
+                .as_array()?
+                .iter()
+                .filter_map(|t| Some((
+                    t.get("uri")?.as_str()?.to_string(),
+                    UserId(id(t, "requester")?),
+                    ChannelId(id(t, "channel")?),
+                )))
+                .collect(),
+        })
+    }
+}
+
+/// The tracks to save: those in songbird's queue, in order, then the cold queue.
+pub(crate) fn saved_tracks<'a>(
+    queued: impl IntoIterator<Item = &'a QueueItem>,
+    cold: impl IntoIterator<Item = &'a QueueItem>,
+) -> Vec<(String, UserId, ChannelId)> {
+    queued.into_iter()
+        .chain(cold)
+        .map(|item| (item.uri.clone(), item.requester, item.channel))
+        .collect()
+}
+
+/// Announce the restart, save every session and leave voice.
+pub async fn shutdown(data: &Arc<RwLock<TypeMap>>, reason: &str) {
+    let queues = data.read().await
+        .get::<LazyQueueKey>()
+        .map(|x| x.values().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let mut sessions = Vec::new();
+    for qctx in queues {
+        let session = Session::read(&qctx).await;
+
+        let _ = qctx.invited_from
+            .say(&qctx.http, format!(
+                "**Restarting** ({}), back in a moment. {} track(s) will pick up where they left off.",
+                reason, session.tracks.len()
+            ))
+            .await;
+
+        if let Err(e) = player::leave_routine(qctx.data.clone(), qctx.guild_id, qctx.manager.clone()).await {
+            tracing::error!("couldn't leave voice in {}: {:?}", qctx.guild_id, e);
+        }
+        sessions.push(session);
+    }
+
+    if sessions.is_empty() {
+        return;
+    }
+
+    let path = path();
+    let body = Value::Array(sessions.iter().map(Session::to_json).collect());
+    let tmp = path.with_extension("tmp");
+    let saved = std::fs::write(&tmp, body.to_string()).and_then(|_| std::fs::rename(&tmp, &path));
+
+    match saved {
+        Ok(()) => tracing::info!("saved {} voice session(s) to {}", sessions.len(), path.display()),
+        Err(e) => tracing::error!("couldn't save voice sessions to {}: {}", path.display(), e),
+    }
+}
+
+/// Rejoin and play what [`shutdown`] saved.
+pub async fn restore(ctx: &Context) {
+    let path = path();
+    let data = match std::fs::read(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => {
+            tracing::error!("couldn't read voice sessions from {}: {}", path.display(), e);
+            return;
+        }
+    };
+    // never restore the same sessions twice, even if this fails
+    let _ = std::fs::remove_file(&path);
+
+    let sessions = serde_json::from_slice::<Value>(&data)
+        .ok()
+        .and_then(|x| x.as_array().cloned())
+        .unwrap_or_default();
+
+    for session in sessions.iter().filter_map(Session::from_json) {
+        if let Err(e) = resume(ctx, &session).await {
+            tracing::error!("couldn't restore the voice session in {}: {}", session.guild_id, e);
+        }
+    }
+}
+
+async fn resume(ctx: &Context, session: &Session) -> Result<(), String> {
+    let qctx = player::join_channel(ctx, session.guild_id, session.voice_channel, session.invited_from)
+        .await
+        .map_err(|e| format!("{:?}", e))?;
+    qctx.volume.store(session.volume, Ordering::Relaxed);
+
+    let items = session.tracks.iter()
+        .filter_map(|(uri, requester, channel)| {
+            let source = Players::from_str(uri)?;
+            Some(QueueItem::new(uri.clone(), source, *requester, *channel, None))
+        })
+        .collect::<Vec<_>>();
+
+    let _ = session.invited_from
+        .say(&ctx.http, format!("**Back**, resuming {} track(s)", items.len()))
+        .await;
+
+    qctx.cold_queue.write().await.extend(items.iter().cloned());
+    tokio::spawn(player::fill_metadata(items));
+
+    player::play_routine(qctx).await.map_err(|e| e.to_string())
+}
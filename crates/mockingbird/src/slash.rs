@@ -0,0 +1,370 @@
+//! Application (slash) commands for the player and ARL checks.
+//!
+//! `register_player` and `register_arl` add the commands enabled by this
+//! build, [`handle`] and [`autocomplete`] answer the interactions meant for
+//! them and return `None` for anything else, as does [`component`] for the
+//! player's buttons.
+//! Replies are deferred, since loading a track or checking an ARL can take
+//! longer than Discord's three seconds, and errors are only shown to
+//! whoever ran the command.
+
+use serenity::{
+    builder::{CreateApplicationCommands, CreateEmbed},
+    model::application::{
+        command::CommandOptionType,
+        interaction::{
+            application_command::{ApplicationCommandInteraction, CommandDataOptionValue},
+            autocomplete::AutocompleteInteraction,
+            message_component::MessageComponentInteraction,
+            InteractionResponseType,
+        },
+    },
+    model::channel::AttachmentType,
+    prelude::*,
+};
+
+/// Choices offered by autocomplete, Discord's limit.
+#[cfg(feature = "controller")]
+const MAX_CHOICES: usize = 25;
+/// Longest choice name or value Discord accepts.
+#[cfg(feature = "controller")]
+const MAX_CHOICE_LEN: usize = 100;
+
+/// A successful reply.
+#[derive(Default)]
+pub struct Reply {
+    pub content: Option<String>,
+    pub embeds: Vec<CreateEmbed>,
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+impl Reply {
+    pub fn text(content: impl Into<String>) -> Self {
+        Self { content: Some(content.into()), ..Default::default() }
+    }
+
+    pub fn embed(embed: CreateEmbed) -> Self {
+        Self { embeds: vec![embed], ..Default::default() }
+    }
+
+    pub fn file(name: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        Self { files: vec![(name.into(), data.into())], ..Default::default() }
+    }
+
+    /// Add `other`'s content, embeds and files to this reply.
+    pub fn extend(&mut self, other: Reply) {
+        self.content = match (self.content.take(), other.content) {
+            (Some(a), Some(b)) => Some(format!("{}\n{}", a, b)),
+            (a, b) => a.or(b),
+        };
+        self.embeds.extend(other.embeds);
+        self.files.extend(other.files);
+    }
+}
+
+/// Defer `cmd`, run `reply` and send what it returns.
+/// Errors replace the deferred reply with one only the caller sees.
+async fn respond<F>(ctx: &Context, cmd: &ApplicationCommandInteraction, deferred_ephemeral: bool, reply: F) -> serenity::Result<()>
+where
+    F: std::future::Future<Output = Result<Reply, String>>,
+{
+    cmd.create_interaction_response(&ctx.http, |r| r
+        .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+        .interaction_response_data(|d| d.ephemeral(deferred_ephemeral))
+    ).await?;
+
+    let (reply, ephemeral) = match reply.await {
+        Ok(reply) => (reply, deferred_ephemeral),
+        Err(e) => (Reply::text(e), true),
+    };
+
+    // the deferred reply can't take files or change who sees it,
+    // so those are sent as a follow up instead
+    if reply.files.is_empty() && ephemeral == deferred_ephemeral {
+        cmd.edit_original_interaction_response(&ctx.http, |r| {
+            if let Some(content) = &reply.content {
+                r.content(content);
+            }
+            r.add_embeds(reply.embeds.clone())
+        }).await?;
+        return Ok(());
+    }
+
+    cmd.delete_original_interaction_response(&ctx.http).await?;
+    cmd.create_followup_message(&ctx.http, |f| {
+        if let Some(content) = &reply.content {
+            f.content(content);
+        }
+        f.ephemeral(ephemeral)
+            .add_embeds(reply.embeds.clone())
+            .add_files(reply.files.iter().map(|(name, data)| AttachmentType::Bytes {
+                data: data.clone().into(),
+                filename: name.clone(),
+            }))
+    }).await?;
+
+    Ok(())
+}
+
+fn option<'a>(cmd: &'a ApplicationCommandInteraction, name: &str) -> Option<&'a CommandDataOptionValue> {
+    cmd.data.options
+        .iter()
+        .find(|x| x.name == name)
+        .and_then(|x| x.resolved.as_ref())
+}
+
+fn string_option(cmd: &ApplicationCommandInteraction, name: &str) -> Option<String> {
+    match option(cmd, name) {
+        Some(CommandDataOptionValue::String(x)) => Some(x.clone()),
+        _ => None,
+    }
+}
+
+/// Add the player commands.
+#[cfg(feature = "controller")]
+pub fn register_player(commands: &mut CreateApplicationCommands) -> &mut CreateApplicationCommands {
+    commands
+        .create_application_command(|c| c
+            .name("join").description("Join your voice channel").dm_permission(false))
+        .create_application_command(|c| c
+            .name("leave").description("Leave the voice channel").dm_permission(false))
+        .create_application_command(|c| c
+            .name("play").description("Queue a track, album or playlist").dm_permission(false)
+            .create_option(|o| o
+                .name("url")
+                .description("Link to queue, or search the queue to queue a track again")
+                .kind(CommandOptionType::String)
+                .required(true)
+                .set_autocomplete(true)))
+        .create_application_command(|c| c
+            .name("now-playing").description("Show the track that is playing").dm_permission(false))
+        .create_application_command(|c| c
+            .name("skip").description("Skip tracks").dm_permission(false)
+            .create_option(|o| o
+                .name("count")
+                .description("How many tracks to skip, the current one included")
+                .kind(CommandOptionType::Integer)
+                .min_int_value(1)))
+        .create_application_command(|c| c
+            .name("shuffle").description("Shuffle the queue").dm_permission(false))
+        .create_application_command(|c| c
+            .name("player").description("Post the player controls here").dm_permission(false))
+}
+
+/// Add the ARL check commands.
+#[cfg(feature = "arl-cmd")]
+pub fn register_arl(commands: &mut CreateApplicationCommands) -> &mut CreateApplicationCommands {
+    commands
+        .create_application_command(|c| c
+            .name("arl").description("Check ARLs")
+            .create_option(|o| o
+                .name("arls")
+                .description("ARLs to check, separated by spaces")
+                .kind(CommandOptionType::String))
+            .create_option(|o| o
+                .name("file")
+                .description("A .txt of ARLs to check in bulk")
+                .kind(CommandOptionType::Attachment))
+            .create_option(|o| o
+                .name("format")
+                .description("How to show the result")
+                .kind(CommandOptionType::String)
+                .add_string_choice("embed", "embed")
+                .add_string_choice("markdown", "md")
+                .add_string_choice("json", "json")
+                .add_string_choice("csv", "csv")))
+        .create_application_command(|c| c
+            .name("arl-raw").description("Deezer's response for an ARL, and the parsed check")
+            .create_option(|o| o
+                .name("arl")
+                .description("ARL to look up")
+                .kind(CommandOptionType::String)
+                .required(true)))
+}
+
+/// Answer `cmd` if it is one of ours.
+pub async fn handle(ctx: &Context, cmd: &ApplicationCommandInteraction) -> Option<serenity::Result<()>> {
+    tracing::info!("[{}::{}] used /{}", cmd.user.id, cmd.user.name, cmd.data.name);
+
+    match cmd.data.name.as_str() {
+        #[cfg(feature = "controller")]
+        "join" | "leave" | "play" | "now-playing" | "skip" | "shuffle"
+            => Some(respond(ctx, cmd, false, player(ctx, cmd)).await),
+
+        #[cfg(feature = "controller")]
+        "player" => Some(respond(ctx, cmd, true, player(ctx, cmd)).await),
+
+        // ARLs are credentials, keep them out of the channel
+        #[cfg(feature = "arl-cmd")]
+        "arl" | "arl-raw"
+            => Some(respond(ctx, cmd, true, arl(ctx, cmd)).await),
+
+        _ => None,
+    }
+}
+
+/// Suggest choices for `ac` if it is one of ours.
+#[allow(unused_variables)]
+pub async fn autocomplete(ctx: &Context, ac: &AutocompleteInteraction) -> Option<serenity::Result<()>> {
+    match ac.data.name.as_str() {
+        #[cfg(feature = "controller")]
+        "play" => Some(play_choices(ctx, ac).await),
+        _ => None,
+    }
+}
+
+/// Answer `mci` if it is one of ours.
+#[allow(unused_variables)]
+pub async fn component(ctx: &Context, mci: &MessageComponentInteraction) -> Option<serenity::Result<()>> {
+    #[cfg(feature = "controller")]
+    if let Some(result) = crate::controls::handle(ctx, mci).await {
+        return Some(result);
+    }
+
+    None
+}
+
+#[cfg(feature = "controller")]
+async fn player(ctx: &Context, cmd: &ApplicationCommandInteraction) -> Result<Reply, String> {
+    use crate::player;
+
+    let guild_id = player::can_control(ctx, cmd.guild_id, cmd.user.id).await?;
+
+    let reply = match cmd.data.name.as_str() {
+        "join" => player::join_voice(ctx, guild_id, cmd.user.id, cmd.channel_id).await,
+        "leave" => player::leave_voice(ctx, guild_id).await,
+        "now-playing" => player::now_playing_text(ctx, guild_id).await,
+        "shuffle" => player::shuffle_queue(ctx, guild_id).await,
+        "player" => match player::queue_context(ctx, guild_id).await {
+            Some(qctx) => crate::controls::post(&qctx, cmd.channel_id)
+                .await
+                .map(|_| "Player posted".to_string())
+                .map_err(|e| e.to_string()),
+            None => Err("Not in a voice channel".to_string()),
+        },
+        "skip" => {
+            let count = match option(cmd, "count") {
+                Some(CommandDataOptionValue::Integer(x)) => *x as isize,
+                _ => 1,
+            };
+            player::skip_tracks(ctx, guild_id, count).await
+        }
+        "play" => {
+            let url = string_option(cmd, "url")
+                .ok_or_else(|| "Must provide a URL to a video or audio".to_string())?;
+            player::enqueue(ctx, guild_id, cmd.user.id, cmd.channel_id, None, url.trim()).await
+        }
+        _ => unreachable!(),
+    };
+
+    reply.map(Reply::text)
+}
+
+/// Links as typed, then queued tracks matching what was typed.
+#[cfg(feature = "controller")]
+async fn play_choices(ctx: &Context, ac: &AutocompleteInteraction) -> serenity::Result<()> {
+    fn clip(x: &str) -> String {
+        x.chars().take(MAX_CHOICE_LEN).collect()
+    }
+
+    let typed = ac.data.options
+        .iter()
+        .find(|x| x.focused)
+        .and_then(|x| x.value.as_ref())
+        .and_then(|x| x.as_str())
+        .unwrap_or_default()
+        .trim()
+        .to_string();
+
+    let mut choices = Vec::new();
+    if typed.starts_with("http") && typed.len() <= MAX_CHOICE_LEN {
+        choices.push((clip(&typed), typed.clone()));
+    }
+
+    if let Some(guild_id) = ac.guild_id {
+        let found = crate::player::search_queue(ctx, guild_id, &typed, MAX_CHOICES).await;
+        choices.extend(
+            found.into_iter()
+                .filter(|item| item.uri.len() <= MAX_CHOICE_LEN)
+                .map(|item| (clip(&item.title()), item.uri))
+        );
+    }
+
+    choices.truncate(MAX_CHOICES);
+    ac.create_autocomplete_response(&ctx.http, |r| {
+        for (name, value) in choices {
+            r.add_string_choice(name, value);
+        }
+        r
+    }).await
+}
+
+#[cfg(feature = "arl-cmd")]
+async fn arl(ctx: &Context, cmd: &ApplicationCommandInteraction) -> Result<Reply, String> {
+    use crate::bulk::{self, RateLimit, Summary};
+    use crate::check::{santitize_arl, BULK_THRESHOLD, DeezerGateway};
+    use crate::render::{self, Format, Render};
+
+    if cmd.data.name == "arl-raw" {
+        let arl = string_option(cmd, "arl").unwrap_or_default();
+        if santitize_arl(&arl).is_err() {
+            return Err("Invalid ARL".to_string());
+        }
+
+        let mut reply = Reply::default();
+        for (name, data) in crate::check::raw_report(arl.trim()).await.map_err(|e| e.to_string())? {
+            reply.extend(Reply::file(name, data));
+        }
+        return Ok(reply);
+    }
+
+    let format = string_option(cmd, "format")
+        .and_then(|x| x.parse::<Format>().ok())
+        .unwrap_or(Format::Embed);
+
+    let mut text = string_option(cmd, "arls").unwrap_or_default();
+    let attachment = match option(cmd, "file") {
+        Some(CommandDataOptionValue::Attachment(attachment)) => Some(attachment),
+        _ => None,
+    };
+    if let Some(attachment) = attachment {
+        text.push('\n');
+        text.push_str(&bulk::read_attachment(attachment).await?);
+    }
+
+    let (arls, invalid) = bulk::parse_arls(&text);
+    if arls.is_empty() {
+        return Err("No valid ARLs found".to_string());
+    }
+
+    if attachment.is_some() || arls.len() > BULK_THRESHOLD {
+        let results = bulk::check_many(&DeezerGateway::from_env(), arls, RateLimit::from_env()).await;
+        let (name, report) = bulk::report(&results, format).map_err(|e| e.to_string())?;
+
+        let mut reply = Reply::embed(Summary::new(&results).embed(invalid));
+        reply.extend(Reply::file(name, report));
+        return Ok(reply);
+    }
+
+    let mut reply = Reply::default();
+    for arl in arls {
+        let check = match crate::check::check_arl(&arl).await {
+            Ok(check) => check,
+            Err(e) => {
+                reply.extend(Reply::text(format!("Error: {}", e)));
+                continue
+            }
+        };
+
+        reply.extend(match format {
+            Format::Embed => Reply::embed(render::EmbedRenderer.render(&arl, &check)),
+            Format::Markdown => Reply::text(render::MarkdownRenderer.render(&arl, &check)),
+            Format::Json => Reply::file(
+                format!("{}.json", arl),
+                serde_json::to_string_pretty(&render::JsonRenderer.render(&arl, &check)).map_err(|e| e.to_string())?,
+            ),
+            Format::Csv => Reply::file(format!("{}.csv", arl), render::CsvRenderer.render(&arl, &check)),
+        });
+    }
+    Ok(reply)
+}
@@ -0,0 +1,381 @@
+//! Child process supervision.
+//!
+//! A [`Pipeline`] owns every process spawned for one track (for example
+//! `deemix-stream | ffmpeg`). All of them are placed in a single process
+//! group, so anything they spawn in turn is covered as well.
+//!
+//! When the pipeline is dropped (track skipped/stopped, `leave`, or a load
+//! that failed half way) the whole group is killed and a reaper thread
+//! collects the exit statuses. Processes that exited on their own with a
+//! non-zero status are logged together with the tail of their stderr.
+//!
+//! A watchdog kills the group when reading the pipeline's output (see
+//! [`Pipeline::watch`]) has been stuck for `MKBIRD_CHILD_TIMEOUT` seconds
+//! (default 300). Only a blocked read counts, so a paused track, whose
+//! output isn't being read, or a long one, whose reads keep returning,
+//! is left alone. A stall is what a hung `deemix-stream` or `ffmpeg`
+//! looks like, and catching it doesn't need a limit on track length.
+//! The watchdog also kills any pipeline older than `MKBIRD_CHILD_MAX_SECS`
+//! (default 21600, `0` for no limit), whatever it is doing, so nothing
+//! outlives a generous upper bound on how long one track can take.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Read,
+    os::unix::process::{CommandExt, ExitStatusExt},
+    process::{Child, ChildStderr, ChildStdout, Command, ExitStatus},
+    sync::{
+        Arc,
+        Mutex,
+        OnceLock,
+        atomic::{AtomicU64, Ordering},
+        mpsc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::budget::BudgetPermit;
+
+/// Bytes of stderr kept per child for error reports.
+const STDERR_TAIL: usize = 4096;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+const DEFAULT_MAX_AGE: Duration = Duration::from_secs(6 * 60 * 60);
+/// Longest the watchdog sleeps between checks.
+const WATCHDOG_TICK: Duration = Duration::from_secs(5);
+
+/// When the read of a pipeline's output now in progress started.
+type Reading = Arc<Mutex<Option<Instant>>>;
+
+static SUPERVISOR: OnceLock<Supervisor> = OnceLock::new();
+
+#[derive(Debug, Clone)]
+pub struct PipelineInfo {
+    pub id: u64,
+    pub label: String,
+    pub pids: Vec<u32>,
+    pub started: Instant,
+}
+
+/// Registry of every live pipeline.
+pub struct Supervisor {
+    next_id: AtomicU64,
+    live: Mutex<HashMap<u64, PipelineInfo>>,
+    timeout: Duration,
+    max_age: Option<Duration>,
+}
+
+impl Supervisor {
+    pub fn global() -> &'static Supervisor {
+        SUPERVISOR.get_or_init(|| Supervisor {
+            next_id: AtomicU64::new(0),
+            live: Mutex::new(HashMap::new()),
+            timeout: std::env::var("MKBIRD_CHILD_TIMEOUT")
+                .ok()
+                .and_then(|x| x.parse::<u64>().ok())
+                .filter(|x| *x > 0)
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_TIMEOUT),
+            max_age: match std::env::var("MKBIRD_CHILD_MAX_SECS").map(|x| x.parse::<u64>()) {
+                Ok(Ok(0)) => None,
+                Ok(Ok(secs)) => Some(Duration::from_secs(secs)),
+                _ => Some(DEFAULT_MAX_AGE),
+            },
+        })
+    }
+
+    pub fn pipelines(&self) -> Vec<PipelineInfo> {
+        let mut pipelines = self.live.lock().unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        pipelines.sort_by_key(|p| p.id);
+        pipelines
+    }
+
+    fn register(&self, label: &str) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.live.lock().unwrap().insert(id, PipelineInfo {
+            id,
+            label: label.to_string(),
+            pids: Vec::new(),
+            started: Instant::now(),
+        });
+        id
+    }
+
+    fn add_pid(&self, id: u64, pid: u32) {
+        if let Some(info) = self.live.lock().unwrap().get_mut(&id) {
+            info.pids.push(pid);
+        }
+    }
+
+    fn unregister(&self, id: u64) {
+        self.live.lock().unwrap().remove(&id);
+    }
+}
+
+struct Supervised {
+    name: String,
+    child: Child,
+    stderr: Option<Arc<Mutex<VecDeque<u8>>>>,
+}
+
+impl Supervised {
+    fn stderr_tail(&self) -> String {
+        self.stderr.as_ref()
+            .map(|tail| String::from_utf8_lossy(tail.lock().unwrap().make_contiguous()).to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// Processes spawned for a single track.
+/// Reading from the pipeline reads the stdout of its final stage.
+pub struct Pipeline {
+    id: u64,
+    label: String,
+    pgid: Option<i32>,
+    children: Vec<Supervised>,
+    stdout: Option<ChildStdout>,
+    permit: Option<BudgetPermit>,
+    watchdog: Option<mpsc::Sender<()>>,
+    reading: Reading,
+    timeout: Duration,
+    max_age: Option<Duration>,
+}
+
+/// A pipeline's output, timed by its watchdog.
+pub struct Watched<R> {
+    inner: R,
+    reading: Reading,
+}
+
+impl<R: Read> Read for Watched<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        *self.reading.lock().unwrap() = Some(Instant::now());
+        let result = self.inner.read(buf);
+        *self.reading.lock().unwrap() = None;
+        result
+    }
+}
+
+impl Pipeline {
+    pub fn new(label: impl Into<String>) -> Self {
+        let label = label.into();
+        let supervisor = Supervisor::global();
+        let id = supervisor.register(&label);
+
+        Self {
+            id,
+            label,
+            pgid: None,
+            children: Vec::new(),
+            stdout: None,
+            permit: None,
+            watchdog: None,
+            reading: Arc::new(Mutex::new(None)),
+            timeout: supervisor.timeout,
+            max_age: supervisor.max_age,
+        }
+    }
+
+    /// Kill the group once a read has been stuck for `stall`, or once
+    /// it has run for `max_age`, instead of the configured limits.
+    /// Only takes effect before the first child is spawned.
+    pub fn with_timeouts(mut self, stall: Duration, max_age: Option<Duration>) -> Self {
+        self.timeout = stall;
+        self.max_age = max_age;
+        self
+    }
+
+    /// Keep `permit` until every child has been reaped.
+    pub fn with_permit(mut self, permit: BudgetPermit) -> Self {
+        self.permit = Some(permit);
+        self
+    }
+
+    /// Spawn `cmd` inside the pipeline's process group.
+    /// The first child spawned becomes the group leader.
+    pub fn spawn(&mut self, name: &str, cmd: &mut Command) -> std::io::Result<&mut Child> {
+        let child = cmd
+            .process_group(self.pgid.unwrap_or(0))
+            .spawn()?;
+
+        if self.pgid.is_none() {
+            self.pgid = Some(child.id() as i32);
+            self.start_watchdog();
+        }
+
+        Supervisor::global().add_pid(self.id, child.id());
+        tracing::debug!("[{}] spawned {} ({})", self.label, name, child.id());
+
+        self.children.push(Supervised {
+            name: name.to_string(),
+            child,
+            stderr: None,
+        });
+
+        Ok(&mut self.children.last_mut().unwrap().child)
+    }
+
+    /// Keep the tail of `stderr` for the most recently spawned child.
+    pub fn capture_stderr(&mut self, stderr: ChildStderr) {
+        let tail = Arc::new(Mutex::new(VecDeque::with_capacity(STDERR_TAIL)));
+        let writer = tail.clone();
+
+        let spawned = std::thread::Builder::new()
+            .name(format!("{} stderr", self.label))
+            .spawn(move || {
+                let mut stderr = stderr;
+                let mut buf = [0u8; 512];
+                while let Ok(n) = stderr.read(&mut buf) {
+                    if n == 0 { break }
+                    let mut tail = writer.lock().unwrap();
+                    tail.extend(&buf[..n]);
+                    let overflow = tail.len().saturating_sub(STDERR_TAIL);
+                    tail.drain(..overflow);
+                }
+            });
+
+        if let Err(e) = spawned {
+            tracing::warn!("[{}] failed to capture stderr: {}", self.label, e);
+            return;
+        }
+
+        if let Some(last) = self.children.last_mut() {
+            last.stderr = Some(tail);
+        }
+    }
+
+    /// The tail of what the child called `name` has written to
+    /// stderr so far, if it was captured.
+    pub fn stderr(&self, name: &str) -> Option<String> {
+        self.children.iter()
+            .find(|proc| proc.name == name && proc.stderr.is_some())
+            .map(Supervised::stderr_tail)
+    }
+
+    /// Audio is read from `stdout`, normally the final stage's.
+    pub fn set_stdout(&mut self, stdout: ChildStdout) {
+        self.stdout = Some(stdout);
+    }
+
+    /// Let the watchdog time reads of `output`, normally the final
+    /// stage's stdout when it is read somewhere else.
+    pub fn watch<R: Read>(&self, output: R) -> Watched<R> {
+        Watched { inner: output, reading: self.reading.clone() }
+    }
+
+    fn start_watchdog(&mut self) {
+        let (tx, rx) = mpsc::channel::<()>();
+        let pgid = self.pgid;
+        let label = self.label.clone();
+        let timeout = self.timeout;
+        let max_age = self.max_age;
+        let tick = max_age.map_or(timeout, |max| max.min(timeout)).min(WATCHDOG_TICK);
+        let reading = self.reading.clone();
+        let started = Instant::now();
+
+        let spawned = std::thread::Builder::new()
+            .name(format!("{} watchdog", self.label))
+            .spawn(move || {
+                while let Err(mpsc::RecvTimeoutError::Timeout) = rx.recv_timeout(tick) {
+                    let stuck = reading.lock().unwrap().is_some_and(|since| since.elapsed() >= timeout);
+                    let expired = max_age.is_some_and(|max| started.elapsed() >= max);
+                    if stuck {
+                        tracing::warn!("[{}] no output for {}s, killing process group", label, timeout.as_secs());
+                    } else if expired {
+                        tracing::warn!("[{}] running for {}s, killing process group", label, started.elapsed().as_secs());
+                    } else {
+                        continue;
+                    }
+
+                    if let Some(pgid) = pgid { killpg(pgid); }
+                    return;
+                }
+            });
+
+        match spawned {
+            Ok(_) => self.watchdog = Some(tx),
+            Err(e) => tracing::warn!("[{}] failed to start watchdog: {}", self.label, e),
+        }
+    }
+}
+
+impl Read for Pipeline {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let reading = self.reading.clone();
+        match self.stdout.as_mut() {
+            Some(stdout) => Watched { inner: stdout, reading }.read(buf),
+            None => Ok(0),
+        }
+    }
+}
+
+fn killpg(pgid: i32) {
+    unsafe { libc::killpg(pgid, libc::SIGKILL); }
+}
+
+fn log_exit(label: &str, proc: &Supervised, status: ExitStatus) {
+    if status.success() {
+        tracing::debug!("[{}] {} exited cleanly", label, proc.name);
+        return;
+    }
+
+    let stderr = proc.stderr_tail();
+
+    match status.code() {
+        Some(code) => tracing::error!("[{}] {} exited with {}: {}", label, proc.name, code, stderr.trim()),
+        None => tracing::error!("[{}] {} killed by signal {:?}: {}", label, proc.name, status.signal(), stderr.trim()),
+    }
+}
+
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        // closing the channel stops the watchdog
+        self.watchdog.take();
+        self.stdout.take();
+
+        let id = self.id;
+        let label = std::mem::take(&mut self.label);
+        let pgid = self.pgid;
+        let children = std::mem::take(&mut self.children);
+        let permit = self.permit.take();
+
+        let reaper = move || {
+            let mut running = Vec::new();
+
+            for mut proc in children {
+                match proc.child.try_wait() {
+                    Ok(Some(status)) => log_exit(&label, &proc, status),
+                    _ => running.push(proc),
+                }
+            }
+
+            if !running.is_empty() {
+                if let Some(pgid) = pgid { killpg(pgid); }
+            }
+
+            for mut proc in running {
+                match proc.child.wait() {
+                    Ok(status) => tracing::debug!("[{}] {} stopped ({})", label, proc.name, status),
+                    Err(e) => tracing::error!("[{}] failed to reap {}: {}", label, proc.name, e),
+                }
+            }
+
+            Supervisor::global().unregister(id);
+            drop(permit);
+        };
+
+        if let Err(e) = std::thread::Builder::new()
+            .name("mkbird reaper".to_string())
+            .spawn(reaper)
+        {
+            // the children went down with the closure, unreaped; at least
+            // don't leave them running
+            tracing::error!("failed to spawn reaper thread, killing process group: {}", e);
+            if let Some(pgid) = pgid { killpg(pgid); }
+            Supervisor::global().unregister(id);
+        }
+    }
+}
@@ -6,6 +6,300 @@ fn binexists(file: &str) {
     assert!(paths.split(':').filter(|p| PathBuf::from(p).join(file).exists()).count() == 1);
 }
 
+/// `ProcessBudget` with small limits, on a single threaded runtime.
+mod budget {
+    use crate::budget::{BudgetError, ProcessBudget, Source};
+    use std::time::Duration;
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap()
+    }
+
+    fn budget(max_children: usize, per_source: usize) -> &'static ProcessBudget {
+        Box::leak(Box::new(ProcessBudget::new(max_children, |_| per_source)))
+    }
+
+    /// Whether `fut` is still waiting after a moment. It is dropped either way.
+    async fn waits<F: std::future::Future>(fut: F) -> bool {
+        tokio::time::timeout(Duration::from_millis(50), fut).await.is_err()
+    }
+
+    #[test]
+    fn per_source_limits() {
+        runtime().block_on(async {
+            let budget = budget(8, 1);
+            let deemix = budget.acquire(Source::Deemix, 2).await.unwrap();
+
+            // a second deemix load waits for the first, other sources don't
+            assert!(waits(budget.acquire(Source::Deemix, 2)).await);
+            let ytdl = budget.acquire(Source::Ytdl, 2).await.unwrap();
+
+            let stats = budget.stats();
+            assert_eq!(stats.children, 4);
+            assert_eq!(stats.waiting, 0);
+            assert_eq!(stats.sources.iter().map(|s| s.active).collect::<Vec<_>>(), [1, 1, 0, 0]);
+
+            drop(deemix);
+            assert!(!waits(budget.acquire(Source::Deemix, 2)).await);
+            drop(ytdl);
+            assert_eq!(budget.stats().children, 0);
+
+            assert!(matches!(
+                budget.acquire(Source::HttpGet, 9).await,
+                Err(BudgetError::TooManyChildren { requested: 9, limit: 8 })
+            ));
+        });
+    }
+
+    #[test]
+    fn waits_in_order() {
+        runtime().block_on(async {
+            let budget = budget(2, 2);
+            let first = budget.acquire(Source::Deemix, 1).await.unwrap();
+
+            // needs both children, so it waits for `first`
+            let big = tokio::spawn(budget.acquire(Source::Ytdl, 2));
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            assert_eq!(budget.stats().waiting, 1);
+
+            // one child is free, but `big` asked first
+            assert!(waits(budget.acquire(Source::HttpGet, 1)).await);
+
+            drop(first);
+            let big = big.await.unwrap().unwrap();
+            assert!(waits(budget.acquire(Source::HttpGet, 1)).await);
+
+            drop(big);
+            assert!(!waits(budget.acquire(Source::HttpGet, 1)).await);
+            assert_eq!(budget.stats().waiting, 0);
+        });
+    }
+}
+
+/// `Pipeline`s of `sh -c` children.
+mod supervisor {
+    use crate::supervisor::{Pipeline, Supervisor};
+    use std::io::{BufRead, BufReader, Read};
+    use std::process::{Command, Stdio};
+    use std::time::{Duration, Instant};
+
+    fn sh(script: &str) -> Command {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", script]).stdout(Stdio::piped());
+        cmd
+    }
+
+    /// Neither exited nor a zombie.
+    fn running(pid: u32) -> bool {
+        std::fs::read_to_string(format!("/proc/{}/stat", pid))
+            .is_ok_and(|stat| stat.rsplit(") ").next().is_some_and(|rest| !rest.starts_with('Z')))
+    }
+
+    /// Wait up to 5 seconds for `done`.
+    fn eventually(mut done: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if done() { return true }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        done()
+    }
+
+    #[test]
+    fn drop_kills_the_group() {
+        let mut pipeline = Pipeline::new("drop test");
+        let child = pipeline.spawn("sh", &mut sh("sleep 30 & echo $!; wait")).unwrap();
+        let pid = child.id();
+
+        // the background sleep is a grandchild, only reachable through the group
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap()).read_line(&mut line).unwrap();
+        let grandchild = line.trim().parse::<u32>().unwrap();
+        assert!(running(grandchild));
+
+        drop(pipeline);
+        assert!(eventually(|| !running(pid) && !running(grandchild)));
+    }
+
+    #[test]
+    fn reaps_and_unregisters() {
+        let mut pipeline = Pipeline::new("reap test");
+        let pid = pipeline.spawn("sh", &mut sh("sleep 30")).unwrap().id();
+        let live = || Supervisor::global().pipelines().iter().any(|p| p.label == "reap test");
+        assert!(live());
+
+        drop(pipeline);
+        // reaped: not even a zombie is left
+        assert!(eventually(|| !std::path::Path::new(&format!("/proc/{}", pid)).exists()));
+        assert!(eventually(|| !live()));
+    }
+
+    #[test]
+    fn keeps_the_tail_of_stderr() {
+        let mut pipeline = Pipeline::new("stderr test");
+        let mut cmd = sh("head -c 10000 /dev/zero | tr '\\0' a >&2; echo end >&2; exit 3");
+        let child = pipeline.spawn("sh", cmd.stderr(Stdio::piped())).unwrap();
+        let stderr = child.stderr.take().unwrap();
+        pipeline.capture_stderr(stderr);
+
+        assert!(eventually(|| pipeline.stderr("sh").is_some_and(|x| x.ends_with("end\n"))));
+        let tail = pipeline.stderr("sh").unwrap();
+        assert_eq!(tail.len(), 4096);
+        assert!(tail.starts_with("aaaa"));
+        assert_eq!(pipeline.stderr("ffmpeg"), None);
+    }
+
+    #[test]
+    fn watchdog_kills_stuck_reads_only() {
+        let mut pipeline = Pipeline::new("stall test").with_timeouts(Duration::from_secs(1), None);
+        let child = pipeline.spawn("sh", &mut sh("sleep 30")).unwrap();
+        let pid = child.id();
+        let stdout = child.stdout.take().unwrap();
+        pipeline.set_stdout(stdout);
+
+        // nobody is reading, like a paused track
+        std::thread::sleep(Duration::from_millis(2500));
+        assert!(running(pid));
+
+        let start = Instant::now();
+        assert_eq!(pipeline.read(&mut [0u8; 64]).unwrap(), 0);
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn watchdog_enforces_max_age() {
+        let mut pipeline = Pipeline::new("age test")
+            .with_timeouts(Duration::from_secs(60), Some(Duration::from_secs(1)));
+        let child = pipeline.spawn("sh", &mut sh("sleep 30")).unwrap();
+        let stdout = child.stdout.take().unwrap();
+        pipeline.set_stdout(stdout);
+
+        let start = Instant::now();
+        assert_eq!(pipeline.read(&mut [0u8; 64]).unwrap(), 0);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
+
+/// `Prebuffered` over an in-memory source that can be slow or stall.
+mod prebuffer {
+    use crate::prebuffer::{PrebufferConfig, Prebuffered, BYTES_PER_SEC};
+    use std::io::Read;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    /// Hands out `data` `chunk` bytes at a time, sleeping `delay` before each
+    /// read. Once it runs out it blocks until `stall` is dropped, if set.
+    struct Source {
+        data: Vec<u8>,
+        pos: usize,
+        chunk: usize,
+        delay: Duration,
+        stall: Option<mpsc::Receiver<()>>,
+    }
+
+    impl Source {
+        fn new(seconds: f64, delay: Duration) -> Self {
+            Self { data: audio(seconds), pos: 0, chunk: 4096, delay, stall: None }
+        }
+    }
+
+    impl Read for Source {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            std::thread::sleep(self.delay);
+            if self.pos == self.data.len() {
+                if let Some(stall) = self.stall.take() {
+                    let _ = stall.recv();
+                }
+                return Ok(0);
+            }
+            let n = buf.len().min(self.chunk).min(self.data.len() - self.pos);
+            buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    /// `seconds` of audio that is never silent.
+    fn audio(seconds: f64) -> Vec<u8> {
+        let len = (seconds * BYTES_PER_SEC as f64) as usize;
+        (0..len).map(|i| (i % 251) as u8 + 1).collect()
+    }
+
+    fn config(seconds: f64, deadline: Duration, capacity: f64) -> PrebufferConfig {
+        PrebufferConfig { seconds, deadline, capacity }
+    }
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap()
+    }
+
+    #[test]
+    fn released_once_seconds_are_buffered() {
+        let source = Source::new(2.0, Duration::ZERO);
+        let expected = source.data.clone();
+        let mut reader = Prebuffered::new("seconds", source, (), config(0.5, Duration::from_secs(5), 1.0)).unwrap();
+
+        let report = runtime().block_on(reader.wait());
+        assert!(!report.timed_out && !report.eof);
+        assert!(report.buffered >= Duration::from_millis(500));
+        // the ring never holds more than its capacity
+        assert!(report.buffered <= Duration::from_secs(1));
+
+        // two seconds through a one second ring, in order
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn released_at_the_deadline() {
+        let (stall, stalled) = mpsc::channel();
+        let mut source = Source::new(0.1, Duration::ZERO);
+        source.stall = Some(stalled);
+        let expected = source.data.clone();
+        let mut reader = Prebuffered::new("deadline", source, (), config(1.0, Duration::from_millis(200), 2.0)).unwrap();
+
+        let report = runtime().block_on(reader.wait());
+        assert!(report.timed_out && !report.eof);
+        assert!(report.load_time >= Duration::from_millis(200));
+        assert_eq!(report.buffered, Duration::from_millis(100));
+
+        let mut out = vec![0u8; expected.len()];
+        reader.read_exact(&mut out).unwrap();
+        assert_eq!(out, expected);
+
+        // the source is stuck: silence, in whole frames, instead of blocking
+        let mut buf = [1u8; 1000];
+        let n = reader.read(&mut buf).unwrap();
+        assert!(n > 0 && n % 8 == 0);
+        assert!(buf[..n].iter().all(|x| *x == 0));
+
+        drop(stall);
+        let mut rest = Vec::new();
+        while let n @ 1.. = reader.read(&mut buf).unwrap() {
+            rest.extend_from_slice(&buf[..n]);
+        }
+        assert!(rest.iter().all(|x| *x == 0));
+    }
+
+    #[test]
+    fn released_at_eof() {
+        let source = Source::new(0.1, Duration::from_millis(10));
+        let expected = source.data.clone();
+        let mut reader = Prebuffered::new("eof", source, (), config(3.0, Duration::from_secs(10), 5.0)).unwrap();
+
+        let report = runtime().block_on(reader.wait());
+        assert!(report.eof && !report.timed_out);
+        assert!(report.load_time < Duration::from_secs(5));
+
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, expected);
+        assert_eq!(reader.read(&mut [0u8; 64]).unwrap(), 0);
+    }
+}
+
 // #[test]
 // #[cfg(feature="deemix")]
 // fn path_deemix() {
@@ -35,3 +329,449 @@ fn path_ffmpeg() {
 fn path_ytdl() {
    binexists("yt-dlp")
 }
+
+#[test]
+#[cfg(feature="deemix")]
+fn stream_quality_steps_down() {
+    use crate::deemix::StreamQuality;
+    use crate::check::SoundQuality;
+
+    assert_eq!("FLAC".parse(), Ok(StreamQuality::Flac));
+    assert_eq!("320".parse(), Ok(StreamQuality::Mp3_320));
+    assert!("wav".parse::<StreamQuality>().is_err());
+
+    assert_eq!(StreamQuality::Flac.step_down(), Some(StreamQuality::Mp3_320));
+    assert_eq!(StreamQuality::Mp3_320.step_down(), Some(StreamQuality::Mp3_128));
+    assert_eq!(StreamQuality::Mp3_128.step_down(), None);
+    assert_eq!(StreamQuality::Flac.min(StreamQuality::Mp3_128), StreamQuality::Mp3_128);
+
+    let sq = SoundQuality { high: true, lossless: false, low: true, reality: false, standard: true };
+    assert_eq!(StreamQuality::from_sound_quality(&sq), StreamQuality::Mp3_320);
+}
+
+#[test]
+#[cfg(feature="deemix")]
+fn secret_store_roundtrip() {
+    use crate::secrets::{SecretError, SecretStore};
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("mkbird-secrets-{}", std::process::id()));
+    let store = SecretStore::new(&path, [7u8; 32]);
+    assert!(store.load().unwrap().is_empty());
+
+    let arls = vec!["a".repeat(192), "b".repeat(192)];
+    store.save(&arls).unwrap();
+    assert_eq!(store.load().unwrap(), arls);
+
+    let sealed = std::fs::read(&path).unwrap();
+    assert!(!sealed.windows(192).any(|w| w == arls[0].as_bytes()));
+    assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+    let wrong_key = SecretStore::new(&path, [8u8; 32]);
+    assert!(matches!(wrong_key.load(), Err(SecretError::Decrypt)));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[cfg(feature="check")]
+fn user_data_parsing() {
+    use crate::check::{ARLError, ExtractChecks};
+    use serde_json::json;
+
+    let premium = json!({"error": [], "results": {
+        "OFFER_ID": "600", "OFFER_NAME": "Premium",
+        "USER": {"USER_ID": 5, "FIRSTNAME": "", "BLOG_NAME": "blog", "OPTIONS": {
+            "license_country": "US", "expiration_timestamp": 1900000000, "mobile_lossless": true,
+            "mobile_sound_quality": {"high": true, "lossless": true},
+            "tablet_sound_quality": {}, "web_sound_quality": {"low": true}
+        }}
+    }});
+    let check = ExtractChecks::from_user_data(premium).unwrap();
+    assert_eq!(check.name, "blog");
+    assert_eq!(check.offer_id, 600);
+    assert_eq!(check.email, None);
+    assert!(check.mobile_sq.lossless && check.lossless());
+
+    let wrong_type = json!({"results": {"OFFER_ID": [1], "USER": {"USER_ID": 1}}});
+    assert!(matches!(
+        ExtractChecks::from_user_data(wrong_type),
+        Err(ARLError::InvalidField { ref field, .. }) if field == "results.OFFER_ID"
+    ));
+
+    let logged_out = json!({"results": {"OFFER_ID": 0, "USER": {"USER_ID": 0, "OPTIONS": []}}});
+    assert!(matches!(ExtractChecks::from_user_data(logged_out), Err(ARLError::NotLoggedIn)));
+
+    let no_options = json!({"results": {"OFFER_ID": 0, "USER": {"USER_ID": 3, "OPTIONS": []}}});
+    assert!(matches!(
+        ExtractChecks::from_user_data(no_options),
+        Err(ARLError::MissingField("results.USER.OPTIONS"))
+    ));
+}
+
+#[test]
+#[cfg(feature="deemix")]
+fn arl_alerts_only_on_change() {
+    use crate::arl::{ArlAlert, ArlHealth};
+    use crate::deemix::StreamQuality;
+
+    let health = |premium, lossless, country: &str| ArlHealth {
+        premium,
+        lossless,
+        country: country.to_string(),
+        expiration: None,
+        quality: StreamQuality::Mp3_320,
+    };
+
+    // a 320-only ARL, checked for the first time after a start
+    let mp3 = health(true, false, "US");
+    assert_eq!(mp3.changes_since(None), vec![]);
+    assert_eq!(health(false, false, "US").changes_since(None), vec![]);
+    assert_eq!(mp3.changes_since(Some(&mp3)), vec![]);
+
+    assert_eq!(health(false, false, "US").changes_since(Some(&health(true, true, "US"))), vec![
+        ArlAlert::LostPremium,
+        ArlAlert::LostLossless,
+    ]);
+    assert_eq!(mp3.changes_since(Some(&health(true, false, "FR"))), vec![
+        ArlAlert::CountryChanged { from: "FR".to_string(), to: "US".to_string() },
+    ]);
+}
+
+/// `DeezerGateway` against a local stand-in serving `fixtures/user_data`.
+/// The ARL picks the fixture; `garbage` and `unavailable` aren't files.
+#[cfg(feature="check")]
+mod gateway {
+    use crate::check::{ARLError, DeezerGateway};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    fn fixture(name: &str) -> Option<String> {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/user_data")
+            .join(format!("{}.json", name));
+        std::fs::read_to_string(path).ok()
+    }
+
+    fn respond(stream: std::net::TcpStream) {
+        let mut reader = BufReader::new(stream);
+        let mut arl = String::new();
+        let mut length = 0;
+
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" { break }
+            let lower = line.to_ascii_lowercase();
+            if let Some(x) = lower.strip_prefix("content-length:") {
+                length = x.trim().parse().unwrap();
+            }
+            if let Some(x) = line.split("arl=").nth(1) {
+                arl = x.split(|c: char| c == ';' || c.is_whitespace()).next().unwrap().to_string();
+            }
+        }
+        reader.by_ref().take(length).read_to_end(&mut Vec::new()).unwrap();
+
+        let (status, body) = match (arl.as_str(), fixture(&arl)) {
+            ("garbage", _) => ("200 OK", "<html>maintenance</html>".to_string()),
+            (_, Some(body)) => ("200 OK", body),
+            (_, None) => ("503 Service Unavailable", String::new()),
+        };
+
+        let mut stream = reader.into_inner();
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status, body.len(), body
+        ).unwrap();
+    }
+
+    fn stand_in() -> DeezerGateway {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                respond(stream.unwrap());
+            }
+        });
+
+        DeezerGateway::new(url.parse().unwrap())
+    }
+
+    fn now() -> i64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64
+    }
+
+    #[tokio::test]
+    async fn premium() {
+        let check = stand_in().check_arl("premium").await.unwrap();
+        assert!(check.premium() && check.lossless() && check.explicit());
+        assert_eq!(check.country, "US");
+        assert!(check.expiration.unwrap() > now());
+        assert!(check.dank());
+    }
+
+    #[tokio::test]
+    async fn free() {
+        let check = stand_in().check_arl("free").await.unwrap();
+        assert!(!check.premium());
+        assert!(!check.lossless());
+        assert!(!check.mobile_sq.high);
+        assert_eq!(check.expiration, None);
+    }
+
+    #[tokio::test]
+    async fn expired() {
+        let check = stand_in().check_arl("expired").await.unwrap();
+        assert!(check.premium());
+        assert!(check.expiration.unwrap() < now());
+    }
+
+    #[tokio::test]
+    async fn premium_without_lossless() {
+        let check = stand_in().check_arl("lossy").await.unwrap();
+        assert!(check.premium());
+        assert!(!check.lossless());
+        assert!(check.mobile_sq.high && !check.mobile_sq.lossless);
+    }
+
+    #[tokio::test]
+    async fn foreign_country() {
+        let check = stand_in().check_arl("foreign").await.unwrap();
+        assert_eq!(check.country, "FR");
+    }
+
+    #[tokio::test]
+    async fn logged_out() {
+        let err = stand_in().check_arl("logged_out").await.unwrap_err();
+        assert!(matches!(err, ARLError::NotLoggedIn));
+    }
+
+    #[tokio::test]
+    async fn malformed() {
+        let gateway = stand_in();
+
+        let err = gateway.check_arl("malformed").await.unwrap_err();
+        assert!(matches!(err, ARLError::InvalidField { ref field, .. } if field == "results.OFFER_ID"));
+
+        let err = gateway.check_arl("api_error").await.unwrap_err();
+        assert!(matches!(err, ARLError::Api(_)));
+
+        let err = gateway.check_arl("garbage").await.unwrap_err();
+        assert!(matches!(err, ARLError::ParseError(_)));
+
+        let err = gateway.check_arl("unavailable").await.unwrap_err();
+        assert!(matches!(err, ARLError::HTTP(_)));
+    }
+
+    #[tokio::test]
+    async fn bulk() {
+        use crate::bulk::{check_many, csv_report, RateLimit, Summary};
+
+        let arls = ["premium", "free", "expired", "foreign", "logged_out"]
+            .map(String::from)
+            .to_vec();
+        let limit = RateLimit { concurrency: 2, per_second: 100.0 };
+        let results = check_many(&stand_in(), arls.clone(), limit).await;
+
+        assert_eq!(results.iter().map(|x| x.arl.clone()).collect::<Vec<_>>(), arls);
+
+        let summary = Summary::new(&results);
+        assert_eq!(summary.total, 5);
+        assert_eq!(summary.failed, 1);
+        assert_eq!(summary.expired, 1);
+        assert_eq!(summary.countries.get("FR"), Some(&1));
+
+        let csv = csv_report(&results);
+        let rows = csv.lines().collect::<Vec<_>>();
+        assert_eq!(rows.len(), 6);
+        assert!(rows.iter().all(|x| x.matches(',').count() == rows[0].matches(',').count()));
+        assert!(rows[5].starts_with("logged_out,") && rows[5].ends_with("expired)"));
+    }
+}
+
+#[test]
+#[cfg(feature="check")]
+fn check_rendering() {
+    use crate::check::ExtractChecks;
+    use crate::render::{self, CsvRenderer, Format, JsonRenderer, MarkdownRenderer, Render};
+    use serde_json::json;
+
+    let check = ExtractChecks::from_user_data(json!({"results": {
+        "OFFER_ID": 600, "OFFER_NAME": "Premium, Family",
+        "USER": {"USER_ID": 5, "FIRSTNAME": "a \"b\"", "OPTIONS": {
+            "license_country": "US", "mobile_lossless": true,
+            "mobile_sound_quality": {"high": true, "lossless": true},
+            "tablet_sound_quality": {}, "web_sound_quality": {"low": true}
+        }}
+    }})).unwrap();
+
+    let table = render::sound_quality_table(&check);
+    let lines = table.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "Quality   Mobile  Tablet  Web");
+    assert_eq!(lines[2], "Lossless  ✓       ✗       ✗");
+    assert_eq!(lines[5], "Low       ✗       ✗       ✓");
+
+    let csv = CsvRenderer.render("arl", &check);
+    let mut rows = csv.lines();
+    assert_eq!(rows.next(), Some(CsvRenderer::HEADER));
+    assert_eq!(rows.next(), Some("arl,5,\"a \"\"b\"\"\",,US,600,\"Premium, Family\",false,false,true,,"));
+
+    let value = JsonRenderer.render("arl", &check);
+    assert_eq!(value["lossless"], true);
+    assert_eq!(value["sound_quality"]["web"]["low"], true);
+
+    assert!(MarkdownRenderer.render("arl", &check).contains("| Lossless | ✓ | ✗ | ✗ |"));
+    assert_eq!("markdown".parse::<Format>(), Ok(Format::Markdown));
+    assert!("xml".parse::<Format>().is_err());
+}
+
+#[test]
+#[cfg(feature="check")]
+fn bulk_arl_parsing() {
+    use crate::bulk::parse_arls;
+
+    let a = "a".repeat(192);
+    let b = "B".repeat(192);
+    let text = format!("{a}\n\n  {b}, {a};nope\r\n{}\n", "c".repeat(191));
+
+    assert_eq!(parse_arls(&text), (vec![a, b], 2));
+}
+
+#[test]
+#[cfg(feature="deemix")]
+fn deezer_track_ids() {
+    use crate::deemix::track_id;
+
+    assert_eq!(track_id("https://www.deezer.com/en/track/3135556"), Some(3135556));
+    assert_eq!(track_id(" https://deezer.com/track/3135556?utm_source=x\n"), Some(3135556));
+    assert_eq!(track_id("https://www.deezer.com/album/302127"), None);
+    assert_eq!(track_id("https://deezer.page.link/abcdef"), None);
+}
+
+#[test]
+#[cfg(feature="ytdl")]
+fn fallback_matching() {
+    use crate::fallback::{best_match, Candidate, TrackQuery};
+    use serde_json::json;
+    use std::time::Duration;
+
+    let query = TrackQuery::from_deemix(&json!({
+        "isrc": "GBAYE0601498", "title": "Bohemian Rhapsody",
+        "artist": {"name": "Queen"}, "duration": 354
+    }));
+    assert_eq!(query.search_terms(), vec!["\"GBAYE0601498\"", "Queen - Bohemian Rhapsody"]);
+
+    let candidate = |title: &str, uploader: &str, secs: u64| Candidate {
+        url: format!("https://youtu.be/{}", secs),
+        title: title.to_string(),
+        uploader: Some(uploader.to_string()),
+        duration: Some(Duration::from_secs(secs)),
+    };
+
+    let picked = best_match(&query, vec![
+        candidate("Bohemian Rhapsody (Live Aid 1985)", "Queen Official", 372),
+        candidate("Bohemian Rhapsody - Piano Cover", "someone", 353),
+        candidate("Bohemian Rhapsody (Remastered 2011)", "Queen - Topic", 355),
+    ]);
+    assert_eq!(picked.unwrap().url, "https://youtu.be/355");
+
+    assert_eq!(best_match(&query, vec![candidate("Another One Bites the Dust", "Queen - Topic", 354)]), None);
+    assert_eq!(best_match(&query, vec![candidate("Bohemian Rhapsody", "Queen", 400)]), None);
+}
+
+#[test]
+#[cfg(feature="controller")]
+fn queue_item_title() {
+    use crate::player::{Players, QueueItem};
+    use serenity::model::id::{ChannelId, UserId};
+    use songbird::input::Metadata;
+
+    let item = QueueItem::new("https://deezer.com/track/1".to_string(), Players::Deemix, UserId(1), ChannelId(2), None);
+    assert_eq!(item.title(), "https://deezer.com/track/1");
+
+    let queued = item.clone();
+    let _ = item.metadata.set(Metadata {
+        track: Some("Title".to_string()),
+        artist: Some("Artist".to_string()),
+        ..Default::default()
+    });
+    assert_eq!(queued.title(), "Artist - Title");
+}
+
+#[test]
+#[cfg(any(feature="controller", feature="arl-cmd"))]
+fn slash_reply_extend() {
+    use crate::slash::Reply;
+
+    let mut reply = Reply::text("first");
+    reply.extend(Reply::file("a.csv", "x"));
+    reply.extend(Reply::text("second"));
+
+    assert_eq!(reply.content.as_deref(), Some("first\nsecond"));
+    assert_eq!(reply.files, vec![("a.csv".to_string(), b"x".to_vec())]);
+    assert!(reply.embeds.is_empty());
+}
+
+#[test]
+#[cfg(feature="controller")]
+fn player_controls() {
+    use crate::controls::{Control, PlayerState};
+
+    for control in Control::ALL {
+        assert_eq!(Control::from_custom_id(&control.custom_id()), Some(control));
+    }
+    assert_eq!(Control::from_custom_id("skip"), None);
+
+    let state = PlayerState {
+        title: Some("Artist - Title".to_string()),
+        paused: true,
+        volume: 80,
+        queued: 2,
+        ..Default::default()
+    };
+    assert_eq!(state.content(), "**Now playing:** Artist - Title\nPaused · Volume 80% · 2 queued");
+    assert_eq!(PlayerState::default().content(), "Nothing is playing\nVolume 0% · 0 queued");
+}
+
+#[test]
+#[cfg(feature="controller")]
+fn session_round_trip() {
+    use crate::session::Session;
+    use serenity::model::id::{ChannelId, GuildId, UserId};
+
+    let session = Session {
+        guild_id: GuildId(1),
+        voice_channel: ChannelId(2),
+        invited_from: ChannelId(3),
+        volume: 80,
+        tracks: vec![
+            ("https://deezer.com/track/1".to_string(), UserId(4), ChannelId(3)),
+            ("https://youtu.be/x".to_string(), UserId(5), ChannelId(6)),
+        ],
+    };
+    assert_eq!(Session::from_json(&session.to_json()), Some(session));
+    assert_eq!(Session::from_json(&serde_json::json!({"guild_id": 1})), None);
+}
+
+#[test]
+#[cfg(feature="controller")]
+fn session_keeps_preloaded_tracks() {
+    use crate::player::{Players, QueueItem};
+    use crate::session::saved_tracks;
+    use serenity::model::id::{ChannelId, UserId};
+
+    let item = |uri: &str, user| QueueItem::new(uri.to_string(), Players::Ytdl, UserId(user), ChannelId(3), None);
+    // playing, and the next track already loaded by songbird
+    let queued = [item("https://youtu.be/a", 1), item("https://youtu.be/b", 2)];
+    let cold = [item("https://youtu.be/c", 1)];
+
+    let tracks = saved_tracks(&queued, &cold);
+    let uris = tracks.iter().map(|(uri, _, _)| uri.as_str()).collect::<Vec<_>>();
+    assert_eq!(uris, ["https://youtu.be/a", "https://youtu.be/b", "https://youtu.be/c"]);
+    assert_eq!(tracks[1].1, UserId(2));
+    assert!(saved_tracks(&[], &[]).is_empty());
+}
@@ -0,0 +1,314 @@
+//! Per-guild settings, kept in a JSON file.
+//!
+//! `SETTINGS_FILE` names the file, `settings.json` by default. Each change
+//! is written to a temporary file and renamed over the old one, so a crash
+//! never leaves half a file behind. Settings a guild hasn't changed fall
+//! back to the bot's defaults.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::PathBuf,
+    sync::{OnceLock, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+pub mod privileges;
+
+#[cfg(test)]
+mod testsuite;
+
+/// Environment variable naming the settings file.
+pub const SETTINGS_FILE: &str = "SETTINGS_FILE";
+pub const DEFAULT_PATH: &str = "settings.json";
+
+pub const DEFAULT_PREFIX: &str = ".";
+pub const MAX_PREFIX_LEN: usize = 8;
+/// Loudest volume a guild may set, in percent.
+pub const MAX_VOLUME: u8 = 200;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GuildSettings {
+    pub prefix: Option<String>,
+    /// Role allowed to use the player. Unset lets everyone.
+    pub dj_role: Option<u64>,
+    /// Volume tracks start at, in percent.
+    pub volume: Option<u8>,
+    /// Most tracks that may be queued at once.
+    pub max_queue: Option<usize>,
+    /// Channel the player posts in, instead of where it was invited from.
+    pub announce_channel: Option<u64>,
+    /// Command groups turned off, by name.
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub disabled: BTreeSet<String>,
+    /// Best deezer quality to stream, set with mockingbird's `quality`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<String>,
+}
+
+impl GuildSettings {
+    pub fn prefix(&self) -> &str {
+        self.prefix.as_deref().unwrap_or(DEFAULT_PREFIX)
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume.unwrap_or(100)
+    }
+
+    /// Command groups are on unless turned off.
+    pub fn is_enabled(&self, group: &str) -> bool {
+        !self.disabled.contains(group)
+    }
+
+    /// Returns whether anything changed.
+    pub fn set_enabled(&mut self, group: &str, enabled: bool) -> bool {
+        match enabled {
+            true => self.disabled.remove(group),
+            false => self.disabled.insert(group.to_string()),
+        }
+    }
+
+    fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Prefix,
+    DjRole,
+    Volume,
+    MaxQueue,
+    AnnounceChannel,
+}
+
+impl Key {
+    pub const ALL: [Key; 5] = [
+        Self::Prefix, Self::DjRole, Self::Volume, Self::MaxQueue, Self::AnnounceChannel,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Prefix => "prefix",
+            Self::DjRole => "dj-role",
+            Self::Volume => "volume",
+            Self::MaxQueue => "max-queue",
+            Self::AnnounceChannel => "announce-channel",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Prefix => "Prefix for text commands",
+            Self::DjRole => "Role allowed to use the player, everyone if unset",
+            Self::Volume => "Volume tracks start at, in percent",
+            Self::MaxQueue => "Most tracks that may be queued, unlimited if unset",
+            Self::AnnounceChannel => "Channel the player posts in, where it was invited from if unset",
+        }
+    }
+
+    /// `key`'s value for `settings`, as shown to users.
+    /// `None` if it isn't set.
+    pub fn get(&self, settings: &GuildSettings) -> Option<String> {
+        match self {
+            Self::Prefix => settings.prefix.clone(),
+            Self::DjRole => settings.dj_role.map(|x| format!("<@&{}>", x)),
+            Self::Volume => settings.volume.map(|x| format!("{}%", x)),
+            Self::MaxQueue => settings.max_queue.map(|x| x.to_string()),
+            Self::AnnounceChannel => settings.announce_channel.map(|x| format!("<#{}>", x)),
+        }
+    }
+
+    /// Parse `value` and store it in `settings`.
+    pub fn set(&self, settings: &mut GuildSettings, value: &str) -> Result<(), SettingsError> {
+        let value = value.trim();
+        let invalid = |reason: &str| SettingsError::Invalid { key: *self, reason: reason.to_string() };
+
+        match self {
+            Self::Prefix => {
+                if value.is_empty() || value.chars().count() > MAX_PREFIX_LEN || value.contains(char::is_whitespace) {
+                    return Err(invalid("must be 1 to 8 characters without spaces"));
+                }
+                settings.prefix = Some(value.to_string());
+            }
+            Self::DjRole => {
+                settings.dj_role = Some(parse_id(value, "<@&").ok_or_else(|| invalid("must be a role mention or id"))?);
+            }
+            Self::Volume => {
+                let volume = value.trim_end_matches('%')
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|x| *x <= MAX_VOLUME)
+                    .ok_or_else(|| invalid("must be a percentage from 0 to 200"))?;
+                settings.volume = Some(volume);
+            }
+            Self::MaxQueue => {
+                let max = value.parse::<usize>()
+                    .ok()
+                    .filter(|x| *x > 0)
+                    .ok_or_else(|| invalid("must be a number above 0"))?;
+                settings.max_queue = Some(max);
+            }
+            Self::AnnounceChannel => {
+                settings.announce_channel = Some(parse_id(value, "<#").ok_or_else(|| invalid("must be a channel mention or id"))?);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn reset(&self, settings: &mut GuildSettings) {
+        match self {
+            Self::Prefix => settings.prefix = None,
+            Self::DjRole => settings.dj_role = None,
+            Self::Volume => settings.volume = None,
+            Self::MaxQueue => settings.max_queue = None,
+            Self::AnnounceChannel => settings.announce_channel = None,
+        }
+    }
+}
+
+impl std::str::FromStr for Key {
+    type Err = SettingsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_lowercase().replace('_', "-");
+        Self::ALL.into_iter()
+            .find(|x| x.name() == s)
+            .ok_or(SettingsError::UnknownKey(s))
+    }
+}
+
+impl std::fmt::Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// An id, bare or in a mention starting with `mention`.
+fn parse_id(value: &str, mention: &str) -> Option<u64> {
+    value.strip_prefix(mention)
+        .and_then(|x| x.strip_suffix('>'))
+        .unwrap_or(value)
+        .parse::<u64>()
+        .ok()
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    IOError(std::io::Error),
+    Json(serde_json::Error),
+    UnknownKey(String),
+    Invalid { key: Key, reason: String },
+}
+
+impl std::fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SettingsError::IOError(e) => write!(f, "IO error: {}", e),
+            SettingsError::Json(e) => write!(f, "Invalid settings file: {}", e),
+            SettingsError::UnknownKey(key) => write!(
+                f, "Unknown setting {:?}, expected one of {}",
+                key,
+                Key::ALL.iter().map(Key::name).collect::<Vec<_>>().join(", ")
+            ),
+            SettingsError::Invalid { key, reason } => write!(f, "{} {}", key, reason),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+impl From<std::io::Error> for SettingsError {
+    fn from(err: std::io::Error) -> Self {
+        SettingsError::IOError(err)
+    }
+}
+
+impl From<serde_json::Error> for SettingsError {
+    fn from(err: serde_json::Error) -> Self {
+        SettingsError::Json(err)
+    }
+}
+
+pub struct Settings {
+    /// `None` keeps settings in memory only.
+    path: Option<PathBuf>,
+    guilds: RwLock<HashMap<u64, GuildSettings>>,
+}
+
+impl Settings {
+    pub fn in_memory() -> Self {
+        Self { path: None, guilds: RwLock::new(HashMap::new()) }
+    }
+
+    /// Read the settings in `path`. A missing file has no settings yet.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, SettingsError> {
+        let path = path.into();
+        let guilds = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self { path: Some(path), guilds: RwLock::new(guilds) })
+    }
+
+    /// Loaded from `SETTINGS_FILE` on first use. If the file can't be read,
+    /// settings are kept in memory and the file is left alone.
+    pub fn global() -> &'static Self {
+        static SETTINGS: OnceLock<Settings> = OnceLock::new();
+        SETTINGS.get_or_init(|| {
+            let path = std::env::var(SETTINGS_FILE).unwrap_or_else(|_| DEFAULT_PATH.to_string());
+            match Self::load(&path) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    tracing::error!("couldn't load settings from {}, changes won't be saved: {}", path, e);
+                    Self::in_memory()
+                }
+            }
+        })
+    }
+
+    pub fn guild(&self, guild_id: u64) -> GuildSettings {
+        self.guilds.read()
+            .expect("settings lock poisoned")
+            .get(&guild_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Change `guild_id`'s settings with `f` and save them.
+    /// Nothing changes if `f` or saving fails.
+    pub fn update<T, F>(&self, guild_id: u64, f: F) -> Result<T, SettingsError>
+    where
+        F: FnOnce(&mut GuildSettings) -> Result<T, SettingsError>,
+    {
+        let mut guilds = self.guilds.write().expect("settings lock poisoned");
+        let mut settings = guilds.get(&guild_id).cloned().unwrap_or_default();
+        let out = f(&mut settings)?;
+
+        let mut updated = guilds.clone();
+        if settings.is_default() {
+            updated.remove(&guild_id);
+        } else {
+            updated.insert(guild_id, settings);
+        }
+
+        self.save(&updated)?;
+        *guilds = updated;
+        Ok(out)
+    }
+
+    fn save(&self, guilds: &HashMap<u64, GuildSettings>) -> Result<(), SettingsError> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(guilds)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,33 @@
+//! Who owns the bot, and a record of who was refused what.
+//!
+//! Owners are set once at startup, from the configuration or the
+//! application's team. Denials are logged under the `audit` target, so
+//! `RUST_LOG=audit=info` keeps just them.
+
+use std::{collections::BTreeSet, sync::OnceLock};
+
+static OWNERS: OnceLock<BTreeSet<u64>> = OnceLock::new();
+
+/// Set the owners. Only the first call counts, returns whether it did.
+pub fn set_owners(owners: impl IntoIterator<Item = u64>) -> bool {
+    OWNERS.set(owners.into_iter().collect()).is_ok()
+}
+
+/// Empty until [`set_owners`] is called.
+pub fn owners() -> impl Iterator<Item = u64> {
+    OWNERS.get().into_iter().flatten().copied()
+}
+
+pub fn is_owner(user_id: u64) -> bool {
+    OWNERS.get().is_some_and(|x| x.contains(&user_id))
+}
+
+/// Log that `user` was refused `command`. `place` is where they asked,
+/// a guild or channel id or `DM`.
+pub fn denied(user_id: u64, user: &str, command: &str, place: &str, reason: &str) {
+    tracing::warn!(
+        target: "audit",
+        "denied {} to {} ({}) in {}: {}",
+        command, user, user_id, place, reason
+    );
+}
@@ -0,0 +1,61 @@
+use crate::{GuildSettings, Key, Settings, SettingsError};
+
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("settings-{}-{}.json", std::process::id(), name))
+}
+
+#[test]
+fn set_and_reset() {
+    let mut settings = GuildSettings::default();
+    assert_eq!(settings.prefix(), ".");
+
+    "prefix".parse::<Key>().unwrap().set(&mut settings, "!").unwrap();
+    Key::DjRole.set(&mut settings, "<@&42>").unwrap();
+    Key::AnnounceChannel.set(&mut settings, "7").unwrap();
+    Key::Volume.set(&mut settings, "80%").unwrap();
+
+    assert_eq!(settings.prefix(), "!");
+    assert_eq!(settings.dj_role, Some(42));
+    assert_eq!(Key::DjRole.get(&settings).as_deref(), Some("<@&42>"));
+    assert_eq!(Key::AnnounceChannel.get(&settings).as_deref(), Some("<#7>"));
+    assert_eq!(settings.volume(), 80);
+
+    assert!(matches!(Key::Prefix.set(&mut settings, "a b"), Err(SettingsError::Invalid { .. })));
+    assert!(matches!(Key::Volume.set(&mut settings, "201"), Err(SettingsError::Invalid { .. })));
+    assert!(matches!(Key::MaxQueue.set(&mut settings, "0"), Err(SettingsError::Invalid { .. })));
+    assert!(matches!("colour".parse::<Key>(), Err(SettingsError::UnknownKey(_))));
+
+    Key::Prefix.reset(&mut settings);
+    assert_eq!(settings.prefix(), ".");
+    assert_eq!(Key::Prefix.get(&settings), None);
+}
+
+#[test]
+fn persisted() {
+    let path = temp_path("persisted");
+    let _ = std::fs::remove_file(&path);
+
+    let settings = Settings::load(&path).unwrap();
+    settings.update(1, |s| Key::Prefix.set(s, "?")).unwrap();
+    settings.update(2, |s| Key::MaxQueue.set(s, "50")).unwrap();
+    settings.update(3, |s| Ok(s.set_enabled("bookmark", false))).unwrap();
+    settings.update(3, |s| { s.quality = Some("320".to_string()); Ok(()) }).unwrap();
+
+    // a failed change is not applied
+    assert!(settings.update(1, |s| Key::Volume.set(s, "loud")).is_err());
+
+    let reloaded = Settings::load(&path).unwrap();
+    assert_eq!(reloaded.guild(1).prefix(), "?");
+    assert_eq!(reloaded.guild(2).max_queue, Some(50));
+    assert!(!reloaded.guild(3).is_enabled("bookmark"));
+    assert!(reloaded.guild(3).is_enabled("player"));
+    assert_eq!(reloaded.guild(3).quality.as_deref(), Some("320"));
+    assert_eq!(reloaded.guild(4), GuildSettings::default());
+
+    // guilds back on the defaults aren't kept
+    reloaded.update(2, |s| { Key::MaxQueue.reset(s); Ok(()) }).unwrap();
+    let data = std::fs::read_to_string(&path).unwrap();
+    assert!(!data.contains("\"2\""));
+
+    std::fs::remove_file(&path).unwrap();
+}
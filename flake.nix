@@ -25,6 +25,7 @@
           stable-features = (with cogpkgs.features; [
               basic-cmds
               bookmark
+              config-cmd
               list-feature-cmd
               mockingbird-core
               mockingbird-ctrl
@@ -120,6 +121,8 @@
               serviceConfig.EnvironmentFile = cfg.environmentFile;
               serviceConfig.ExecStart = "${self.outputs.packages."${pkgs.system}".coggiebot-stable}/bin/coggiebot";
               serviceConfig.Restart = "on-failure";
+              # graceful shutdown (reboot, SIGTERM) exits with 3
+              serviceConfig.RestartForceExitStatus = "3";
               serviceConfig.User = "coggiebot";
             };
           };
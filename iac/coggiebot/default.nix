@@ -41,9 +41,10 @@ let
           }
           { name = "basic-cmds"; }
           { name = "bookmark"; }
+          { name = "config-cmd"; }
           { name = "prerelease";
             pkg-override = (prev: {
-              prev.buildInputs = prev.buildInputs ++ [ pkgs.git ];
+              buildInputs = prev.buildInputs ++ [ pkgs.git pkgs.cargo pkgs.rustc ];
             });
           }
           { name = "mockingbird-core";
@@ -92,9 +93,6 @@ let
           }
 
           { name = "mockingbird-deemix-check";
-            pkg-override = (prev: {
-              buildInputs = prev.buildInputs ++ [ pkgs.util-linux ];
-            });
             dependencies = [ "mockingbird-core" ];
           }
           { name = "mockingbird-ytdl";
@@ -54,6 +54,8 @@ rec {
       User=coggiebot
       Group=coggiebot
       SuccessExitStatus=0 1
+      # graceful shutdown (reboot, SIGTERM) exits with 3
+      RestartForceExitStatus=3
       Nice=-8
 
       PrivateDevices=true